@@ -0,0 +1,312 @@
+use std::{
+    marker::PhantomData,
+    ops::{Add, Sub},
+    simd::{
+        LaneCount, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        SupportedLaneCount,
+    },
+};
+
+pub trait AccValue: Copy + Add<Output = Self> + Sub<Output = Self> {
+    fn zeroed() -> Self;
+
+    // Returns (a, b) ordered so the first has the larger magnitude
+    fn by_magnitude(a: Self, b: Self) -> (Self, Self);
+}
+
+macro_rules! impl_acc_value {
+    ($t:ty) => {
+        impl AccValue for $t {
+            fn zeroed() -> Self {
+                0.0
+            }
+
+            #[inline(always)]
+            fn by_magnitude(a: Self, b: Self) -> (Self, Self) {
+                if a.abs() >= b.abs() {
+                    (a, b)
+                } else {
+                    (b, a)
+                }
+            }
+        }
+
+        impl<const N: usize> AccValue for Simd<$t, N>
+        where
+            LaneCount<N>: SupportedLaneCount,
+        {
+            fn zeroed() -> Self {
+                Simd::splat(0.0)
+            }
+
+            #[inline(always)]
+            fn by_magnitude(a: Self, b: Self) -> (Self, Self) {
+                let m = a.abs().simd_ge(b.abs());
+                (m.select(a, b), m.select(b, a))
+            }
+        }
+    };
+}
+
+impl_acc_value!(f32);
+impl_acc_value!(f64);
+
+pub trait Summation {
+    fn add<V: AccValue>(sum: &mut V, c: &mut V, x: V);
+
+    fn result<V: AccValue>(sum: V, c: V) -> V;
+}
+
+pub struct Plain;
+
+impl Summation for Plain {
+    #[inline(always)]
+    fn add<V: AccValue>(sum: &mut V, _: &mut V, x: V) {
+        *sum = *sum + x;
+    }
+
+    #[inline(always)]
+    fn result<V: AccValue>(sum: V, _: V) -> V {
+        sum
+    }
+}
+
+pub struct Kahan;
+
+impl Summation for Kahan {
+    #[inline(always)]
+    fn add<V: AccValue>(sum: &mut V, c: &mut V, x: V) {
+        let y = x - *c;
+        let t = *sum + y;
+        *c = (t - *sum) - y;
+        *sum = t;
+    }
+
+    #[inline(always)]
+    fn result<V: AccValue>(sum: V, c: V) -> V {
+        sum - c
+    }
+}
+
+pub struct Neumaier;
+
+impl Summation for Neumaier {
+    #[inline(always)]
+    fn add<V: AccValue>(sum: &mut V, c: &mut V, x: V) {
+        let t = *sum + x;
+        let (big, small) = V::by_magnitude(*sum, x);
+        let c_t = *c + ((big - t) + small);
+
+        // Fold the correction back into the sum so that it stays below half an
+        // ulp of it. Otherwise it grows into a plain sum of all the terms that
+        // were too small for the running sum and loses them in turn.
+        let s = t + c_t;
+        *c = c_t - (s - t);
+        *sum = s;
+    }
+
+    #[inline(always)]
+    fn result<V: AccValue>(sum: V, c: V) -> V {
+        sum + c
+    }
+}
+
+pub struct Acc<S: Summation, V: AccValue> {
+    pub sum: V,
+    pub c: V,
+    s: PhantomData<fn() -> S>,
+}
+
+impl<S: Summation, V: AccValue> Clone for Acc<S, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<S: Summation, V: AccValue> Copy for Acc<S, V> {}
+
+impl<S: Summation, V: AccValue> Acc<S, V> {
+    pub fn new() -> Self {
+        Self::from_parts(V::zeroed(), V::zeroed())
+    }
+
+    pub fn from_parts(sum: V, c: V) -> Self {
+        Self {
+            sum,
+            c,
+            s: PhantomData,
+        }
+    }
+
+    #[inline(always)]
+    pub fn add(&mut self, x: V) {
+        S::add(&mut self.sum, &mut self.c, x);
+    }
+
+    // The correction of other is added on its own, S::result(0, c) being the
+    // value it stands for
+    pub fn merge(&mut self, other: Self) {
+        self.add(other.sum);
+        self.add(S::result(V::zeroed(), other.c));
+    }
+
+    pub fn value(&self) -> V {
+        S::result(self.sum, self.c)
+    }
+}
+
+impl<S: Summation, V: AccValue> Default for Acc<S, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<S: Summation, T: AccValue + SimdElement, const N: usize> Acc<S, Simd<T, N>>
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: AccValue,
+{
+    // Every lane is merged with its own correction, so none of them is
+    // rounded away before the horizontal sum
+    pub fn reduce(&self) -> Acc<S, T> {
+        let mut acc = Acc::new();
+        for (sum, c) in self.sum.to_array().into_iter().zip(self.c.to_array()) {
+            acc.merge(Acc::from_parts(sum, c));
+        }
+        acc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lennard_jones_t;
+
+    // A large first term followed by many terms below half an ulp of it, as
+    // with the distant pairs of a big system
+    fn terms() -> Vec<f32> {
+        let mut xs = vec![1.0e4];
+        xs.extend((0..1_000_000).map(|k| 1.0e-5 * (1.0 + (k % 7) as f32)));
+        xs
+    }
+
+    fn exact(xs: &[f32]) -> f64 {
+        xs.iter().map(|&x| x as f64).sum()
+    }
+
+    fn sum<S: Summation>(xs: &[f32]) -> f32 {
+        let mut acc = Acc::<S, f32>::new();
+        for &x in xs {
+            acc.add(x);
+        }
+        acc.value()
+    }
+
+    fn rel_err(e: f32, e_ref: f64) -> f64 {
+        ((e as f64 - e_ref) / e_ref).abs()
+    }
+
+    #[test]
+    fn small_terms() {
+        let xs = terms();
+        let e_ref = exact(&xs);
+
+        assert!(rel_err(sum::<Plain>(&xs), e_ref) > 1e-3);
+        assert!(rel_err(sum::<Kahan>(&xs), e_ref) < 1e-6);
+        assert!(rel_err(sum::<Neumaier>(&xs), e_ref) < 1e-6);
+    }
+
+    #[test]
+    fn neumaier_cancellation() {
+        let xs = [1.0, 1.0e100, 1.0, -1.0e100];
+
+        let mut acc = Acc::<Neumaier, f64>::new();
+        for x in xs {
+            acc.add(x);
+        }
+        assert_eq!(acc.value(), 2.0);
+    }
+
+    fn merged<S: Summation>(xs: &[f32]) -> f32 {
+        let (a, b) = xs.split_at(xs.len() / 2);
+
+        let mut acc_a = Acc::<S, f32>::new();
+        for &x in a {
+            acc_a.add(x);
+        }
+
+        // Only the correction of the second half knows about its small terms
+        let mut acc_b = Acc::<S, f32>::new();
+        acc_b.add(1.0e4);
+        for &x in b {
+            acc_b.add(x);
+        }
+        acc_b.add(-1.0e4);
+
+        acc_a.merge(acc_b);
+        acc_a.value()
+    }
+
+    #[test]
+    fn merge_keeps_correction() {
+        let xs = terms();
+        let e_ref = exact(&xs);
+
+        assert!(rel_err(merged::<Kahan>(&xs), e_ref) < 1e-6);
+        assert!(rel_err(merged::<Neumaier>(&xs), e_ref) < 1e-6);
+    }
+
+    // Same f32 pair terms as the kernels, summed in f64 one row at a time so
+    // that only the summation error of the kernels is measured
+    fn lattice_reference(r: [&[f32]; 3]) -> f64 {
+        use rayon::prelude::*;
+
+        let s2 = 2.0f32.powf(-1.0 / 3.0);
+        let [x, y, z] = r;
+        let e: f64 = (0..x.len())
+            .into_par_iter()
+            .map(|i| {
+                (0..i)
+                    .map(|j| {
+                        let (dx, dy, dz) =
+                            (x[j] - x[i], y[j] - y[i], z[j] - z[i]);
+                        let sr2 = s2 / (dx * dx + dy * dy + dz * dz);
+                        let sr6 = sr2 * sr2 * sr2;
+                        (sr6 * sr6 - sr6) as f64
+                    })
+                    .sum::<f64>()
+            })
+            .sum();
+        4.0 * e
+    }
+
+    // 47^3 = 103823 atoms, about 5.4e9 pairs
+    #[test]
+    #[ignore = "slow, run with --ignored in release"]
+    fn lattice() {
+        let [x, y, z] = lennard_jones_t::setup_cubic_lattice::<f32>(47, 1.0);
+        let e_ref = lattice_reference([&x, &y, &z]);
+
+        let e = |e: f32| rel_err(e, e_ref);
+        let plain = lennard_jones_t::lennard_jones_acc::<16, Plain, _>;
+        let kahan = lennard_jones_t::lennard_jones_acc::<16, Kahan, _>;
+        let neumaier = lennard_jones_t::lennard_jones_acc::<16, Neumaier, _>;
+
+        assert!(e(plain(1.0, 1.0, &x, &y, &z)) > 1e-3);
+        assert!(e(kahan(1.0, 1.0, &x, &y, &z)) < 1e-6);
+        assert!(e(neumaier(1.0, 1.0, &x, &y, &z)) < 1e-6);
+    }
+
+    #[test]
+    fn simd_reduce() {
+        let xs = terms();
+        let e_ref = 4.0 * exact(&xs);
+
+        let mut acc = Acc::<Neumaier, Simd<f32, 4>>::new();
+        for &x in &xs {
+            acc.add(Simd::splat(x));
+        }
+
+        assert!(rel_err(acc.reduce().value(), e_ref) < 1e-6);
+    }
+}
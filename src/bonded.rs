@@ -0,0 +1,686 @@
+use std::{
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{
+        LaneCount, Simd, SimdElement, SimdFloat, StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BondPotential<T> {
+    // E = k / 2 (r - r0)^2
+    Harmonic { k: T, r0: T },
+    // E = -k / 2 r_max^2 ln(1 - (r / r_max)^2)
+    Fene { k: T, r_max: T },
+}
+
+// E = k (1 + d cos(n phi)) with d = +-1, which covers the usual torsions of
+// bead-spring models while keeping everything in terms of cos(phi), so no
+// atan2 is needed in the SIMD kernel
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DihedralPotential<T> {
+    pub k: T,
+    pub d: T,
+    pub n: u32,
+}
+
+// Angles use E = k_angle (1 + cos(theta)), with the minimum for a straight
+// chain. The exclusions are the nonbonded pairs whose LJ interaction is
+// removed again by lennard_jones_excluded.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Topology<T> {
+    pub bond: BondPotential<T>,
+    pub k_angle: T,
+    pub dihedral: DihedralPotential<T>,
+    pub bonds: Vec<[usize; 2]>,
+    pub angles: Vec<[usize; 3]>,
+    pub dihedrals: Vec<[usize; 4]>,
+    pub exclusions: Vec<[usize; 2]>,
+}
+
+impl<T: Float> Topology<T> {
+    pub fn new(
+        bond: BondPotential<T>,
+        k_angle: T,
+        dihedral: DihedralPotential<T>,
+    ) -> Self {
+        Self {
+            bond,
+            k_angle,
+            dihedral,
+            bonds: Vec::new(),
+            angles: Vec::new(),
+            dihedrals: Vec::new(),
+            exclusions: Vec::new(),
+        }
+    }
+
+    // Linear chains of consecutive particles, with every bond, angle and
+    // dihedral along each chain
+    pub fn add_chains(&mut self, start: usize, n_chains: usize, len: usize) {
+        for c in 0..n_chains {
+            let s = start + c * len;
+            for i in s..s + len {
+                if i + 1 < s + len {
+                    self.bonds.push([i, i + 1]);
+                }
+                if i + 2 < s + len {
+                    self.angles.push([i, i + 1, i + 2]);
+                }
+                if i + 3 < s + len {
+                    self.dihedrals.push([i, i + 1, i + 2, i + 3]);
+                }
+            }
+        }
+    }
+
+    // Excludes 1-2 pairs for depth 1, also 1-3 pairs for depth 2 and also 1-4
+    // pairs for depth 3
+    pub fn exclude(&mut self, depth: usize) {
+        let mut ex = Vec::new();
+
+        if depth >= 1 {
+            ex.extend(self.bonds.iter().copied());
+        }
+        if depth >= 2 {
+            ex.extend(self.angles.iter().map(|&[i, _, k]| [i, k]));
+        }
+        if depth >= 3 {
+            ex.extend(self.dihedrals.iter().map(|&[i, _, _, l]| [i, l]));
+        }
+
+        for p in ex.iter_mut() {
+            p.sort_unstable();
+        }
+        ex.sort_unstable();
+        ex.dedup();
+
+        self.exclusions = ex;
+    }
+}
+
+fn min_image<T: Float>(d: T, l: T) -> T {
+    d - (d / l).round() * l
+}
+
+// Gathers the minimum image vectors from particle a to particle b of up to N
+// tuples into lanes. A short last batch is padded by repeating its last tuple,
+// and the padding lanes are ignored when the results are scattered.
+fn gather<const N: usize, const M: usize, T: Float + SimdElement>(
+    batch: &[[usize; M]],
+    a: usize,
+    b: usize,
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+) -> [Simd<T, N>; 3]
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let mut d = [[T::zero(); N]; 3];
+    for l in 0..N {
+        let t = batch[l.min(batch.len() - 1)];
+        let (i, j) = (t[a], t[b]);
+
+        d[0][l] = min_image(x[j] - x[i], box_l[0]);
+        d[1][l] = min_image(y[j] - y[i], box_l[1]);
+        d[2][l] = min_image(z[j] - z[i], box_l[2]);
+    }
+    d.map(Simd::from_array)
+}
+
+fn scatter<const N: usize, T: Float + SimdElement + AddAssign>(
+    g: &mut [T],
+    idx: impl Iterator<Item = usize>,
+    v: Simd<T, N>,
+) where
+    LaneCount<N>: SupportedLaneCount,
+{
+    for (i, v) in idx.zip(v.to_array()) {
+        g[i] += v;
+    }
+}
+
+fn sum_lanes<const N: usize, T: Float + SimdElement>(
+    v: Simd<T, N>,
+    len: usize,
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    v.to_array()[..len].iter().fold(T::zero(), |a, &b| a + b)
+}
+
+fn reborrow<'a, T>(
+    g: &'a mut Option<(&mut [T], &mut [T], &mut [T])>,
+) -> Option<(&'a mut [T], &'a mut [T], &'a mut [T])> {
+    g.as_mut()
+        .map(|(gx, gy, gz)| (&mut **gx, &mut **gy, &mut **gz))
+}
+
+fn dot<const N: usize, T: SimdElement>(
+    a: &[Simd<T, N>; 3],
+    b: &[Simd<T, N>; 3],
+) -> Simd<T, N>
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>> + Mul<Output = Simd<T, N>>,
+{
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross<const N: usize, T: SimdElement>(
+    a: &[Simd<T, N>; 3],
+    b: &[Simd<T, N>; 3],
+) -> [Simd<T, N>; 3]
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Sub<Output = Simd<T, N>> + Mul<Output = Simd<T, N>>,
+{
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+// Shared driver for all pair terms. f maps the squared distance to the energy
+// and its derivative with respect to the squared distance.
+#[allow(clippy::too_many_arguments)]
+fn pair_terms<const N: usize, T, F>(
+    pairs: &[[usize; 2]],
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+    mut w: Option<&mut T>,
+    f: F,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+    F: Fn(Simd<T, N>) -> (Simd<T, N>, Simd<T, N>),
+{
+    let mut e = T::zero();
+    for batch in pairs.chunks(N) {
+        let d = gather(batch, 0, 1, box_l, x, y, z);
+        let (es, de) = f(dot(&d, &d));
+
+        e += sum_lanes(es, batch.len());
+
+        if let Some(w) = &mut w {
+            let r2 = dot(&d, &d);
+            **w -= sum_lanes(de * r2 + de * r2, batch.len());
+        }
+
+        if let Some((gx, gy, gz)) = &mut g {
+            let s = de + de;
+            let gs = [&mut **gx, &mut **gy, &mut **gz];
+            for (g, d) in gs.into_iter().zip(d) {
+                let gd = s * d;
+                scatter(g, batch.iter().map(|p| p[1]), gd);
+                scatter(g, batch.iter().map(|p| p[0]), -gd);
+            }
+        }
+    }
+    e
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn bond_energy<const N: usize, T>(
+    pot: BondPotential<T>,
+    bonds: &[[usize; 2]],
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    g: Option<(&mut [T], &mut [T], &mut [T])>,
+    w: Option<&mut T>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat,
+{
+    let one = Simd::splat(T::one());
+    let half = Simd::splat(T::from(0.5).unwrap());
+
+    match pot {
+        BondPotential::Harmonic { k, r0 } => {
+            let k = Simd::splat(k);
+            let r0 = Simd::splat(r0);
+            pair_terms(bonds, box_l, x, y, z, g, w, |r2| {
+                let r = r2.sqrt();
+                let dr = r - r0;
+                (half * k * dr * dr, half * k * dr / r)
+            })
+        }
+        BondPotential::Fene { k, r_max } => {
+            let k = Simd::splat(k);
+            let rm2 = Simd::splat(r_max * r_max);
+            pair_terms(bonds, box_l, x, y, z, g, w, |r2| {
+                let a = one - r2 / rm2;
+                let ln_a = Simd::from_array(a.to_array().map(T::ln));
+                (-half * k * rm2 * ln_a, half * k / a)
+            })
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn angle_energy<const N: usize, T>(
+    k_angle: T,
+    angles: &[[usize; 3]],
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+    mut w: Option<&mut T>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat,
+{
+    let k = Simd::splat(k_angle);
+    let one = Simd::splat(T::one());
+
+    let mut e = T::zero();
+    for batch in angles.chunks(N) {
+        let a = gather(batch, 1, 0, box_l, x, y, z);
+        let b = gather(batch, 1, 2, box_l, x, y, z);
+
+        let aa = dot(&a, &a);
+        let bb = dot(&b, &b);
+        let inv = one / (aa * bb).sqrt();
+        let c = dot(&a, &b) * inv;
+
+        e += sum_lanes(k * (one + c), batch.len());
+
+        let ga = [0, 1, 2].map(|q| k * (b[q] * inv - c * a[q] / aa));
+        let gb = [0, 1, 2].map(|q| k * (a[q] * inv - c * b[q] / bb));
+
+        if let Some(w) = &mut w {
+            **w -= sum_lanes(dot(&a, &ga) + dot(&b, &gb), batch.len());
+        }
+
+        if let Some((gx, gy, gz)) = &mut g {
+            let gs = [&mut **gx, &mut **gy, &mut **gz];
+            for (q, g) in gs.into_iter().enumerate() {
+                let (ga, gb) = (ga[q], gb[q]);
+
+                scatter(g, batch.iter().map(|t| t[0]), ga);
+                scatter(g, batch.iter().map(|t| t[2]), gb);
+                scatter(g, batch.iter().map(|t| t[1]), -(ga + gb));
+            }
+        }
+    }
+    e
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn dihedral_energy<const N: usize, T>(
+    pot: DihedralPotential<T>,
+    dihedrals: &[[usize; 4]],
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+    mut w: Option<&mut T>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat,
+{
+    assert!(pot.n >= 1);
+
+    let k = Simd::splat(pot.k);
+    let d = Simd::splat(pot.d);
+    let n = Simd::splat(T::from(pot.n).unwrap());
+    let one = Simd::splat(T::one());
+    let two = Simd::splat(T::from(2.0).unwrap());
+
+    let mut e = T::zero();
+    for batch in dihedrals.chunks(N) {
+        let b1 = gather(batch, 0, 1, box_l, x, y, z);
+        let b2 = gather(batch, 1, 2, box_l, x, y, z);
+        let b3 = gather(batch, 2, 3, box_l, x, y, z);
+
+        let m = cross(&b1, &b2);
+        let p = cross(&b2, &b3);
+
+        let mm = dot(&m, &m);
+        let pp = dot(&p, &p);
+        let inv = one / (mm * pp).sqrt();
+        let c = dot(&m, &p) * inv;
+
+        // Chebyshev recurrences for cos(n phi) = T_n(c) and its derivative
+        // n U_{n-1}(c)
+        let (mut t_prev, mut t) = (one, c);
+        let (mut u_prev, mut u) = (Simd::splat(T::zero()), one);
+        for _ in 1..pot.n {
+            (t_prev, t) = (t, two * c * t - t_prev);
+            (u_prev, u) = (u, two * c * u - u_prev);
+        }
+
+        e += sum_lanes(k * (one + d * t), batch.len());
+
+        let de = k * d * n * u;
+
+        let dm = [0, 1, 2].map(|q| de * (p[q] * inv - c * m[q] / mm));
+        let dp = [0, 1, 2].map(|q| de * (m[q] * inv - c * p[q] / pp));
+
+        // Gradients with respect to the bond vectors b1, b2 and b3
+        let g1 = cross(&b2, &dm);
+        let g2 = cross(&dm, &b1);
+        let g2 = [0, 1, 2].map(|q| g2[q] + cross(&b3, &dp)[q]);
+        let g3 = cross(&dp, &b2);
+
+        if let Some(w) = &mut w {
+            let bg = dot(&b1, &g1) + dot(&b2, &g2) + dot(&b3, &g3);
+            **w -= sum_lanes(bg, batch.len());
+        }
+
+        if let Some((gx, gy, gz)) = &mut g {
+            let gs = [&mut **gx, &mut **gy, &mut **gz];
+            for (q, g) in gs.into_iter().enumerate() {
+                scatter(g, batch.iter().map(|t| t[0]), -g1[q]);
+                scatter(g, batch.iter().map(|t| t[1]), g1[q] - g2[q]);
+                scatter(g, batch.iter().map(|t| t[2]), g2[q] - g3[q]);
+                scatter(g, batch.iter().map(|t| t[3]), g3[q]);
+            }
+        }
+    }
+    e
+}
+
+// Removes the LJ interaction of the excluded pairs that the nonbonded kernels
+// included, so it is meant to be added to their result. Bonded neighbours sit
+// close to the LJ minimum, so subtracting their terms again is harmless.
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_excluded<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    pairs: &[[usize; 2]],
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    g: Option<(&mut [T], &mut [T], &mut [T])>,
+    w: Option<&mut T>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+
+    let s2 = Simd::splat(two.powf(-one / three) * r_eq.powi(2));
+    let rc2 = r_cut * r_cut;
+    let four_e_b = Simd::splat(T::from(4.0).unwrap() * e_b);
+    let twentyfour_e_b = Simd::splat(T::from(24.0).unwrap() * e_b);
+    let one = Simd::splat(one);
+    let two = Simd::splat(two);
+
+    pair_terms(pairs, box_l, x, y, z, g, w, |r2| {
+        let r2 = Simd::from_array(r2.to_array().map(|r2| {
+            if r2 < rc2 {
+                r2
+            } else {
+                T::infinity()
+            }
+        }));
+        let sr2 = s2 / r2;
+        let sr6 = sr2 * sr2 * sr2;
+        let sr12 = sr6 * sr6;
+
+        let e = four_e_b * (sr12 - sr6);
+        let de = -twentyfour_e_b * sr6 / r2 * (two * sr6 - one);
+
+        (-e, -de / two)
+    })
+}
+
+impl<T> Topology<T>
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+{
+    // Total bonded energy including the exclusion correction. The gradient
+    // and the virial, sum of r_ij . f_ij, are added to rather than overwritten
+    // so that they can be combined with the nonbonded ones.
+    #[allow(clippy::too_many_arguments)]
+    pub fn energy<const N: usize>(
+        &self,
+        r_eq: T,
+        e_b: T,
+        r_cut: T,
+        box_l: [T; 3],
+        x: &[T],
+        y: &[T],
+        z: &[T],
+        mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+        mut w: Option<&mut T>,
+    ) -> T
+    where
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Add<Output = Simd<T, N>>
+            + Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>
+            + Div<Output = Simd<T, N>>
+            + Neg<Output = Simd<T, N>>
+            + SimdFloat<Scalar = T>
+            + StdFloat,
+    {
+        bond_energy::<N, _>(
+            self.bond,
+            &self.bonds,
+            box_l,
+            x,
+            y,
+            z,
+            reborrow(&mut g),
+            w.as_deref_mut(),
+        ) + angle_energy::<N, _>(
+            self.k_angle,
+            &self.angles,
+            box_l,
+            x,
+            y,
+            z,
+            reborrow(&mut g),
+            w.as_deref_mut(),
+        ) + dihedral_energy::<N, _>(
+            self.dihedral,
+            &self.dihedrals,
+            box_l,
+            x,
+            y,
+            z,
+            reborrow(&mut g),
+            w.as_deref_mut(),
+        ) + lennard_jones_excluded::<N, _>(
+            r_eq,
+            e_b,
+            r_cut,
+            &self.exclusions,
+            box_l,
+            x,
+            y,
+            z,
+            reborrow(&mut g),
+            w,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::dual::{gradient, Dual};
+
+    // Every term written out one at a time in terms of the angles, evaluated
+    // in dual numbers
+    fn reference(
+        top: &Topology<f64>,
+        r_cut: f64,
+        box_l: [f64; 3],
+        r: &[[Dual<f64>; 3]],
+    ) -> Dual<f64> {
+        let c = Dual::constant;
+        let half = c(0.5);
+        let vec = |i: usize, j: usize| {
+            [0, 1, 2].map(|q| {
+                let d = r[j][q] - r[i][q];
+                d - (d / c(box_l[q])).round() * c(box_l[q])
+            })
+        };
+        let dot = |a: [Dual<f64>; 3], b: [Dual<f64>; 3]| {
+            a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+        };
+        let cross = |a: [Dual<f64>; 3], b: [Dual<f64>; 3]| {
+            [
+                a[1] * b[2] - a[2] * b[1],
+                a[2] * b[0] - a[0] * b[2],
+                a[0] * b[1] - a[1] * b[0],
+            ]
+        };
+
+        let mut e = c(0.0);
+        for &[i, j] in &top.bonds {
+            let d = vec(i, j);
+            let r2 = dot(d, d);
+            e += match top.bond {
+                BondPotential::Harmonic { k, r0 } => {
+                    half * c(k) * (r2.sqrt() - c(r0)).powi(2)
+                }
+                BondPotential::Fene { k, r_max } => {
+                    let rm2 = c(r_max * r_max);
+                    -half * c(k) * rm2 * (c(1.0) - r2 / rm2).ln()
+                }
+            };
+        }
+        for &[i, j, k] in &top.angles {
+            let (a, b) = (vec(j, i), vec(j, k));
+            let cos = dot(a, b) / (dot(a, a) * dot(b, b)).sqrt();
+            e += c(top.k_angle) * (c(1.0) + cos);
+        }
+        for &[i, j, k, l] in &top.dihedrals {
+            let m = cross(vec(i, j), vec(j, k));
+            let p = cross(vec(j, k), vec(k, l));
+            let phi = (dot(m, p) / (dot(m, m) * dot(p, p)).sqrt()).acos();
+            let DihedralPotential { k, d, n } = top.dihedral;
+            e += c(k) * (c(1.0) + c(d) * (c(n as f64) * phi).cos());
+        }
+        let s2 = c(2.0f64.powf(-1.0 / 3.0));
+        for &[i, j] in &top.exclusions {
+            let d = vec(i, j);
+            let r2 = dot(d, d);
+            if r2.re < r_cut * r_cut {
+                let sr6 = (s2 / r2).powi(3);
+                e -= c(4.0) * (sr6 * sr6 - sr6);
+            }
+        }
+        e
+    }
+
+    #[test]
+    fn dual_gradient() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let (m, len, a, b) = (3, 9, 1.1, 0.97);
+        let box_l = [m as f64 * a, m as f64 * a, len as f64 * b];
+        let r: Vec<[f64; 3]> = (0..m * m * len)
+            .map(|i| {
+                let c = i / len;
+                let p = [
+                    (c % m) as f64 * a,
+                    (c / m) as f64 * a,
+                    (i % len) as f64 * b,
+                ];
+                p.map(|p| p + rng.gen_range(-0.1..0.1))
+            })
+            .collect();
+        let [x, y, z] = crate::lennard_jones_t::to_soa(&r);
+        let r_cut = 2.5;
+
+        let dihedral = DihedralPotential {
+            k: 0.5,
+            d: 1.0,
+            n: 3,
+        };
+        for bond in [
+            BondPotential::Harmonic { k: 400.0, r0: b },
+            BondPotential::Fene {
+                k: 30.0,
+                r_max: 1.5,
+            },
+        ] {
+            let mut top = Topology::new(bond, 1.5, dihedral);
+            top.add_chains(0, m * m, len);
+            top.exclude(3);
+
+            let mut g = [(); 3].map(|_| vec![0.0; r.len()]);
+            let [gx, gy, gz] = &mut g;
+            top.energy::<4>(
+                1.0,
+                1.0,
+                r_cut,
+                box_l,
+                &x,
+                &y,
+                &z,
+                Some((gx, gy, gz)),
+                None,
+            );
+
+            let g_ref = gradient(&r, |r| reference(&top, r_cut, box_l, r));
+            let scale =
+                g_ref.iter().flatten().fold(0.0f64, |m, g| m.max(g.abs()));
+            for (i, g_ref) in g_ref.iter().enumerate() {
+                for q in 0..3 {
+                    let err = (g[q][i] - g_ref[q]).abs() / scale;
+                    assert!(err < 1e-12, "{bond:?}: atom {i}, error {err:e}");
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,718 @@
+use std::{
+    iter::Sum,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+use crate::triclinic::TriclinicBox;
+
+// Cells are binned in fractional coordinates, so the same code handles
+// orthogonal and triclinic boxes. box_l holds the diagonal of the cell matrix
+// and tilt its off-diagonal elements, see TriclinicBox.
+pub struct CellList<T> {
+    pub box_l: [T; 3],
+    pub tilt: [T; 3],
+    pub r_cut: T,
+    pub n_cells: [usize; 3],
+    pub cell_start: Vec<usize>,
+    pub indices: Vec<usize>,
+}
+
+impl<T: Float> CellList<T> {
+    pub fn new(box_l: [T; 3], r_cut: T) -> Self {
+        Self::new_triclinic(&TriclinicBox::orthogonal(box_l), r_cut)
+    }
+
+    pub fn new_triclinic(bx: &TriclinicBox<T>, r_cut: T) -> Self {
+        let n_cells =
+            bx.widths().map(|w| (w / r_cut).floor().to_usize().unwrap());
+
+        // With one or two cells along an axis the half shell visits the same
+        // cell more than once, but with different image shifts, so every
+        // image within the cutoff is still seen once. Images further than one
+        // box away are not, so the box must be at least r_cut wide.
+        assert!(
+            n_cells.iter().all(|&n| n >= 1),
+            "the box must be at least r_cut wide along every axis"
+        );
+
+        Self {
+            box_l: bx.l,
+            tilt: bx.tilt,
+            r_cut,
+            n_cells,
+            cell_start: vec![0; n_cells.iter().product::<usize>() + 1],
+            indices: Vec::new(),
+        }
+    }
+
+    pub fn n_cells_total(&self) -> usize {
+        self.n_cells.iter().product()
+    }
+
+    pub fn triclinic(&self) -> TriclinicBox<T> {
+        TriclinicBox::new(self.box_l, self.tilt)
+    }
+
+    fn cell_coord(&self, s: T, q: usize) -> usize {
+        let c = (s * T::from(self.n_cells[q]).unwrap())
+            .floor()
+            .to_isize()
+            .unwrap();
+        c.clamp(0, self.n_cells[q] as isize - 1) as usize
+    }
+
+    fn cell_index(&self, c: [usize; 3]) -> usize {
+        (c[0] * self.n_cells[1] + c[1]) * self.n_cells[2] + c[2]
+    }
+
+    pub fn build(&mut self, x: &[T], y: &[T], z: &[T]) {
+        assert_eq!(x.len(), y.len());
+        assert_eq!(x.len(), z.len());
+
+        let bx = self.triclinic();
+
+        let cells: Vec<_> = x
+            .iter()
+            .zip(y)
+            .zip(z)
+            .map(|((&x, &y), &z)| {
+                let s = bx.to_frac([x, y, z]);
+                self.cell_index([
+                    self.cell_coord(s[0], 0),
+                    self.cell_coord(s[1], 1),
+                    self.cell_coord(s[2], 2),
+                ])
+            })
+            .collect();
+
+        self.cell_start.clear();
+        self.cell_start.resize(self.n_cells_total() + 1, 0);
+
+        for &c in &cells {
+            self.cell_start[c + 1] += 1;
+        }
+
+        for c in 0..self.n_cells_total() {
+            self.cell_start[c + 1] += self.cell_start[c];
+        }
+
+        let mut fill = self.cell_start.clone();
+
+        self.indices.resize(x.len(), 0);
+        for (i, &c) in cells.iter().enumerate() {
+            self.indices[fill[c]] = i;
+            fill[c] += 1;
+        }
+    }
+
+    pub fn cell(&self, c: usize) -> &[usize] {
+        &self.indices[self.cell_start[c]..self.cell_start[c + 1]]
+    }
+
+    // The 13 neighbour cells in the forward half shell of cell c, each with the
+    // periodic image shift to add to their coordinates
+    pub fn half_shell(
+        &self,
+        c: usize,
+    ) -> impl Iterator<Item = (usize, [T; 3])> + '_ {
+        let [nx, ny, nz] = self.n_cells;
+        let cc = [c / (ny * nz), (c / nz) % ny, c % nz];
+        let vs = self.triclinic().vectors();
+
+        (-1isize..=1)
+            .flat_map(|a| {
+                (-1isize..=1)
+                    .flat_map(move |b| (-1isize..=1).map(move |d| [a, b, d]))
+            })
+            .filter(|o| {
+                o[0] > 0 || (o[0] == 0 && (o[1] > 0 || (o[1] == 0 && o[2] > 0)))
+            })
+            .map(move |o| {
+                let mut d = [0; 3];
+                let mut shift = [T::zero(); 3];
+                for q in 0..3 {
+                    let n = [nx, ny, nz][q] as isize;
+                    let mut dq = cc[q] as isize + o[q];
+                    if dq < 0 {
+                        dq += n;
+                        for (s, v) in shift.iter_mut().zip(vs[q]) {
+                            *s = *s - v;
+                        }
+                    } else if dq >= n {
+                        dq -= n;
+                        for (s, v) in shift.iter_mut().zip(vs[q]) {
+                            *s = *s + v;
+                        }
+                    }
+                    d[q] = dq as usize;
+                }
+                (self.cell_index(d), shift)
+            })
+    }
+}
+
+pub fn wrap_positions<T: Float>(
+    box_l: [T; 3],
+    x: &mut [T],
+    y: &mut [T],
+    z: &mut [T],
+) {
+    for (v, l) in [x, y, z].into_iter().zip(box_l) {
+        for r in v.iter_mut() {
+            *r = *r - (*r / l).floor() * l;
+        }
+    }
+}
+
+fn cut<const N: usize, T: Float + SimdElement>(
+    r2: Simd<T, N>,
+    rc2: Simd<T, N>,
+) -> Simd<T, N>
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    // Pairs outside the cutoff get an infinite distance, which makes every
+    // term of the potential and gradient vanish
+    r2.simd_lt(rc2).select(r2, Simd::splat(T::infinity()))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn lennard_jones_cell_self<T: Float + AddAssign + SubAssign>(
+    s2: T,
+    rc2: T,
+    e_b: T,
+    ic: &[usize],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+) -> T {
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let mut e = T::zero();
+    for (k, &i) in ic.iter().enumerate() {
+        for &j in &ic[..k] {
+            let dx = x[j] - x[i];
+            let dy = y[j] - y[i];
+            let dz = z[j] - z[i];
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            if r2 >= rc2 {
+                continue;
+            }
+
+            let sr2 = s2 / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            e += sr12 - sr6;
+
+            if let Some((gx, gy, gz)) = &mut g {
+                let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
+
+                gx[i] -= gs * dx;
+                gy[i] -= gs * dy;
+                gz[i] -= gs * dz;
+
+                gx[j] += gs * dx;
+                gy[j] += gs * dy;
+                gz[j] += gs * dz;
+            }
+        }
+    }
+    e
+}
+
+pub(crate) struct NeighbourBuf<T> {
+    pub(crate) j: Vec<usize>,
+    pub(crate) x: Vec<T>,
+    pub(crate) y: Vec<T>,
+    pub(crate) z: Vec<T>,
+}
+
+impl<T: Float> NeighbourBuf<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            j: Vec::new(),
+            x: Vec::new(),
+            y: Vec::new(),
+            z: Vec::new(),
+        }
+    }
+
+    // Gathers the image-shifted coordinates of all particles in the half
+    // shell of cell c, padded to a whole number of chunks with particles
+    // far outside the cutoff
+    pub(crate) fn gather<const N: usize>(
+        &mut self,
+        cells: &CellList<T>,
+        c: usize,
+        x: &[T],
+        y: &[T],
+        z: &[T],
+    ) {
+        self.j.clear();
+        self.x.clear();
+        self.y.clear();
+        self.z.clear();
+
+        for (d, [sx, sy, sz]) in cells.half_shell(c) {
+            for &j in cells.cell(d) {
+                self.j.push(j);
+                self.x.push(x[j] + sx);
+                self.y.push(y[j] + sy);
+                self.z.push(z[j] + sz);
+            }
+        }
+
+        let far = T::max_value().sqrt();
+        while self.x.len() % N != 0 {
+            self.x.push(far);
+            self.y.push(far);
+            self.z.push(far);
+        }
+    }
+}
+
+pub fn lennard_jones_cells<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    cells: &CellList<T>,
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let four = T::from(4.0).unwrap();
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert_eq!(x.len(), cells.indices.len());
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let rc2 = cells.r_cut.powi(2);
+    let rc2s = Simd::splat(rc2);
+
+    let mut buf = NeighbourBuf::new();
+
+    let mut e = T::zero();
+    let mut es = Simd::splat(T::zero());
+    for c in 0..cells.n_cells_total() {
+        let ic = cells.cell(c);
+        if ic.is_empty() {
+            continue;
+        }
+
+        e += lennard_jones_cell_self(s2, rc2, e_b, ic, x, y, z, None);
+
+        buf.gather::<N>(cells, c, x, y, z);
+
+        let (xcs, _): (&[[_; N]], _) = buf.x.as_chunks();
+        let (ycs, _): (&[[_; N]], _) = buf.y.as_chunks();
+        let (zcs, _): (&[[_; N]], _) = buf.z.as_chunks();
+
+        for &i in ic {
+            let xi = Simd::splat(x[i]);
+            let yi = Simd::splat(y[i]);
+            let zi = Simd::splat(z[i]);
+
+            for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
+                let dx = Simd::from(*xc) - xi;
+                let dy = Simd::from(*yc) - yi;
+                let dz = Simd::from(*zc) - zi;
+
+                let r2 = cut(dx * dx + dy * dy + dz * dz, rc2s);
+                let sr2 = s2s / r2;
+                let sr6 = sr2 * sr2 * sr2;
+                let sr12 = sr6 * sr6;
+
+                es += sr12 - sr6;
+            }
+        }
+    }
+
+    e += es.reduce_sum();
+
+    e * four * e_b
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_cells<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+    cells: &CellList<T>,
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let four = T::from(4.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+
+    let e_b_s = Simd::splat(e_b);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert_eq!(x.len(), cells.indices.len());
+
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    gx.fill(T::zero());
+    gy.fill(T::zero());
+    gz.fill(T::zero());
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let rc2 = cells.r_cut.powi(2);
+    let rc2s = Simd::splat(rc2);
+
+    let mut buf = NeighbourBuf::new();
+    let mut gx_buf = Vec::new();
+    let mut gy_buf = Vec::new();
+    let mut gz_buf = Vec::new();
+
+    let mut e = T::zero();
+    let mut es = Simd::splat(T::zero());
+    for c in 0..cells.n_cells_total() {
+        let ic = cells.cell(c);
+        if ic.is_empty() {
+            continue;
+        }
+
+        e += lennard_jones_cell_self(
+            s2,
+            rc2,
+            e_b,
+            ic,
+            x,
+            y,
+            z,
+            Some((&mut *gx, &mut *gy, &mut *gz)),
+        );
+
+        buf.gather::<N>(cells, c, x, y, z);
+
+        for g_buf in [&mut gx_buf, &mut gy_buf, &mut gz_buf] {
+            g_buf.clear();
+            g_buf.resize(buf.x.len(), T::zero());
+        }
+
+        let (xcs, _): (&[[_; N]], _) = buf.x.as_chunks();
+        let (ycs, _): (&[[_; N]], _) = buf.y.as_chunks();
+        let (zcs, _): (&[[_; N]], _) = buf.z.as_chunks();
+
+        let (gxcs, _): (&mut [[_; N]], _) = gx_buf.as_chunks_mut();
+        let (gycs, _): (&mut [[_; N]], _) = gy_buf.as_chunks_mut();
+        let (gzcs, _): (&mut [[_; N]], _) = gz_buf.as_chunks_mut();
+
+        for &i in ic {
+            let xi = Simd::splat(x[i]);
+            let yi = Simd::splat(y[i]);
+            let zi = Simd::splat(z[i]);
+
+            let mut gxi = Simd::splat(T::zero());
+            let mut gyi = Simd::splat(T::zero());
+            let mut gzi = Simd::splat(T::zero());
+
+            for (((((xc, yc), zc), gxc), gyc), gzc) in xcs
+                .iter()
+                .zip(ycs)
+                .zip(zcs)
+                .zip(gxcs.iter_mut())
+                .zip(gycs.iter_mut())
+                .zip(gzcs.iter_mut())
+            {
+                let dx = Simd::from(*xc) - xi;
+                let dy = Simd::from(*yc) - yi;
+                let dz = Simd::from(*zc) - zi;
+
+                let r2 = cut(dx * dx + dy * dy + dz * dz, rc2s);
+                let sr2 = s2s / r2;
+                let sr6 = sr2 * sr2 * sr2;
+                let sr12 = sr6 * sr6;
+
+                es += sr12 - sr6;
+
+                let gs =
+                    -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+                let gxs = gs * dx;
+                let gys = gs * dy;
+                let gzs = gs * dz;
+
+                gxi -= gxs;
+                gyi -= gys;
+                gzi -= gzs;
+
+                *gxc = *(Simd::from(*gxc) + gxs).as_array();
+                *gyc = *(Simd::from(*gyc) + gys).as_array();
+                *gzc = *(Simd::from(*gzc) + gzs).as_array();
+            }
+
+            gx[i] += gxi.reduce_sum();
+            gy[i] += gyi.reduce_sum();
+            gz[i] += gzi.reduce_sum();
+        }
+
+        for (k, &j) in buf.j.iter().enumerate() {
+            gx[j] += gx_buf[k];
+            gy[j] += gy_buf[k];
+            gz[j] += gz_buf[k];
+        }
+    }
+
+    e += es.reduce_sum();
+
+    e * four * e_b
+}
+
+// Pair virial, sum of r_ij . f_ij over all pairs within the cutoff, for the
+// pressure
+pub fn lennard_jones_virial_cells<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    cells: &CellList<T>,
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let two_s = Simd::splat(two);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert_eq!(x.len(), cells.indices.len());
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let rc2 = cells.r_cut.powi(2);
+    let rc2s = Simd::splat(rc2);
+
+    let mut buf = NeighbourBuf::new();
+
+    let mut w = T::zero();
+    let mut ws = Simd::splat(T::zero());
+    for c in 0..cells.n_cells_total() {
+        let ic = cells.cell(c);
+        if ic.is_empty() {
+            continue;
+        }
+
+        for (k, &i) in ic.iter().enumerate() {
+            for &j in &ic[..k] {
+                let dx = x[j] - x[i];
+                let dy = y[j] - y[i];
+                let dz = z[j] - z[i];
+
+                let r2 = dx * dx + dy * dy + dz * dz;
+                if r2 >= rc2 {
+                    continue;
+                }
+
+                let sr2 = s2 / r2;
+                let sr6 = sr2 * sr2 * sr2;
+
+                w += sr6 * (two * sr6 - one);
+            }
+        }
+
+        buf.gather::<N>(cells, c, x, y, z);
+
+        let (xcs, _): (&[[_; N]], _) = buf.x.as_chunks();
+        let (ycs, _): (&[[_; N]], _) = buf.y.as_chunks();
+        let (zcs, _): (&[[_; N]], _) = buf.z.as_chunks();
+
+        for &i in ic {
+            let xi = Simd::splat(x[i]);
+            let yi = Simd::splat(y[i]);
+            let zi = Simd::splat(z[i]);
+
+            for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
+                let dx = Simd::from(*xc) - xi;
+                let dy = Simd::from(*yc) - yi;
+                let dz = Simd::from(*zc) - zi;
+
+                let r2 = cut(dx * dx + dy * dy + dz * dz, rc2s);
+                let sr2 = s2s / r2;
+                let sr6 = sr2 * sr2 * sr2;
+
+                ws += sr6 * (two_s * sr6 - Simd::splat(one));
+            }
+        }
+    }
+
+    w += ws.reduce_sum();
+
+    w * twentyfour * e_b
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::lennard_jones_t::to_soa;
+
+    // Energy, gradient and virial summed over every image within the cutoff,
+    // with shifts of up to two box vectors
+    fn reference(
+        bx: &TriclinicBox<f64>,
+        r_cut: f64,
+        r: &[[f64; 3]],
+    ) -> (f64, Vec<[f64; 3]>, f64) {
+        let s2 = 2.0f64.powf(-1.0 / 3.0);
+        let vs = bx.vectors();
+
+        let mut e = 0.0;
+        let mut g = vec![[0.0; 3]; r.len()];
+        let mut w = 0.0;
+        for (i, ri) in r.iter().enumerate() {
+            for rj in r {
+                for k in 0..125 {
+                    let o = [k / 25, k / 5 % 5, k % 5].map(|o| o as f64 - 2.0);
+                    let d: [f64; 3] = std::array::from_fn(|q| {
+                        rj[q] - ri[q]
+                            + o[0] * vs[0][q]
+                            + o[1] * vs[1][q]
+                            + o[2] * vs[2][q]
+                    });
+                    let r2: f64 = d.iter().map(|d| d * d).sum();
+                    if r2 == 0.0 || r2 >= r_cut * r_cut {
+                        continue;
+                    }
+
+                    // Every pair is seen from both ends
+                    let sr6 = (s2 / r2).powi(3);
+                    e += 2.0 * (sr6 * sr6 - sr6);
+                    w += 12.0 * sr6 * (2.0 * sr6 - 1.0);
+
+                    let gs = -24.0 * sr6 / r2 * (2.0 * sr6 - 1.0);
+                    for (g, d) in g[i].iter_mut().zip(d) {
+                        *g -= gs * d;
+                    }
+                }
+            }
+        }
+        (e, g, w)
+    }
+
+    #[test]
+    fn small_boxes() {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let r_cut = 2.5;
+
+        for bx in [
+            TriclinicBox::orthogonal([4.0, 6.0, 9.0]),
+            TriclinicBox::new([4.5, 5.5, 8.0], [1.0, -0.8, 0.6]),
+        ] {
+            let mut cells = CellList::new_triclinic(&bx, r_cut);
+            assert_eq!(cells.n_cells, [1, 2, 3]);
+
+            // A jittered 3x3x3 lattice in fractional coordinates
+            let r: Vec<_> = (0..27)
+                .map(|k| {
+                    bx.to_cart([k / 9, k / 3 % 3, k % 3].map(|k| {
+                        (k as f64 + 0.5) / 3.0 + rng.gen_range(-0.05..0.05)
+                    }))
+                })
+                .collect();
+            let [x, y, z] = to_soa(&r);
+            cells.build(&x, &y, &z);
+
+            let (e_ref, g_ref, w_ref) = reference(&bx, r_cut, &r);
+
+            let e = lennard_jones_cells::<8, _>(1.0, 1.0, &x, &y, &z, &cells);
+            let mut g = [(); 3].map(|_| vec![0.0; r.len()]);
+            let [gx, gy, gz] = &mut g;
+            let e_g = lennard_jones_grad_cells::<8, _>(
+                1.0, 1.0, &x, &y, &z, gx, gy, gz, &cells,
+            );
+            let w = lennard_jones_virial_cells::<8, _>(
+                1.0, 1.0, &x, &y, &z, &cells,
+            );
+
+            assert!((e - e_ref).abs() < 1e-12 * e_ref.abs());
+            assert!((e_g - e_ref).abs() < 1e-12 * e_ref.abs());
+            assert!((w - w_ref).abs() < 1e-12 * w_ref.abs());
+            for (i, g_ref) in g_ref.iter().enumerate() {
+                for (g, g_ref) in g.iter().zip(g_ref) {
+                    assert!((g[i] - g_ref).abs() < 1e-10);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least r_cut wide")]
+    fn box_below_cutoff() {
+        CellList::new([2.0, 4.0, 4.0], 2.5);
+    }
+}
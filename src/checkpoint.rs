@@ -0,0 +1,556 @@
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    bonded::{BondPotential, DihedralPotential, Topology},
+    constraints::Constraints,
+    external::External,
+    md::{Barostat, Md, System, Thermostat},
+};
+
+const MAGIC: &[u8; 8] = b"LJMDCKPT";
+const VERSION: u32 = 6;
+
+// Layout, all little endian:
+//
+//   magic, version: u32, step: u64, n: u64, box_l: [f64; 3],
+//   r_eq, e_b, r_cut, dt: f64,
+//   thermostat tag: u8, thermostat parameters: [f64; 3],
+//   rng seed: [u8; 32], rng stream: u64, rng word position: u128,
+//   x, y, z, vx, vy, vz: [f64; n], species: [u8; n]
+//
+// followed since version 2 by a u8 flag for whether a topology is present and
+// if so:
+//
+//   bond tag: u8, bond parameters: [f64; 2], k_angle: f64,
+//   dihedral k, d: f64, dihedral n: u32,
+//   bonds, angles, dihedrals, exclusions: count: u64, [u64; count * arity]
+//
+// and since version 3 by a u8 flag for whether constraints are present and if
+// so:
+//
+//   tol: f64, max_iter: u64, pairs: count: u64, [u64; count * 2],
+//   lengths: [f64; count], constraint virial: f64
+//
+// and since version 4 by the external fields, count: u64 and for each a u8 tag
+// followed by its parameters in declaration order, with the wall axis as u8,
+// since version 5 by a u8 flag for whether tail corrections are enabled, and
+// since version 6 by the barostat:
+//
+//   barostat tag: u8, barostat parameters: [f64; 3], interval: u64,
+//   volume moves, accepted volume moves: u64
+//
+// Forces are not stored since they are a pure function of the positions and
+// are recomputed bit for bit on load.
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn write_f64s<W: Write>(w: &mut W, v: &[f64]) -> io::Result<()> {
+    for x in v {
+        w.write_all(&x.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_bytes<R: Read, const N: usize>(r: &mut R) -> io::Result<[u8; N]> {
+    let mut buf = [0; N];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    Ok(u64::from_le_bytes(read_bytes(r)?))
+}
+
+fn read_f64<R: Read>(r: &mut R) -> io::Result<f64> {
+    Ok(f64::from_le_bytes(read_bytes(r)?))
+}
+
+fn write_indices<W: Write, const M: usize>(
+    w: &mut W,
+    v: &[[usize; M]],
+) -> io::Result<()> {
+    w.write_all(&(v.len() as u64).to_le_bytes())?;
+    for &i in v.iter().flatten() {
+        w.write_all(&(i as u64).to_le_bytes())?;
+    }
+    Ok(())
+}
+
+fn read_indices<R: Read, const M: usize>(
+    r: &mut R,
+) -> io::Result<Vec<[usize; M]>> {
+    let n = read_u64(r)?;
+    (0..n)
+        .map(|_| {
+            let mut t = [0; M];
+            for i in t.iter_mut() {
+                *i = read_u64(r)? as usize;
+            }
+            Ok(t)
+        })
+        .collect()
+}
+
+fn write_topology<W: Write>(w: &mut W, t: &Topology<f64>) -> io::Result<()> {
+    let (tag, params) = match t.bond {
+        BondPotential::Harmonic { k, r0 } => (0u8, [k, r0]),
+        BondPotential::Fene { k, r_max } => (1, [k, r_max]),
+    };
+    w.write_all(&[tag])?;
+    write_f64s(w, &params)?;
+    write_f64s(w, &[t.k_angle, t.dihedral.k, t.dihedral.d])?;
+    w.write_all(&t.dihedral.n.to_le_bytes())?;
+
+    write_indices(w, &t.bonds)?;
+    write_indices(w, &t.angles)?;
+    write_indices(w, &t.dihedrals)?;
+    write_indices(w, &t.exclusions)
+}
+
+fn read_topology<R: Read>(r: &mut R) -> io::Result<Topology<f64>> {
+    let [tag] = read_bytes::<_, 1>(r)?;
+    let [a, b] = [read_f64(r)?, read_f64(r)?];
+    let bond = match tag {
+        0 => BondPotential::Harmonic { k: a, r0: b },
+        1 => BondPotential::Fene { k: a, r_max: b },
+        _ => return Err(invalid("unknown bond potential")),
+    };
+
+    let k_angle = read_f64(r)?;
+    let dihedral = DihedralPotential {
+        k: read_f64(r)?,
+        d: read_f64(r)?,
+        n: u32::from_le_bytes(read_bytes(r)?),
+    };
+
+    let mut t = Topology::new(bond, k_angle, dihedral);
+    t.bonds = read_indices(r)?;
+    t.angles = read_indices(r)?;
+    t.dihedrals = read_indices(r)?;
+    t.exclusions = read_indices(r)?;
+
+    Ok(t)
+}
+
+fn write_constraints<W: Write>(w: &mut W, c: &Constraints) -> io::Result<()> {
+    write_f64s(w, &[c.tol])?;
+    w.write_all(&(c.max_iter as u64).to_le_bytes())?;
+    write_indices(w, &c.pairs)?;
+    write_f64s(w, &c.lengths)
+}
+
+fn read_constraints<R: Read>(r: &mut R) -> io::Result<Constraints> {
+    let mut c = Constraints::new(read_f64(r)?, read_u64(r)? as usize);
+    c.pairs = read_indices(r)?;
+    c.lengths = read_f64s(r, c.pairs.len())?;
+    Ok(c)
+}
+
+fn write_external<W: Write>(w: &mut W, f: &External<f64>) -> io::Result<()> {
+    match *f {
+        External::Wall93 {
+            axis,
+            pos,
+            epsilon,
+            sigma,
+        } => {
+            w.write_all(&[0, axis as u8])?;
+            write_f64s(w, &[pos, epsilon, sigma])
+        }
+        External::Wall1043 {
+            axis,
+            pos,
+            epsilon,
+            sigma,
+            rho,
+            delta,
+        } => {
+            w.write_all(&[1, axis as u8])?;
+            write_f64s(w, &[pos, epsilon, sigma, rho, delta])
+        }
+        External::Harmonic { center, k } => {
+            w.write_all(&[2])?;
+            write_f64s(w, &center)?;
+            write_f64s(w, &k)
+        }
+        External::Force { f } => {
+            w.write_all(&[3])?;
+            write_f64s(w, &f)
+        }
+        External::Sphere {
+            center,
+            radius,
+            epsilon,
+            sigma,
+        } => {
+            w.write_all(&[4])?;
+            write_f64s(w, &center)?;
+            write_f64s(w, &[radius, epsilon, sigma])
+        }
+    }
+}
+
+fn read_external<R: Read>(r: &mut R) -> io::Result<External<f64>> {
+    let [tag] = read_bytes::<_, 1>(r)?;
+
+    let mut read_axis = || -> io::Result<usize> {
+        match read_bytes::<_, 1>(r)? {
+            [a] if a < 3 => Ok(a as usize),
+            _ => Err(invalid("invalid wall axis")),
+        }
+    };
+
+    Ok(match tag {
+        0 => External::Wall93 {
+            axis: read_axis()?,
+            pos: read_f64(r)?,
+            epsilon: read_f64(r)?,
+            sigma: read_f64(r)?,
+        },
+        1 => External::Wall1043 {
+            axis: read_axis()?,
+            pos: read_f64(r)?,
+            epsilon: read_f64(r)?,
+            sigma: read_f64(r)?,
+            rho: read_f64(r)?,
+            delta: read_f64(r)?,
+        },
+        2 => External::Harmonic {
+            center: [read_f64(r)?, read_f64(r)?, read_f64(r)?],
+            k: [read_f64(r)?, read_f64(r)?, read_f64(r)?],
+        },
+        3 => External::Force {
+            f: [read_f64(r)?, read_f64(r)?, read_f64(r)?],
+        },
+        4 => External::Sphere {
+            center: [read_f64(r)?, read_f64(r)?, read_f64(r)?],
+            radius: read_f64(r)?,
+            epsilon: read_f64(r)?,
+            sigma: read_f64(r)?,
+        },
+        _ => return Err(invalid("unknown external field")),
+    })
+}
+
+fn read_f64s<R: Read>(r: &mut R, n: usize) -> io::Result<Vec<f64>> {
+    (0..n).map(|_| read_f64(r)).collect()
+}
+
+impl Md {
+    pub fn save<W: Write>(&self, mut w: W) -> io::Result<()> {
+        let sys = &self.sys;
+
+        w.write_all(MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&self.step.to_le_bytes())?;
+        w.write_all(&(sys.len() as u64).to_le_bytes())?;
+        write_f64s(&mut w, &sys.box_l)?;
+        write_f64s(&mut w, &[self.r_eq, self.e_b, self.r_cut, self.dt])?;
+
+        let (tag, params) = match self.thermostat {
+            Thermostat::None => (0u8, [0.0; 3]),
+            Thermostat::Langevin { temperature, gamma } => {
+                (1, [temperature, gamma, 0.0])
+            }
+            Thermostat::NoseHoover { temperature, q, xi } => {
+                (2, [temperature, q, xi])
+            }
+        };
+        w.write_all(&[tag])?;
+        write_f64s(&mut w, &params)?;
+
+        w.write_all(&self.rng.get_seed())?;
+        w.write_all(&self.rng.get_stream().to_le_bytes())?;
+        w.write_all(&self.rng.get_word_pos().to_le_bytes())?;
+
+        for v in [&sys.x, &sys.y, &sys.z, &sys.vx, &sys.vy, &sys.vz] {
+            write_f64s(&mut w, v)?;
+        }
+        w.write_all(&sys.species)?;
+
+        match &self.topology {
+            Some(t) => {
+                w.write_all(&[1])?;
+                write_topology(&mut w, t)?;
+            }
+            None => w.write_all(&[0])?,
+        }
+
+        match &self.constraints {
+            Some(c) => {
+                w.write_all(&[1])?;
+                write_constraints(&mut w, c)?;
+                write_f64s(&mut w, &[self.constraint_virial])?;
+            }
+            None => w.write_all(&[0])?,
+        }
+
+        w.write_all(&(self.external.len() as u64).to_le_bytes())?;
+        for f in &self.external {
+            write_external(&mut w, f)?;
+        }
+
+        w.write_all(&[self.tail_correction as u8])?;
+
+        let (tag, params, interval) = match self.barostat {
+            Barostat::None => (0u8, [0.0; 3], 0),
+            Barostat::Berendsen {
+                pressure,
+                tau,
+                compressibility,
+            } => (1, [pressure, tau, compressibility], 0),
+            Barostat::MonteCarlo {
+                pressure,
+                temperature,
+                max_dlnv,
+                interval,
+            } => (2, [pressure, temperature, max_dlnv], interval),
+        };
+        w.write_all(&[tag])?;
+        write_f64s(&mut w, &params)?;
+        for x in [interval, self.volume_moves, self.volume_accepted] {
+            w.write_all(&x.to_le_bytes())?;
+        }
+
+        w.flush()
+    }
+
+    pub fn load<R: Read>(mut r: R) -> io::Result<Self> {
+        if &read_bytes::<_, 8>(&mut r)? != MAGIC {
+            return Err(invalid("not a checkpoint file"));
+        }
+
+        let version = u32::from_le_bytes(read_bytes(&mut r)?);
+        if !(1..=VERSION).contains(&version) {
+            return Err(invalid(&format!(
+                "unsupported checkpoint version {version}"
+            )));
+        }
+
+        let step = read_u64(&mut r)?;
+        let n = read_u64(&mut r)? as usize;
+
+        let box_l = [read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?];
+        let [r_eq, e_b, r_cut, dt] = [
+            read_f64(&mut r)?,
+            read_f64(&mut r)?,
+            read_f64(&mut r)?,
+            read_f64(&mut r)?,
+        ];
+
+        let [tag] = read_bytes::<_, 1>(&mut r)?;
+        let [a, b, c] =
+            [read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?];
+        let thermostat = match tag {
+            0 => Thermostat::None,
+            1 => Thermostat::Langevin {
+                temperature: a,
+                gamma: b,
+            },
+            2 => Thermostat::NoseHoover {
+                temperature: a,
+                q: b,
+                xi: c,
+            },
+            _ => return Err(invalid("unknown thermostat")),
+        };
+
+        let mut rng = ChaCha8Rng::from_seed(read_bytes(&mut r)?);
+        rng.set_stream(read_u64(&mut r)?);
+        rng.set_word_pos(u128::from_le_bytes(read_bytes(&mut r)?));
+
+        let mut sys = System::new(
+            box_l,
+            [
+                read_f64s(&mut r, n)?,
+                read_f64s(&mut r, n)?,
+                read_f64s(&mut r, n)?,
+            ],
+        );
+        sys.vx = read_f64s(&mut r, n)?;
+        sys.vy = read_f64s(&mut r, n)?;
+        sys.vz = read_f64s(&mut r, n)?;
+        r.read_exact(&mut sys.species)?;
+
+        let mut md = Self::new(sys, r_eq, e_b, r_cut, dt, thermostat, rng);
+        md.step = step;
+
+        if version >= 2 && read_bytes::<_, 1>(&mut r)? == [1] {
+            md.set_topology(read_topology(&mut r)?);
+        }
+
+        // The velocities were saved projected, so they are not projected again
+        if version >= 3 && read_bytes::<_, 1>(&mut r)? == [1] {
+            md.constraints = Some(read_constraints(&mut r)?);
+            md.constraint_virial = read_f64(&mut r)?;
+        }
+
+        if version >= 4 {
+            let n = read_u64(&mut r)?;
+            let external = (0..n)
+                .map(|_| read_external(&mut r))
+                .collect::<io::Result<_>>()?;
+            md.set_external(external);
+        }
+
+        if version >= 5 && read_bytes::<_, 1>(&mut r)? == [1] {
+            md.set_tail_correction(true);
+        }
+
+        if version >= 6 {
+            let [tag] = read_bytes::<_, 1>(&mut r)?;
+            let [a, b, c] =
+                [read_f64(&mut r)?, read_f64(&mut r)?, read_f64(&mut r)?];
+            let interval = read_u64(&mut r)?;
+            md.barostat = match tag {
+                0 => Barostat::None,
+                1 => Barostat::Berendsen {
+                    pressure: a,
+                    tau: b,
+                    compressibility: c,
+                },
+                2 if interval == 0 => {
+                    return Err(invalid("zero volume move interval"))
+                }
+                2 => Barostat::MonteCarlo {
+                    pressure: a,
+                    temperature: b,
+                    max_dlnv: c,
+                    interval,
+                },
+                _ => return Err(invalid("unknown barostat")),
+            };
+            md.volume_moves = read_u64(&mut r)?;
+            md.volume_accepted = read_u64(&mut r)?;
+        }
+
+        Ok(md)
+    }
+
+    pub fn save_file<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.save(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::load(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lennard_jones_t::setup_cubic_lattice;
+
+    fn new_md(thermostat: Thermostat, barostat: Barostat) -> Md {
+        let (n, a) = (5, 1.1);
+        let sys = System::new([a * n as f64; 3], setup_cubic_lattice(n, a));
+        let mut md = Md::with_seed(sys, 1.0, 1.0, 2.5, 0.005, thermostat, 42);
+        md.set_barostat(barostat);
+        md.init_velocities(0.5);
+        md
+    }
+
+    fn assert_bits_eq(u: &[f64], v: &[f64]) {
+        assert_eq!(u.len(), v.len());
+        assert!(u.iter().zip(v).all(|(a, b)| a.to_bits() == b.to_bits()));
+    }
+
+    // n steps, a save and load through memory and m more steps end in exactly
+    // the same state as n + m steps without interruption
+    #[test]
+    fn restart_is_bit_identical() {
+        let (n, m) = (30, 30);
+
+        let langevin = Thermostat::Langevin {
+            temperature: 0.5,
+            gamma: 1.0,
+        };
+        let nose_hoover = Thermostat::NoseHoover {
+            temperature: 0.5,
+            q: 10.0,
+            xi: 0.0,
+        };
+        let monte_carlo = Barostat::MonteCarlo {
+            pressure: 1.0,
+            temperature: 0.5,
+            max_dlnv: 0.01,
+            interval: 4,
+        };
+
+        for (thermostat, barostat) in [
+            (Thermostat::None, Barostat::None),
+            (langevin, Barostat::None),
+            (nose_hoover, Barostat::None),
+            (langevin, monte_carlo),
+        ] {
+            let mut md = new_md(thermostat, barostat);
+            md.run(n + m);
+
+            let mut md2 = new_md(thermostat, barostat);
+            md2.run(n);
+            let mut buf = Vec::new();
+            md2.save(&mut buf).unwrap();
+            drop(md2);
+
+            let mut md2 = Md::load(&buf[..]).unwrap();
+            md2.run(m);
+
+            assert_eq!(md.step, md2.step);
+            assert_eq!(md.thermostat, md2.thermostat);
+            assert_eq!(md.barostat, md2.barostat);
+            assert_eq!(md.volume_moves, md2.volume_moves);
+            assert_eq!(md.volume_accepted, md2.volume_accepted);
+
+            assert_eq!(md.rng.get_seed(), md2.rng.get_seed());
+            assert_eq!(md.rng.get_stream(), md2.rng.get_stream());
+            assert_eq!(md.rng.get_word_pos(), md2.rng.get_word_pos());
+
+            assert_bits_eq(&md.sys.box_l, &md2.sys.box_l);
+            assert_bits_eq(&[md.e_pot], &[md2.e_pot]);
+            for (u, v) in [
+                (&md.sys.x, &md2.sys.x),
+                (&md.sys.y, &md2.sys.y),
+                (&md.sys.z, &md2.sys.z),
+                (&md.sys.vx, &md2.sys.vx),
+                (&md.sys.vy, &md2.sys.vy),
+                (&md.sys.vz, &md2.sys.vz),
+            ] {
+                assert_bits_eq(u, v);
+            }
+
+            if barostat != Barostat::None {
+                assert!(md.volume_moves > 0);
+            }
+        }
+    }
+
+    // The interval is followed by the two volume move counters
+    #[test]
+    fn rejects_zero_volume_move_interval() {
+        let md = new_md(
+            Thermostat::None,
+            Barostat::MonteCarlo {
+                pressure: 1.0,
+                temperature: 0.5,
+                max_dlnv: 0.01,
+                interval: 4,
+            },
+        );
+        let mut buf = Vec::new();
+        md.save(&mut buf).unwrap();
+
+        let k = buf.len() - 24;
+        buf[k..k + 8].fill(0);
+        let err = Md::load(&buf[..]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,280 @@
+use std::{
+    ops::{Add, Div, Mul, Neg, Sub},
+    simd::{LaneCount, Simd, SimdElement, SupportedLaneCount},
+};
+
+use num_traits::Float;
+
+use crate::lennard_jones_t::sigma2;
+
+// Many small independent clusters of the same size with the cluster index in
+// the lanes. Every batch of N clusters is a block of n_atoms [[T; N]; 3], so
+// atom i of the N clusters loads as one vector and every pair of atoms is one
+// SIMD evaluation for the whole batch. The last batch is padded with copies of
+// the last cluster, whose results are discarded.
+pub struct Clusters<T, const N: usize> {
+    pub n_atoms: usize,
+    pub len: usize,
+    pub atoms: Vec<[[T; N]; 3]>,
+}
+
+impl<T: Float + SimdElement, const N: usize> Clusters<T, N> {
+    pub fn zeros(n_atoms: usize, len: usize) -> Self {
+        Self {
+            n_atoms,
+            len,
+            atoms: vec![[[T::zero(); N]; 3]; (len + N - 1) / N * n_atoms],
+        }
+    }
+
+    pub fn from_soa(clusters: &[[Vec<T>; 3]]) -> Self {
+        let n_atoms = clusters.first().map_or(0, |c| c[0].len());
+        let mut cl = Self::zeros(n_atoms, clusters.len());
+
+        for c in 0..cl.n_batches() * N {
+            let r = &clusters[c.min(clusters.len() - 1)];
+            cl.set_cluster(c, [&r[0], &r[1], &r[2]]);
+        }
+
+        cl
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn n_batches(&self) -> usize {
+        (self.len + N - 1) / N
+    }
+
+    pub fn set_cluster(&mut self, c: usize, r: [&[T]; 3]) {
+        let (b, l) = (c / N, c % N);
+        let atoms = &mut self.atoms[b * self.n_atoms..(b + 1) * self.n_atoms];
+        for (q, r) in r.into_iter().enumerate() {
+            assert_eq!(r.len(), atoms.len());
+            for (a, &x) in atoms.iter_mut().zip(r) {
+                a[q][l] = x;
+            }
+        }
+    }
+
+    pub fn cluster(&self, c: usize) -> [Vec<T>; 3] {
+        let (b, l) = (c / N, c % N);
+        let atoms = &self.atoms[b * self.n_atoms..(b + 1) * self.n_atoms];
+        [0, 1, 2].map(|q| atoms.iter().map(|a| a[q][l]).collect())
+    }
+}
+
+fn batch<const N: usize, T>(
+    s2: T,
+    e_b: T,
+    atoms: &[[[T; N]; 3]],
+    e: &mut [T],
+    mut g: Option<&mut [[[T; N]; 3]]>,
+) where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+{
+    let zero = Simd::splat(T::zero());
+    let one = Simd::splat(T::one());
+    let two = Simd::splat(T::from(2.0).unwrap());
+    let twentyfour_e_b = Simd::splat(T::from(24.0).unwrap() * e_b);
+    let s2s = Simd::splat(s2);
+
+    if let Some(g) = &mut g {
+        g.fill([[T::zero(); N]; 3]);
+    }
+
+    let mut es = zero;
+    for (i, ai) in atoms.iter().enumerate() {
+        let ri = ai.map(Simd::from_array);
+        let mut gi = [zero; 3];
+
+        for (j, aj) in atoms[..i].iter().enumerate() {
+            let mut d = [zero; 3];
+            for ((d, &aj), ri) in d.iter_mut().zip(aj).zip(ri) {
+                *d = Simd::from_array(aj) - ri;
+            }
+            let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            es += sr6 * sr6 - sr6;
+
+            if let Some(g) = &mut g {
+                let gs = -twentyfour_e_b * sr6 / r2 * (two * sr6 - one);
+                for ((gj, gi), d) in g[j].iter_mut().zip(&mut gi).zip(d) {
+                    let gq = gs * d;
+                    *gi -= gq;
+                    *gj = (Simd::from_array(*gj) + gq).to_array();
+                }
+            }
+        }
+
+        if let Some(g) = &mut g {
+            for (g, gi) in g[i].iter_mut().zip(gi) {
+                *g = (Simd::from_array(*g) + gi).to_array();
+            }
+        }
+    }
+
+    let es = (es * Simd::splat(T::from(4.0).unwrap() * e_b)).to_array();
+    let n = e.len();
+    e.copy_from_slice(&es[..n]);
+}
+
+// Fills e with the energy of every cluster
+pub fn lennard_jones_clusters<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: &Clusters<T, N>,
+    e: &mut [T],
+) where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+{
+    assert_eq!(e.len(), r.len());
+
+    // Clusters without atoms have no batches to fill in their energy
+    if r.n_atoms == 0 {
+        e.fill(T::zero());
+        return;
+    }
+
+    let s2 = sigma2(r_eq);
+    for (atoms, e) in r.atoms.chunks(r.n_atoms).zip(e.chunks_mut(N)) {
+        batch(s2, e_b, atoms, e, None);
+    }
+}
+
+// Fills e with the energy and g with the gradient of every cluster
+pub fn lennard_jones_grad_clusters<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: &Clusters<T, N>,
+    e: &mut [T],
+    g: &mut Clusters<T, N>,
+) where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+{
+    assert_eq!(e.len(), r.len());
+    assert_eq!(g.atoms.len(), r.atoms.len());
+
+    if r.n_atoms == 0 {
+        e.fill(T::zero());
+        return;
+    }
+
+    let s2 = sigma2(r_eq);
+    for ((atoms, e), g) in r
+        .atoms
+        .chunks(r.n_atoms)
+        .zip(e.chunks_mut(N))
+        .zip(g.atoms.chunks_mut(r.n_atoms))
+    {
+        batch(s2, e_b, atoms, e, Some(g));
+    }
+}
+
+// Parallel over batches
+pub fn lennard_jones_clusters_par<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: &Clusters<T, N>,
+    e: &mut [T],
+) where
+    T: Float + SimdElement + Send + Sync,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+{
+    use rayon::prelude::*;
+
+    assert_eq!(e.len(), r.len());
+
+    if r.n_atoms == 0 {
+        e.fill(T::zero());
+        return;
+    }
+
+    let s2 = sigma2(r_eq);
+    r.atoms
+        .par_chunks(r.n_atoms)
+        .zip(e.par_chunks_mut(N))
+        .for_each(|(atoms, e)| batch(s2, e_b, atoms, e, None));
+}
+
+pub fn lennard_jones_grad_clusters_par<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: &Clusters<T, N>,
+    e: &mut [T],
+    g: &mut Clusters<T, N>,
+) where
+    T: Float + SimdElement + Send + Sync,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>,
+{
+    use rayon::prelude::*;
+
+    assert_eq!(e.len(), r.len());
+    assert_eq!(g.atoms.len(), r.atoms.len());
+
+    if r.n_atoms == 0 {
+        e.fill(T::zero());
+        return;
+    }
+
+    let s2 = sigma2(r_eq);
+    r.atoms
+        .par_chunks(r.n_atoms)
+        .zip(e.par_chunks_mut(N))
+        .zip(g.atoms.par_chunks_mut(r.n_atoms))
+        .for_each(|((atoms, e), g)| batch(s2, e_b, atoms, e, Some(g)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_atoms() {
+        let r = Clusters::<f64, 4>::zeros(0, 5);
+        let mut g = Clusters::zeros(0, 5);
+
+        let mut e = vec![1.0; 5];
+        lennard_jones_clusters(1.0, 1.0, &r, &mut e);
+        assert_eq!(e, [0.0; 5]);
+
+        let mut e = vec![1.0; 5];
+        lennard_jones_grad_clusters_par(1.0, 1.0, &r, &mut e, &mut g);
+        assert_eq!(e, [0.0; 5]);
+    }
+}
@@ -0,0 +1,154 @@
+fn min_image(d: f64, l: f64) -> f64 {
+    d - (d / l).round() * l
+}
+
+fn diff(box_l: [f64; 3], r: [&[f64]; 3], i: usize, j: usize) -> [f64; 3] {
+    [0, 1, 2].map(|q| min_image(r[q][j] - r[q][i], box_l[q]))
+}
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+// Fixed distance constraints between pairs of unit mass particles. SHAKE
+// iterates until every squared length is within a relative tolerance tol, and
+// RATTLE until every relative velocity along a constraint is below tol d / dt,
+// panicking if max_iter sweeps are not enough.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Constraints {
+    pub pairs: Vec<[usize; 2]>,
+    pub lengths: Vec<f64>,
+    pub tol: f64,
+    pub max_iter: usize,
+}
+
+impl Constraints {
+    pub fn new(tol: f64, max_iter: usize) -> Self {
+        Self {
+            pairs: Vec::new(),
+            lengths: Vec::new(),
+            tol,
+            max_iter,
+        }
+    }
+
+    pub fn from_bonds(
+        bonds: &[[usize; 2]],
+        length: f64,
+        tol: f64,
+        max_iter: usize,
+    ) -> Self {
+        Self {
+            pairs: bonds.to_vec(),
+            lengths: vec![length; bonds.len()],
+            tol,
+            max_iter,
+        }
+    }
+
+    pub fn add(&mut self, i: usize, j: usize, length: f64) {
+        self.pairs.push([i, j]);
+        self.lengths.push(length);
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    // Corrects the positions r after an unconstrained drift from r_old, and the
+    // velocities consistently with the correction. Returns the virial of the
+    // constraint forces, sum of r_ij . f_ij.
+    pub fn shake(
+        &self,
+        box_l: [f64; 3],
+        r_old: [&[f64]; 3],
+        [x, y, z]: [&mut [f64]; 3],
+        [vx, vy, vz]: [&mut [f64]; 3],
+        dt: f64,
+    ) -> f64 {
+        let mut w = 0.0;
+
+        for _ in 0..self.max_iter {
+            let mut done = true;
+
+            for (&[i, j], &d) in self.pairs.iter().zip(&self.lengths) {
+                let s = diff(box_l, [&*x, &*y, &*z], i, j);
+                let delta = d * d - dot(s, s);
+                if delta.abs() <= 2.0 * self.tol * d * d {
+                    continue;
+                }
+                done = false;
+
+                let s_old = diff(box_l, r_old, i, j);
+                let g = delta / (4.0 * dot(s, s_old));
+
+                let rv = [
+                    (&mut *x, &mut *vx),
+                    (&mut *y, &mut *vy),
+                    (&mut *z, &mut *vz),
+                ];
+                for (q, (r, v)) in rv.into_iter().enumerate() {
+                    let dr = g * s_old[q];
+                    r[i] -= dr;
+                    r[j] += dr;
+                    v[i] -= dr / dt;
+                    v[j] += dr / dt;
+                }
+
+                w += 2.0 * g * dot(s_old, s_old) / (dt * dt);
+            }
+
+            if done {
+                return w;
+            }
+        }
+
+        panic!("SHAKE did not converge in {} iterations", self.max_iter);
+    }
+
+    // Removes the velocity components along the constraints. Returns the
+    // virial of the constraint forces at the current positions.
+    pub fn rattle(
+        &self,
+        box_l: [f64; 3],
+        r: [&[f64]; 3],
+        [vx, vy, vz]: [&mut [f64]; 3],
+        dt: f64,
+    ) -> f64 {
+        let mut w = 0.0;
+
+        for _ in 0..self.max_iter {
+            let mut done = true;
+
+            for (&[i, j], &d) in self.pairs.iter().zip(&self.lengths) {
+                let s = diff(box_l, r, i, j);
+                let u = [vx[j] - vx[i], vy[j] - vy[i], vz[j] - vz[i]];
+                let sv = dot(s, u);
+                if sv.abs() * dt <= self.tol * d * d {
+                    continue;
+                }
+                done = false;
+
+                let k = sv / (2.0 * dot(s, s));
+
+                let vs = [&mut *vx, &mut *vy, &mut *vz];
+                for (q, v) in vs.into_iter().enumerate() {
+                    v[i] += k * s[q];
+                    v[j] -= k * s[q];
+                }
+
+                w -= 2.0 * k * dot(s, s) / dt;
+            }
+
+            if done {
+                return w;
+            }
+        }
+
+        panic!("RATTLE did not converge in {} iterations", self.max_iter);
+    }
+}
@@ -0,0 +1,791 @@
+use std::{
+    cmp::Ordering,
+    iter::Sum,
+    num::FpCategory,
+    ops::{
+        Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Rem, Sub,
+        SubAssign,
+    },
+};
+
+use num_traits::{Float, Num, NumCast, One, ToPrimitive, Zero};
+
+use crate::accumulator::AccValue;
+
+// Forward mode automatic differentiation. A dual number re + eps e with
+// e^2 = 0 carries the derivative along one direction through any function
+// generic over Float, so f(x + v e) = f(x) + (grad f . v) e. Comparisons only
+// look at the value, so branches like cutoffs follow the primal computation.
+//
+// Since the derivative part may itself be any Float, nesting gives second
+// derivatives: with x + u e1 + v e2 as a HyperDual the e1 e2 part of f is
+// u^T H v.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Dual<T> {
+    pub re: T,
+    pub eps: T,
+}
+
+pub type HyperDual<T> = Dual<Dual<T>>;
+
+impl<T: Float> Dual<T> {
+    pub fn new(re: T, eps: T) -> Self {
+        Self { re, eps }
+    }
+
+    pub fn constant(re: T) -> Self {
+        Self::new(re, T::zero())
+    }
+
+    pub fn variable(re: T) -> Self {
+        Self::new(re, T::one())
+    }
+
+    // Value f(re) with derivative df at re by the chain rule
+    fn chain(self, f: T, df: T) -> Self {
+        Self::new(f, self.eps * df)
+    }
+}
+
+// Seeds the coordinates with the direction v
+pub fn seed<T: Float, const D: usize>(
+    r: &[[T; D]],
+    v: &[[T; D]],
+) -> Vec<[Dual<T>; D]> {
+    assert_eq!(r.len(), v.len());
+
+    r.iter()
+        .zip(v)
+        .map(|(r, v)| {
+            let mut d = [Dual::constant(T::zero()); D];
+            for q in 0..D {
+                d[q] = Dual::new(r[q], v[q]);
+            }
+            d
+        })
+        .collect()
+}
+
+// Gradient of f at r from one forward pass per coordinate
+pub fn gradient<T: Float, const D: usize>(
+    r: &[[T; D]],
+    f: impl Fn(&[[Dual<T>; D]]) -> Dual<T>,
+) -> Vec<[T; D]> {
+    let mut rd = seed(r, &vec![[T::zero(); D]; r.len()]);
+    let mut g = vec![[T::zero(); D]; r.len()];
+
+    for i in 0..r.len() {
+        for q in 0..D {
+            rd[i][q].eps = T::one();
+            g[i][q] = f(&rd).eps;
+            rd[i][q].eps = T::zero();
+        }
+    }
+
+    g
+}
+
+impl<T: PartialEq> PartialEq for Dual<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.re == other.re
+    }
+}
+
+impl<T: PartialOrd> PartialOrd for Dual<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.re.partial_cmp(&other.re)
+    }
+}
+
+impl<T: Float> Add for Dual<T> {
+    type Output = Self;
+
+    fn add(self, o: Self) -> Self {
+        Self::new(self.re + o.re, self.eps + o.eps)
+    }
+}
+
+impl<T: Float> Sub for Dual<T> {
+    type Output = Self;
+
+    fn sub(self, o: Self) -> Self {
+        Self::new(self.re - o.re, self.eps - o.eps)
+    }
+}
+
+impl<T: Float> Mul for Dual<T> {
+    type Output = Self;
+
+    fn mul(self, o: Self) -> Self {
+        Self::new(self.re * o.re, self.eps * o.re + self.re * o.eps)
+    }
+}
+
+impl<T: Float> Div for Dual<T> {
+    type Output = Self;
+
+    fn div(self, o: Self) -> Self {
+        let re = self.re / o.re;
+        Self::new(re, (self.eps - re * o.eps) / o.re)
+    }
+}
+
+impl<T: Float> Rem for Dual<T> {
+    type Output = Self;
+
+    fn rem(self, o: Self) -> Self {
+        let k = (self.re / o.re).trunc();
+        Self::new(self.re % o.re, self.eps - k * o.eps)
+    }
+}
+
+impl<T: Float> Neg for Dual<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self::new(-self.re, -self.eps)
+    }
+}
+
+impl<T: Float> AddAssign for Dual<T> {
+    fn add_assign(&mut self, o: Self) {
+        *self = *self + o;
+    }
+}
+
+impl<T: Float> SubAssign for Dual<T> {
+    fn sub_assign(&mut self, o: Self) {
+        *self = *self - o;
+    }
+}
+
+impl<T: Float> MulAssign for Dual<T> {
+    fn mul_assign(&mut self, o: Self) {
+        *self = *self * o;
+    }
+}
+
+impl<T: Float> DivAssign for Dual<T> {
+    fn div_assign(&mut self, o: Self) {
+        *self = *self / o;
+    }
+}
+
+impl<T: Float> Sum for Dual<T> {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::zero(), |a, b| a + b)
+    }
+}
+
+impl<T: Float> Zero for Dual<T> {
+    fn zero() -> Self {
+        Self::constant(T::zero())
+    }
+
+    fn is_zero(&self) -> bool {
+        self.re.is_zero() && self.eps.is_zero()
+    }
+}
+
+impl<T: Float> One for Dual<T> {
+    fn one() -> Self {
+        Self::constant(T::one())
+    }
+}
+
+impl<T: Float> Num for Dual<T> {
+    type FromStrRadixErr = T::FromStrRadixErr;
+
+    fn from_str_radix(
+        s: &str,
+        radix: u32,
+    ) -> Result<Self, Self::FromStrRadixErr> {
+        T::from_str_radix(s, radix).map(Self::constant)
+    }
+}
+
+impl<T: Float> ToPrimitive for Dual<T> {
+    fn to_i64(&self) -> Option<i64> {
+        self.re.to_i64()
+    }
+
+    fn to_u64(&self) -> Option<u64> {
+        self.re.to_u64()
+    }
+
+    fn to_f32(&self) -> Option<f32> {
+        self.re.to_f32()
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        self.re.to_f64()
+    }
+}
+
+impl<T: Float> NumCast for Dual<T> {
+    fn from<N: ToPrimitive>(n: N) -> Option<Self> {
+        T::from(n).map(Self::constant)
+    }
+}
+
+impl<T: Float + AccValue> AccValue for Dual<T> {
+    fn zeroed() -> Self {
+        Self::new(T::zeroed(), T::zeroed())
+    }
+
+    fn by_magnitude(a: Self, b: Self) -> (Self, Self) {
+        if a.re.abs() >= b.re.abs() {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+}
+
+impl<T: Float> Float for Dual<T> {
+    fn nan() -> Self {
+        Self::constant(T::nan())
+    }
+
+    fn infinity() -> Self {
+        Self::constant(T::infinity())
+    }
+
+    fn neg_infinity() -> Self {
+        Self::constant(T::neg_infinity())
+    }
+
+    fn neg_zero() -> Self {
+        Self::constant(T::neg_zero())
+    }
+
+    fn min_value() -> Self {
+        Self::constant(T::min_value())
+    }
+
+    fn min_positive_value() -> Self {
+        Self::constant(T::min_positive_value())
+    }
+
+    fn epsilon() -> Self {
+        Self::constant(T::epsilon())
+    }
+
+    fn max_value() -> Self {
+        Self::constant(T::max_value())
+    }
+
+    fn is_nan(self) -> bool {
+        self.re.is_nan()
+    }
+
+    fn is_infinite(self) -> bool {
+        self.re.is_infinite()
+    }
+
+    fn is_finite(self) -> bool {
+        self.re.is_finite()
+    }
+
+    fn is_normal(self) -> bool {
+        self.re.is_normal()
+    }
+
+    fn classify(self) -> FpCategory {
+        self.re.classify()
+    }
+
+    // Piecewise constant functions have zero derivative almost everywhere
+    fn floor(self) -> Self {
+        Self::constant(self.re.floor())
+    }
+
+    fn ceil(self) -> Self {
+        Self::constant(self.re.ceil())
+    }
+
+    fn round(self) -> Self {
+        Self::constant(self.re.round())
+    }
+
+    fn trunc(self) -> Self {
+        Self::constant(self.re.trunc())
+    }
+
+    fn fract(self) -> Self {
+        Self::new(self.re.fract(), self.eps)
+    }
+
+    fn abs(self) -> Self {
+        if self.re.is_sign_negative() {
+            -self
+        } else {
+            self
+        }
+    }
+
+    fn signum(self) -> Self {
+        Self::constant(self.re.signum())
+    }
+
+    fn is_sign_positive(self) -> bool {
+        self.re.is_sign_positive()
+    }
+
+    fn is_sign_negative(self) -> bool {
+        self.re.is_sign_negative()
+    }
+
+    fn copysign(self, sign: Self) -> Self {
+        if self.re.is_sign_negative() == sign.re.is_sign_negative() {
+            self
+        } else {
+            -self
+        }
+    }
+
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        self * a + b
+    }
+
+    fn recip(self) -> Self {
+        let r = self.re.recip();
+        self.chain(r, -r * r)
+    }
+
+    fn powi(self, n: i32) -> Self {
+        if n == 0 {
+            return Self::one();
+        }
+        let dn = T::from(n).unwrap();
+        self.chain(self.re.powi(n), dn * self.re.powi(n - 1))
+    }
+
+    // d(x^y) = y x^(y - 1) dx + x^y ln(x) dy, where the second term is left
+    // out for constant exponents so negative bases work
+    fn powf(self, n: Self) -> Self {
+        let p = self.re.powf(n.re);
+        let mut d = self.chain(p, n.re * self.re.powf(n.re - T::one()));
+        if !n.eps.is_zero() {
+            d.eps = d.eps + n.eps * p * self.re.ln();
+        }
+        d
+    }
+
+    fn sqrt(self) -> Self {
+        let s = self.re.sqrt();
+        self.chain(s, (s + s).recip())
+    }
+
+    fn exp(self) -> Self {
+        let e = self.re.exp();
+        self.chain(e, e)
+    }
+
+    fn exp2(self) -> Self {
+        let e = self.re.exp2();
+        self.chain(e, e * T::from(2.0).unwrap().ln())
+    }
+
+    fn ln(self) -> Self {
+        self.chain(self.re.ln(), self.re.recip())
+    }
+
+    fn log(self, base: Self) -> Self {
+        self.ln() / base.ln()
+    }
+
+    fn log2(self) -> Self {
+        let ln2 = T::from(2.0).unwrap().ln();
+        self.chain(self.re.log2(), (self.re * ln2).recip())
+    }
+
+    fn log10(self) -> Self {
+        let ln10 = T::from(10.0).unwrap().ln();
+        self.chain(self.re.log10(), (self.re * ln10).recip())
+    }
+
+    fn max(self, other: Self) -> Self {
+        if self.re.is_nan() || other.re > self.re {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn min(self, other: Self) -> Self {
+        if self.re.is_nan() || other.re < self.re {
+            other
+        } else {
+            self
+        }
+    }
+
+    fn abs_sub(self, other: Self) -> Self {
+        if self.re <= other.re {
+            Self::zero()
+        } else {
+            self - other
+        }
+    }
+
+    fn cbrt(self) -> Self {
+        let c = self.re.cbrt();
+        self.chain(c, (T::from(3.0).unwrap() * c * c).recip())
+    }
+
+    fn hypot(self, other: Self) -> Self {
+        let h = self.re.hypot(other.re);
+        Self::new(h, (self.re * self.eps + other.re * other.eps) / h)
+    }
+
+    fn sin(self) -> Self {
+        self.chain(self.re.sin(), self.re.cos())
+    }
+
+    fn cos(self) -> Self {
+        self.chain(self.re.cos(), -self.re.sin())
+    }
+
+    fn tan(self) -> Self {
+        let t = self.re.tan();
+        self.chain(t, T::one() + t * t)
+    }
+
+    fn asin(self) -> Self {
+        let d = (T::one() - self.re * self.re).sqrt().recip();
+        self.chain(self.re.asin(), d)
+    }
+
+    fn acos(self) -> Self {
+        let d = -(T::one() - self.re * self.re).sqrt().recip();
+        self.chain(self.re.acos(), d)
+    }
+
+    fn atan(self) -> Self {
+        let d = (T::one() + self.re * self.re).recip();
+        self.chain(self.re.atan(), d)
+    }
+
+    // atan(y / x) with self = y and other = x
+    fn atan2(self, other: Self) -> Self {
+        let (y, x) = (self.re, other.re);
+        let d = (x * self.eps - y * other.eps) / (x * x + y * y);
+        Self::new(y.atan2(x), d)
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        (self.sin(), self.cos())
+    }
+
+    fn exp_m1(self) -> Self {
+        self.chain(self.re.exp_m1(), self.re.exp())
+    }
+
+    fn ln_1p(self) -> Self {
+        self.chain(self.re.ln_1p(), (T::one() + self.re).recip())
+    }
+
+    fn sinh(self) -> Self {
+        self.chain(self.re.sinh(), self.re.cosh())
+    }
+
+    fn cosh(self) -> Self {
+        self.chain(self.re.cosh(), self.re.sinh())
+    }
+
+    fn tanh(self) -> Self {
+        let t = self.re.tanh();
+        self.chain(t, T::one() - t * t)
+    }
+
+    fn asinh(self) -> Self {
+        let d = (self.re * self.re + T::one()).sqrt().recip();
+        self.chain(self.re.asinh(), d)
+    }
+
+    fn acosh(self) -> Self {
+        let d = (self.re * self.re - T::one()).sqrt().recip();
+        self.chain(self.re.acosh(), d)
+    }
+
+    fn atanh(self) -> Self {
+        let d = (T::one() - self.re * self.re).recip();
+        self.chain(self.re.atanh(), d)
+    }
+
+    fn integer_decode(self) -> (u64, i16, i8) {
+        self.re.integer_decode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        cell_list, clusters, groups, lennard_jones,
+        lennard_jones_t::{self, GradWorkspace},
+        storage::{self, Aos, ParticleStorage, Soa},
+        triclinic::TriclinicBox,
+    };
+
+    fn jitter(rng: &mut ChaCha8Rng, r: &mut [[f64; 3]]) {
+        for x in r.iter_mut().flatten() {
+            *x += rng.gen_range(-0.1..0.1);
+        }
+    }
+
+    fn lattice(seed: u64, n: usize) -> Vec<[f64; 3]> {
+        let mut r = lennard_jones::setup_cubic_lattice(n, 1.1);
+        jitter(&mut ChaCha8Rng::seed_from_u64(seed), &mut r);
+        r
+    }
+
+    fn energy(r: &[[Dual<f64>; 3]]) -> Dual<f64> {
+        let one = Dual::constant(1.0);
+        lennard_jones::lennard_jones_naive(one, one, r)
+    }
+
+    // Largest difference relative to the largest reference component
+    fn max_err(g: &[[f64; 3]], g_ref: &[[f64; 3]]) -> f64 {
+        let scale = g_ref.iter().flatten().fold(0.0f64, |m, x| m.max(x.abs()));
+        g.iter()
+            .flatten()
+            .zip(g_ref.iter().flatten())
+            .fold(0.0f64, |m, (a, b)| m.max((a - b).abs()))
+            / scale
+    }
+
+    fn aos(g: &[Vec<f64>; 3]) -> Vec<[f64; 3]> {
+        (0..g[0].len())
+            .map(|i| [g[0][i], g[1][i], g[2][i]])
+            .collect()
+    }
+
+    #[test]
+    fn lennard_jones_kernels() {
+        let r = lattice(1, 5);
+        let len = r.len();
+        let [x, y, z] = lennard_jones_t::to_soa(&r);
+        let g_ref = gradient(&r, energy);
+
+        let mut g = vec![[0.0; 3]; len];
+        lennard_jones::lennard_jones_grad_naive(1.0, 1.0, &mut g, &r);
+        assert!(max_err(&g, &g_ref) < 1e-12);
+        lennard_jones::lennard_jones_grad::<8, _>(1.0, 1.0, &mut g, &r);
+        assert!(max_err(&g, &g_ref) < 1e-12);
+
+        let mut gs = [(); 3].map(|_| vec![0.0; len]);
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad_dim::<8, 3, _>(
+            1.0,
+            1.0,
+            [&x, &y, &z],
+            [gx, gy, gz],
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+
+        let mut ga = Aos::from_aos(&r);
+        storage::lennard_jones_grad::<8, 3, _, _>(
+            1.0,
+            1.0,
+            &Aos::from_aos(&r),
+            &mut ga,
+        );
+        assert!(max_err(&ga.0, &g_ref) < 1e-12);
+        let mut gb = Soa::<f64, 3>::from_aos(&r);
+        storage::lennard_jones_grad::<8, 3, _, _>(
+            1.0,
+            1.0,
+            &Soa::from_aos(&r),
+            &mut gb,
+        );
+        assert!(max_err(&aos(&gb.0), &g_ref) < 1e-12);
+
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad::<8, _>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad_tiled::<8, _>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+
+        let mut ws = GradWorkspace::new();
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad_par::<8, _>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz, &mut ws,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+    }
+
+    // Single precision positions, so the reference is taken at the rounded
+    // positions. 125 atoms leave a remainder of 5 for the scalar tail.
+    #[test]
+    fn mixed_precision() {
+        let r32: Vec<_> =
+            lattice(2, 5).iter().map(|r| r.map(|x| x as f32)).collect();
+        let r64: Vec<_> = r32.iter().map(|r| r.map(|x| x as f64)).collect();
+        let [x, y, z] = lennard_jones_t::to_soa(&r32);
+        let g_ref = gradient(&r64, energy);
+
+        let mut gs = [(); 3].map(|_| vec![0.0; r64.len()]);
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad_mixed::<8>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-5);
+
+        let mut ws = GradWorkspace::new();
+        let [gx, gy, gz] = &mut gs;
+        lennard_jones_t::lennard_jones_grad_par_mixed::<8>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz, &mut ws,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-5);
+    }
+
+    // Interaction between the two halves only
+    #[test]
+    fn groups() {
+        let r = lattice(3, 5);
+        let len = r.len();
+        let h = len / 2;
+        let [x, y, z] = lennard_jones_t::to_soa(&r);
+        let g_ref =
+            gradient(&r, |r| energy(r) - energy(&r[..h]) - energy(&r[h..]));
+
+        let mut ga = [(); 3].map(|_| vec![0.0; h]);
+        let mut gb = [(); 3].map(|_| vec![0.0; len - h]);
+        let [ax, ay, az] = &mut ga;
+        let [bx, by, bz] = &mut gb;
+        groups::lennard_jones_cross_grad::<8, _>(
+            1.0,
+            1.0,
+            [&x[..h], &y[..h], &z[..h]],
+            [&x[h..], &y[h..], &z[h..]],
+            [ax, ay, az],
+            [bx, by, bz],
+        );
+        let g = [aos(&ga), aos(&gb)].concat();
+        assert!(max_err(&g, &g_ref) < 1e-12);
+    }
+
+    // Batches of small clusters, the last one padded
+    #[test]
+    fn clusters() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let clusters: Vec<_> = (0..13)
+            .map(|_| {
+                let mut c = lennard_jones::setup_cubic_lattice(2, 1.1);
+                c.truncate(7);
+                jitter(&mut rng, &mut c);
+                c
+            })
+            .collect();
+        let soa: Vec<_> = clusters
+            .iter()
+            .map(|c| lennard_jones_t::to_soa(c))
+            .collect();
+        let cl = clusters::Clusters::<f64, 8>::from_soa(&soa);
+
+        let mut e = vec![0.0; clusters.len()];
+        for par in [false, true] {
+            let mut gc = clusters::Clusters::zeros(7, clusters.len());
+            if par {
+                clusters::lennard_jones_grad_clusters_par(
+                    1.0, 1.0, &cl, &mut e, &mut gc,
+                );
+            } else {
+                clusters::lennard_jones_grad_clusters(
+                    1.0, 1.0, &cl, &mut e, &mut gc,
+                );
+            }
+            for (c, r) in clusters.iter().enumerate() {
+                let g_ref = gradient(r, energy);
+                assert!(max_err(&aos(&gc.cluster(c)), &g_ref) < 1e-12);
+            }
+        }
+    }
+
+    // Energy with minimum image and a truncated potential
+    fn periodic<T: Float>(r_cut: T, bx: &TriclinicBox<T>, r: &[[T; 3]]) -> T {
+        let s2 = lennard_jones_t::sigma2(T::one());
+        let mut e = T::zero();
+        for (i, ri) in r.iter().enumerate() {
+            for rj in &r[..i] {
+                let d = bx.min_image([0, 1, 2].map(|q| ri[q] - rj[q]));
+                let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+                if r2 < r_cut * r_cut {
+                    let sr6 = (s2 / r2).powi(3);
+                    e = e + sr6 * sr6 - sr6;
+                }
+            }
+        }
+        e * T::from(4.0).unwrap()
+    }
+
+    // Periodic kernels in a sheared box
+    #[test]
+    fn periodic_kernels() {
+        let (m, r_cut) = (5, 2.0);
+        let bx = TriclinicBox::new([1.1 * m as f64; 3], [0.5, 0.3, -0.4]);
+        let mut r: Vec<_> = lennard_jones::setup_cubic_lattice(m, 1.0)
+            .into_iter()
+            .map(|s| bx.to_cart(s.map(|s| (s + 0.5) / m as f64)))
+            .collect();
+        jitter(&mut ChaCha8Rng::seed_from_u64(5), &mut r);
+        let [x, y, z] = lennard_jones_t::to_soa(&r);
+
+        let bxd = TriclinicBox::new(
+            bx.l.map(Dual::constant),
+            bx.tilt.map(Dual::constant),
+        );
+        let g_ref = gradient(&r, |r| periodic(Dual::constant(r_cut), &bxd, r));
+
+        let mut gs = [(); 3].map(|_| vec![0.0; r.len()]);
+        let [gx, gy, gz] = &mut gs;
+        crate::triclinic::lennard_jones_grad_triclinic::<8, _>(
+            1.0, 1.0, r_cut, &bx, &x, &y, &z, gx, gy, gz,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+
+        let mut cells = cell_list::CellList::new_triclinic(&bx, r_cut);
+        cells.build(&x, &y, &z);
+        let [gx, gy, gz] = &mut gs;
+        cell_list::lennard_jones_grad_cells::<8, _>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz, &cells,
+        );
+        assert!(max_err(&aos(&gs), &g_ref) < 1e-12);
+    }
+
+    // The dual derivative of the hand written gradient along v against the
+    // mixed second derivatives of the energy in hyper-dual numbers
+    #[test]
+    fn hessian_vector_product() {
+        let mut rng = ChaCha8Rng::seed_from_u64(6);
+        let r = lattice(6, 4);
+        let v: Vec<[f64; 3]> = (0..r.len())
+            .map(|_| [(); 3].map(|_| rng.gen_range(-1.0..1.0)))
+            .collect();
+
+        let one = Dual::constant(1.0);
+        let rv = seed(&r, &v);
+        let mut gv = vec![[Dual::constant(0.0); 3]; r.len()];
+        lennard_jones::lennard_jones_grad_naive(one, one, &mut gv, &rv);
+        let hv: Vec<_> = gv.iter().map(|g| g.map(|g| g.eps)).collect();
+
+        let one = HyperDual::constant(one);
+        let h_ref: Vec<_> =
+            gradient(&rv, |r| lennard_jones::lennard_jones_naive(one, one, r))
+                .iter()
+                .map(|g| g.map(|g| g.eps))
+                .collect();
+        assert!(max_err(&hv, &h_ref) < 1e-12);
+    }
+}
@@ -0,0 +1,863 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+use crate::{
+    cell_list::{CellList, NeighbourBuf},
+    lennard_jones_t::scalar_pairs,
+};
+
+// Classic Ewald summation of point charges in an orthogonal periodic box, in
+// units where the energy of two charges is q_i q_j / r. The total energy is
+//
+//   E = sum_{i<j} q_i q_j erfc(alpha r_ij) / r_ij                 (r < r_cut)
+//     + 2 pi / V sum_{k != 0} exp(-k^2 / (4 alpha^2)) / k^2 |S(k)|^2
+//     - alpha / sqrt(pi) sum_i q_i^2 - pi Q^2 / (2 V alpha^2)
+//
+// with S(k) = sum_j q_j exp(i k . r_j), the wave vectors k = 2 pi n / L
+// limited to |k| < k_cut and Q the net charge, whose term is the energy of a
+// neutralizing background.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ewald<T> {
+    pub box_l: [T; 3],
+    pub alpha: T,
+    pub r_cut: T,
+    pub k_cut: T,
+    pub n_max: [usize; 3],
+}
+
+impl<T: Float> Ewald<T> {
+    // Chooses alpha and k_cut such that exp(-(alpha r_cut)^2) and
+    // exp(-k_cut^2 / (4 alpha^2)) both equal the accuracy
+    pub fn new(box_l: [T; 3], r_cut: T, accuracy: T) -> Self {
+        let two = T::from(2.0).unwrap();
+        for l in box_l {
+            assert!(two * r_cut <= l, "r_cut must be at most half the box");
+        }
+
+        let s = (-accuracy.ln()).sqrt();
+        let alpha = s / r_cut;
+        let k_cut = two * alpha * s;
+
+        Self::with_params(box_l, alpha, r_cut, k_cut)
+    }
+
+    pub fn with_params(box_l: [T; 3], alpha: T, r_cut: T, k_cut: T) -> Self {
+        let tau = T::from(2.0 * PI).unwrap();
+        let n_max =
+            box_l.map(|l| (k_cut * l / tau).floor().to_usize().unwrap());
+
+        Self {
+            box_l,
+            alpha,
+            r_cut,
+            k_cut,
+            n_max,
+        }
+    }
+
+    pub fn volume(&self) -> T {
+        self.box_l[0] * self.box_l[1] * self.box_l[2]
+    }
+
+    // Self interaction and neutralizing background, independent of the
+    // positions
+    pub fn self_energy(&self, q: &[T]) -> T {
+        let pi = T::from(PI).unwrap();
+        let q2 = q.iter().fold(T::zero(), |acc, &q| acc + q * q);
+        let q_net = q.iter().fold(T::zero(), |acc, &q| acc + q);
+
+        -self.alpha / pi.sqrt() * q2
+            - pi * q_net * q_net
+                / (T::from(2.0).unwrap() * self.volume() * self.alpha.powi(2))
+    }
+}
+
+// exp(y) for -40 <= y <= 0 from a Taylor polynomial of exp(y / 256) squared
+// eight times
+fn exp_neg<const N: usize, T>(y: Simd<T, N>) -> Simd<T, N>
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>,
+{
+    let y = y / Simd::splat(T::from(256.0).unwrap());
+
+    let mut p = Simd::splat(T::one());
+    for k in (1..=10).rev() {
+        p = Simd::splat(T::one()) + y * p / Simd::splat(T::from(k).unwrap());
+    }
+    for _ in 0..8 {
+        p *= p;
+    }
+
+    p
+}
+
+// erfc(x) and exp(-x^2) for 0 <= x <= 6. Below x = 2 erfc = 1 - erf with the
+// series erf(x) = 2 / sqrt(pi) exp(-x^2) sum_n 2^n x^(2n+1) / (2n+1)!!, which
+// has no cancellation, and above it the continued fraction
+// erfc(x) = exp(-x^2) / sqrt(pi) / (x + 1/2 / (x + 1 / (x + 3/2 / (x + ...)))).
+// Both lanes are computed and the absolute error is around 1e-15.
+fn erfc_exp<const N: usize, T>(x: Simd<T, N>) -> (Simd<T, N>, Simd<T, N>)
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = Simd::splat(T::one());
+    let half = Simd::splat(T::from(0.5).unwrap());
+    let inv_sqrt_pi = Simd::splat(T::from(1.0 / PI.sqrt()).unwrap());
+
+    let x2 = x * x;
+    let ex2 = exp_neg(-x2);
+
+    let mut t = x;
+    let mut s = x;
+    for k in 1..30 {
+        t = t * x2 / Simd::splat(T::from(k as f64 + 0.5).unwrap());
+        s += t;
+    }
+    let series =
+        one - Simd::splat(T::from(2.0).unwrap()) * inv_sqrt_pi * ex2 * s;
+
+    let mut f = x;
+    for k in (1..=40).rev() {
+        f = x + half * Simd::splat(T::from(k).unwrap()) / f;
+    }
+    let frac = inv_sqrt_pi * ex2 / f;
+
+    let small = x.simd_lt(Simd::splat(T::from(2.0).unwrap()));
+    (small.select(series, frac), ex2)
+}
+
+fn min_image<const N: usize, T>(d: Simd<T, N>, l: T) -> Simd<T, N>
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + StdFloat,
+{
+    let l = Simd::splat(l);
+    d - (d / l).round() * l
+}
+
+// Pair energies and derivatives divided by the distance of the separations
+// d, zero beyond the cutoff
+fn real_term<const N: usize, T>(
+    ew: &Ewald<T>,
+    qq: Simd<T, N>,
+    d: [Simd<T, N>; 3],
+) -> (Simd<T, N>, Simd<T, N>)
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let zero = Simd::splat(T::zero());
+    let rc2 = Simd::splat(ew.r_cut * ew.r_cut);
+    let alpha = Simd::splat(ew.alpha);
+
+    let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+
+    // Lanes beyond the cutoff are evaluated at the cutoff and discarded
+    let inside = r2.simd_lt(rc2);
+    let r2 = inside.select(r2, rc2);
+
+    let r = r2.sqrt();
+    let (erfc, ex2) = erfc_exp(alpha * r);
+
+    let e = qq * erfc / r;
+    let two_alpha_sqrt_pi =
+        Simd::splat(T::from(2.0 / PI.sqrt()).unwrap() * ew.alpha);
+    let de = -(e + qq * two_alpha_sqrt_pi * ex2) / r2;
+
+    (inside.select(e, zero), inside.select(de, zero))
+}
+
+// Real space energy of particle j against the N particles in ri under the
+// minimum image convention, returning the pair energies, the derivatives
+// divided by the distance and the separations
+fn real_pair<const N: usize, T>(
+    ew: &Ewald<T>,
+    qi: Simd<T, N>,
+    ri: [Simd<T, N>; 3],
+    qj: T,
+    rj: [T; 3],
+) -> (Simd<T, N>, Simd<T, N>, [Simd<T, N>; 3])
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let d =
+        [0, 1, 2].map(|a| min_image(ri[a] - Simd::splat(rj[a]), ew.box_l[a]));
+    let (e, de) = real_term(ew, qi * Simd::splat(qj), d);
+
+    (e, de, d)
+}
+
+fn check_lengths<T>(q: &[T], r: [&[T]; 3], g: &Option<[&mut [T]; 3]>) {
+    for r in r {
+        assert_eq!(r.len(), q.len());
+    }
+    if let Some(g) = g {
+        for g in g {
+            assert_eq!(g.len(), q.len());
+        }
+    }
+}
+
+// Adds the real space gradient to g if given
+pub fn real_space<const N: usize, T>(
+    ew: &Ewald<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    mut g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    check_lengths(q, r, &g);
+
+    let zero = Simd::splat(T::zero());
+    let n = q.len();
+
+    let mut es = zero;
+    for i in 0..n / N {
+        let qi = Simd::from_slice(&q[N * i..]);
+        let ri = r.map(|r| Simd::from_slice(&r[N * i..]));
+
+        let mut gi = [zero; 3];
+        for j in 0..N * i {
+            let rj = [r[0][j], r[1][j], r[2][j]];
+            let (e, de, d) = real_pair(ew, qi, ri, q[j], rj);
+            es += e;
+
+            if let Some(g) = &mut g {
+                for a in 0..3 {
+                    let ga = de * d[a];
+                    gi[a] += ga;
+                    g[a][j] -= ga.reduce_sum();
+                }
+            }
+        }
+
+        if let Some(g) = &mut g {
+            for (g, gi) in g.iter_mut().zip(gi) {
+                for (g, gi) in
+                    g[N * i..N * (i + 1)].iter_mut().zip(gi.to_array())
+                {
+                    *g += gi;
+                }
+            }
+        }
+    }
+
+    // The remaining pairs one at a time in the first lane
+    let mut e = es.reduce_sum();
+    scalar_pairs::<N>(n, |i, j| {
+        let ri = [r[0][i], r[1][i], r[2][i]].map(Simd::splat);
+        let rj = [r[0][j], r[1][j], r[2][j]];
+        let (eij, de, d) = real_pair(ew, Simd::splat(q[i]), ri, q[j], rj);
+        e += eij[0];
+
+        if let Some(g) = &mut g {
+            for a in 0..3 {
+                g[a][i] += de[0] * d[a][0];
+                g[a][j] -= de[0] * d[a][0];
+            }
+        }
+    });
+
+    e
+}
+
+// Real space sum over the cells, which unlike real_space allows any r_cut up
+// to the width of the box. The positions must lie inside the box, as after
+// wrap_positions, and the cells must be built from them. Adds the gradient
+// to g if given.
+pub fn real_space_cells<const N: usize, T>(
+    ew: &Ewald<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    cells: &CellList<T>,
+    mut g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    check_lengths(q, r, &g);
+    assert_eq!(q.len(), cells.indices.len());
+
+    let [x, y, z] = r;
+    let zero = Simd::splat(T::zero());
+
+    let mut buf = NeighbourBuf::new();
+    let mut qs = Vec::new();
+
+    let mut e = T::zero();
+    let mut es = zero;
+    for c in 0..cells.n_cells_total() {
+        let ic = cells.cell(c);
+        if ic.is_empty() {
+            continue;
+        }
+
+        // The padding gets no charge
+        buf.gather::<N>(cells, c, x, y, z);
+        qs.clear();
+        qs.extend(buf.j.iter().map(|&j| q[j]));
+        qs.resize(buf.x.len(), T::zero());
+
+        for (k, &i) in ic.iter().enumerate() {
+            let qi = Simd::splat(q[i]);
+            let ri = [x[i], y[i], z[i]].map(Simd::splat);
+
+            // Pairs within the cell one at a time in the first lane
+            for &j in &ic[..k] {
+                let d = [0, 1, 2].map(|a| ri[a] - Simd::splat(r[a][j]));
+                let (eij, de) = real_term(ew, qi * Simd::splat(q[j]), d);
+                e += eij[0];
+
+                if let Some(g) = &mut g {
+                    for a in 0..3 {
+                        g[a][i] += de[0] * d[a][0];
+                        g[a][j] -= de[0] * d[a][0];
+                    }
+                }
+            }
+
+            let mut gi = [zero; 3];
+            for (o, js) in (0..buf.x.len()).step_by(N).zip(buf.j.chunks(N)) {
+                let rj =
+                    [&buf.x, &buf.y, &buf.z].map(|v| Simd::from_slice(&v[o..]));
+                let d = [0, 1, 2].map(|a| ri[a] - rj[a]);
+                let (eij, de) =
+                    real_term(ew, qi * Simd::from_slice(&qs[o..]), d);
+                es += eij;
+
+                if let Some(g) = &mut g {
+                    for a in 0..3 {
+                        let ga = de * d[a];
+                        gi[a] += ga;
+                        for (&j, ga) in js.iter().zip(ga.to_array()) {
+                            g[a][j] -= ga;
+                        }
+                    }
+                }
+            }
+
+            if let Some(g) = &mut g {
+                for a in 0..3 {
+                    g[a][i] += gi[a].reduce_sum();
+                }
+            }
+        }
+    }
+
+    e + es.reduce_sum()
+}
+
+// Powers exp(i m 2 pi r_a / L_a) for m = 0..=n_max[a] of every particle,
+// padded with zeros to whole chunks
+struct Phases<T> {
+    re: [Vec<Vec<T>>; 3],
+    im: [Vec<Vec<T>>; 3],
+}
+
+impl<T: Float> Phases<T> {
+    fn new<const N: usize>(ew: &Ewald<T>, r: [&[T]; 3]) -> Self {
+        let n_pad = (r[0].len() + N - 1) / N * N;
+        let tau = T::from(2.0 * PI).unwrap();
+
+        let mut re = [Vec::new(), Vec::new(), Vec::new()];
+        let mut im = [Vec::new(), Vec::new(), Vec::new()];
+        for a in 0..3 {
+            let (mut sin, mut cos): (Vec<_>, Vec<_>) = r[a]
+                .iter()
+                .map(|&x| (tau * x / ew.box_l[a]).sin_cos())
+                .unzip();
+            sin.resize(n_pad, T::zero());
+            cos.resize(n_pad, T::zero());
+
+            let mut pre = vec![T::one(); r[a].len()];
+            let mut pim = vec![T::zero(); r[a].len()];
+            pre.resize(n_pad, T::zero());
+            pim.resize(n_pad, T::zero());
+            for _ in 0..ew.n_max[a] {
+                let nre = (0..n_pad)
+                    .map(|j| pre[j] * cos[j] - pim[j] * sin[j])
+                    .collect();
+                let nim = (0..n_pad)
+                    .map(|j| pre[j] * sin[j] + pim[j] * cos[j])
+                    .collect();
+                re[a].push(std::mem::replace(&mut pre, nre));
+                im[a].push(std::mem::replace(&mut pim, nim));
+            }
+            re[a].push(pre);
+            im[a].push(pim);
+        }
+
+        Self { re, im }
+    }
+
+    // Phase of chunk c for the signed index m along axis a
+    fn get<const N: usize>(
+        &self,
+        a: usize,
+        m: isize,
+        c: usize,
+    ) -> (Simd<T, N>, Simd<T, N>)
+    where
+        T: SimdElement,
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Neg<Output = Simd<T, N>>,
+    {
+        let k = m.unsigned_abs();
+        let re = Simd::from_slice(&self.re[a][k][N * c..]);
+        let im = Simd::from_slice(&self.im[a][k][N * c..]);
+        if m < 0 {
+            (re, -im)
+        } else {
+            (re, im)
+        }
+    }
+}
+
+// Adds the reciprocal space gradient to g if given. Only half of the wave
+// vectors are visited since S(-k) is the complex conjugate of S(k).
+pub fn reciprocal<const N: usize, T>(
+    ew: &Ewald<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    mut g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    check_lengths(q, r, &g);
+
+    let n = q.len();
+    let n_chunks = (n + N - 1) / N;
+    let zero = Simd::splat(T::zero());
+
+    let mut qp = q.to_vec();
+    qp.resize(n_chunks * N, T::zero());
+
+    let ph = Phases::new::<N>(ew, r);
+    let tau = T::from(2.0 * PI).unwrap();
+    let pre = T::from(4.0 * PI).unwrap() / ew.volume();
+    let four_alpha2 = T::from(4.0).unwrap() * ew.alpha * ew.alpha;
+    let kc2 = ew.k_cut * ew.k_cut;
+
+    let [mx, my, mz] = ew.n_max.map(|m| m as isize);
+
+    let mut gp = [(); 3].map(|_| vec![T::zero(); n_chunks * N]);
+    let mut e = T::zero();
+    for nx in 0..=mx {
+        for ny in -my..=my {
+            for nz in -mz..=mz {
+                if nx == 0 && (ny < 0 || (ny == 0 && nz <= 0)) {
+                    continue;
+                }
+
+                let m = [nx, ny, nz];
+                let k = [0, 1, 2]
+                    .map(|a| tau * T::from(m[a]).unwrap() / ew.box_l[a]);
+                let k2 = k[0] * k[0] + k[1] * k[1] + k[2] * k[2];
+                if k2 >= kc2 {
+                    continue;
+                }
+                let a = (-k2 / four_alpha2).exp() / k2;
+
+                let phase = |c: usize| {
+                    let (xr, xi) = ph.get::<N>(0, nx, c);
+                    let (yr, yi) = ph.get::<N>(1, ny, c);
+                    let (zr, zi) = ph.get::<N>(2, nz, c);
+                    let (xyr, xyi) = (xr * yr - xi * yi, xr * yi + xi * yr);
+                    (xyr * zr - xyi * zi, xyr * zi + xyi * zr)
+                };
+
+                let mut s_re = zero;
+                let mut s_im = zero;
+                for c in 0..n_chunks {
+                    let qc = Simd::from_slice(&qp[N * c..]);
+                    let (er, ei) = phase(c);
+                    s_re += qc * er;
+                    s_im += qc * ei;
+                }
+                let (s_re, s_im) = (s_re.reduce_sum(), s_im.reduce_sum());
+
+                e += pre * a * (s_re * s_re + s_im * s_im);
+
+                // dE/dr_j = -2 pre a q_j k Im(S* exp(i k . r_j))
+                if g.is_some() {
+                    let c0 = T::from(-2.0).unwrap() * pre * a;
+                    let s_re = Simd::splat(s_re);
+                    let s_im = Simd::splat(s_im);
+                    for c in 0..n_chunks {
+                        let qc = Simd::from_slice(&qp[N * c..]);
+                        let (er, ei) = phase(c);
+                        let f = Simd::splat(c0) * qc * (s_re * ei - s_im * er);
+                        for a in 0..3 {
+                            let gc = &mut gp[a][N * c..N * (c + 1)];
+                            let ga =
+                                Simd::from_slice(gc) + Simd::splat(k[a]) * f;
+                            gc.copy_from_slice(ga.as_array());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(g) = &mut g {
+        for (g, gp) in g.iter_mut().zip(&gp) {
+            for (g, &gp) in g.iter_mut().zip(gp) {
+                *g += gp;
+            }
+        }
+    }
+
+    e
+}
+
+// Total Ewald energy. The gradient is overwritten if given.
+pub fn ewald<const N: usize, T>(
+    ew: &Ewald<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    match g {
+        Some(mut g) => {
+            for g in g.iter_mut() {
+                g.fill(T::zero());
+            }
+            let [gx, gy, gz] = g;
+            let e_real = real_space::<N, _>(
+                ew,
+                q,
+                r,
+                Some([&mut *gx, &mut *gy, &mut *gz]),
+            );
+            e_real
+                + reciprocal::<N, _>(ew, q, r, Some([gx, gy, gz]))
+                + ew.self_energy(q)
+        }
+        None => {
+            real_space::<N, _>(ew, q, r, None)
+                + reciprocal::<N, _>(ew, q, r, None)
+                + ew.self_energy(q)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        cell_list::{wrap_positions, CellList},
+        dual::{self, Dual},
+    };
+
+    // Rock salt with unit nearest neighbour distance, where the energy per
+    // ion pair is -M
+    #[test]
+    fn madelung() {
+        const MADELUNG: f64 = 1.747564594633182;
+
+        let n = 6;
+        let mut q = Vec::new();
+        let mut r = [Vec::new(), Vec::new(), Vec::new()];
+        for i in 0..n * n * n {
+            let c = [i / (n * n), i / n % n, i % n];
+            let even = c.iter().sum::<usize>() % 2 == 0;
+            q.push(if even { 1.0 } else { -1.0 });
+            for (r, c) in r.iter_mut().zip(c) {
+                r.push(c as f64);
+            }
+        }
+        let rs = [&r[0][..], &r[1], &r[2]];
+        let box_l = [n as f64; 3];
+
+        for r_cut in [n as f64 / 2.0, n as f64 / 3.0] {
+            let ew = Ewald::new(box_l, r_cut, 1e-12);
+            let m = -2.0 * ewald::<4, _>(&ew, &q, rs, None) / q.len() as f64;
+            assert!((m - MADELUNG).abs() < 1e-9, "r_cut {r_cut}: M = {m}");
+        }
+    }
+
+    // Random neutral configuration whose size is not a multiple of the lane
+    // count
+    fn random_ions() -> (Vec<f64>, [Vec<f64>; 3], Ewald<f64>) {
+        let mut rng = ChaCha8Rng::seed_from_u64(5);
+        let l = 6.0;
+        let n_ions = 30;
+        let q = (0..n_ions)
+            .map(|i| if i % 2 == 0 { 1.0 } else { -1.0 })
+            .collect();
+        let r = [(); 3]
+            .map(|_| (0..n_ions).map(|_| rng.gen::<f64>() * l).collect());
+
+        (q, r, Ewald::new([l; 3], l / 2.0, 1e-12))
+    }
+
+    #[test]
+    fn lanes_and_alpha() {
+        let (q, r, ew) = random_ions();
+        let rs = [&r[0][..], &r[1], &r[2]];
+        let ew2 = Ewald::new(ew.box_l, ew.box_l[0] / 3.0, 1e-12);
+
+        let e = ewald::<4, _>(&ew, &q, rs, None);
+        let e1 = ewald::<1, _>(&ew, &q, rs, None);
+        let e2 = ewald::<4, _>(&ew2, &q, rs, None);
+        assert!((e - e1).abs() < 1e-12 * e.abs().max(1.0));
+        assert!((e - e2).abs() < 1e-9 * e.abs().max(1.0));
+    }
+
+    // Central differences of the energy
+    #[test]
+    fn gradient() {
+        let (q, mut r, ew) = random_ions();
+        let n_ions = q.len();
+
+        let mut g = [(); 3].map(|_| vec![0.0; n_ions]);
+        {
+            let [gx, gy, gz] = &mut g;
+            let rs = [&r[0][..], &r[1], &r[2]];
+            ewald::<4, _>(&ew, &q, rs, Some([gx, gy, gz]));
+        }
+
+        let energy = |r: &[Vec<f64>; 3]| {
+            ewald::<4, _>(&ew, &q, [&r[0], &r[1], &r[2]], None)
+        };
+        let h = 1e-5;
+        for a in 0..3 {
+            for i in 0..n_ions {
+                let x = r[a][i];
+                r[a][i] = x + h;
+                let ep = energy(&r);
+                r[a][i] = x - h;
+                let em = energy(&r);
+                r[a][i] = x;
+
+                let fd = (ep - em) / (2.0 * h);
+                assert!((fd - g[a][i]).abs() < 1e-6, "{fd} vs {}", g[a][i]);
+            }
+        }
+    }
+
+    // The same series and continued fraction as erfc_exp, one value at a
+    // time, so that dual numbers carry the derivative through them
+    fn erfc<T: Float>(x: T) -> T {
+        let x2 = x * x;
+        let ex2 = (-x2).exp();
+        let inv_sqrt_pi = T::from(1.0 / PI.sqrt()).unwrap();
+
+        if x < T::from(2.0).unwrap() {
+            let mut t = x;
+            let mut s = x;
+            for k in 1..30 {
+                t = t * x2 / T::from(k as f64 + 0.5).unwrap();
+                s = s + t;
+            }
+            T::one() - T::from(2.0).unwrap() * inv_sqrt_pi * ex2 * s
+        } else {
+            let mut f = x;
+            for k in (1..=40).rev() {
+                f = x + T::from(0.5 * k as f64).unwrap() / f;
+            }
+            inv_sqrt_pi * ex2 / f
+        }
+    }
+
+    // Real space energy over every image within the cutoff, which also holds
+    // for r_cut above half the box
+    fn reference_real(
+        ew: &Ewald<f64>,
+        q: &[f64],
+        r: &[[Dual<f64>; 3]],
+    ) -> Dual<f64> {
+        let c = Dual::constant;
+        let mut e = c(0.0);
+        for (i, ri) in r.iter().enumerate() {
+            for (j, rj) in r.iter().enumerate() {
+                for k in 0..27 {
+                    let o = [k / 9, k / 3 % 3, k % 3];
+                    let d = [0, 1, 2].map(|a| {
+                        rj[a] - ri[a] + c((o[a] as f64 - 1.0) * ew.box_l[a])
+                    });
+                    let d = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                    if (i == j && k == 13) || d.re >= ew.r_cut {
+                        continue;
+                    }
+
+                    // Every pair is seen from both ends
+                    e += c(0.5 * q[i] * q[j]) * erfc(c(ew.alpha) * d) / d;
+                }
+            }
+        }
+        e
+    }
+
+    fn reference_reciprocal(
+        ew: &Ewald<f64>,
+        q: &[f64],
+        r: &[[Dual<f64>; 3]],
+    ) -> Dual<f64> {
+        let c = Dual::constant;
+        let [mx, my, mz] = ew.n_max.map(|m| m as isize);
+
+        let mut e = c(0.0);
+        for nx in -mx..=mx {
+            for ny in -my..=my {
+                for nz in -mz..=mz {
+                    let m = [nx, ny, nz];
+                    let k =
+                        [0, 1, 2].map(|a| 2.0 * PI * m[a] as f64 / ew.box_l[a]);
+                    let k2 = k[0] * k[0] + k[1] * k[1] + k[2] * k[2];
+                    if m == [0; 3] || k2 >= ew.k_cut * ew.k_cut {
+                        continue;
+                    }
+
+                    let mut s_re = c(0.0);
+                    let mut s_im = c(0.0);
+                    for (q, r) in q.iter().zip(r) {
+                        let kr =
+                            c(k[0]) * r[0] + c(k[1]) * r[1] + c(k[2]) * r[2];
+                        s_re += c(*q) * kr.cos();
+                        s_im += c(*q) * kr.sin();
+                    }
+
+                    let a = (-k2 / (4.0 * ew.alpha * ew.alpha)).exp() / k2;
+                    e += c(2.0 * PI / ew.volume() * a)
+                        * (s_re * s_re + s_im * s_im);
+                }
+            }
+        }
+        e
+    }
+
+    fn max_err(g: &[Vec<f64>; 3], g_ref: &[[f64; 3]]) -> f64 {
+        let scale = g_ref.iter().flatten().fold(0.0f64, |m, g| m.max(g.abs()));
+        g_ref
+            .iter()
+            .enumerate()
+            .flat_map(|(i, g_ref)| (0..3).map(move |a| (a, i, g_ref[a])))
+            .fold(0.0f64, |m, (a, i, g_ref)| m.max((g[a][i] - g_ref).abs()))
+            / scale
+    }
+
+    #[test]
+    fn dual_gradient() {
+        let (q, r, ew) = random_ions();
+        let ew = Ewald::new(ew.box_l, ew.box_l[0] / 2.0, 1e-8);
+        let n_ions = q.len();
+        let r_aos: Vec<_> =
+            (0..n_ions).map(|i| [r[0][i], r[1][i], r[2][i]]).collect();
+
+        let mut g = [(); 3].map(|_| vec![0.0; n_ions]);
+        let [gx, gy, gz] = &mut g;
+        let rs = [&r[0][..], &r[1], &r[2]];
+        ewald::<4, _>(&ew, &q, rs, Some([gx, gy, gz]));
+
+        let g_ref = dual::gradient(&r_aos, |r| {
+            reference_real(&ew, &q, r) + reference_reciprocal(&ew, &q, r)
+        });
+        assert!(max_err(&g, &g_ref) < 1e-10);
+    }
+
+    // The cell list real space with r_cut above half the box
+    #[test]
+    fn cells_dual_gradient() {
+        let (q, r, ew) = random_ions();
+        let l = ew.box_l[0];
+        let ew = Ewald::with_params(ew.box_l, 0.7, 0.9 * l, 1.0);
+        let n_ions = q.len();
+
+        let [mut x, mut y, mut z] = r;
+        wrap_positions(ew.box_l, &mut x, &mut y, &mut z);
+        let mut cells = CellList::new(ew.box_l, ew.r_cut);
+        cells.build(&x, &y, &z);
+
+        let mut g = [(); 3].map(|_| vec![0.0; n_ions]);
+        let [gx, gy, gz] = &mut g;
+        let rs = [&x[..], &y, &z];
+        real_space_cells::<4, _>(&ew, &q, rs, &cells, Some([gx, gy, gz]));
+
+        let r_aos: Vec<_> = (0..n_ions).map(|i| [x[i], y[i], z[i]]).collect();
+        let g_ref = dual::gradient(&r_aos, |r| reference_real(&ew, &q, r));
+        assert!(max_err(&g, &g_ref) < 1e-10);
+    }
+}
@@ -0,0 +1,460 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+// One body potentials acting on every particle. The walls are planes normal to
+// the given axis and act on both sides with the distance |r_axis - pos|, so a
+// slit pore is two walls. The sphere confines particles inside the radius with
+// a 9-3 potential in the distance to the surface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum External<T> {
+    // E = epsilon (2/15 (sigma/d)^9 - (sigma/d)^3)
+    Wall93 {
+        axis: usize,
+        pos: T,
+        epsilon: T,
+        sigma: T,
+    },
+    // Steele potential of a stack of planes with density rho and spacing
+    // delta, E = 2 pi epsilon rho sigma^2 delta (2/5 (sigma/d)^10 -
+    // (sigma/d)^4 - sigma^4 / (3 delta (d + 0.61 delta)^3))
+    Wall1043 {
+        axis: usize,
+        pos: T,
+        epsilon: T,
+        sigma: T,
+        rho: T,
+        delta: T,
+    },
+    // E = 1/2 sum_q k_q (r_q - center_q)^2
+    Harmonic {
+        center: [T; 3],
+        k: [T; 3],
+    },
+    // E = -f . r, which is only meaningful along non-periodic directions
+    Force {
+        f: [T; 3],
+    },
+    Sphere {
+        center: [T; 3],
+        radius: T,
+        epsilon: T,
+        sigma: T,
+    },
+}
+
+// Energy and derivative with respect to d of the 9-3 wall
+fn wall93<const N: usize, T: Float + SimdElement>(
+    epsilon: T,
+    sigma: T,
+    d: Simd<T, N>,
+) -> (Simd<T, N>, Simd<T, N>)
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>,
+{
+    let eps = Simd::splat(epsilon);
+
+    let s = Simd::splat(sigma) / d;
+    let s3 = s * s * s;
+    let s9 = s3 * s3 * s3;
+
+    let e = eps * (Simd::splat(T::from(2.0 / 15.0).unwrap()) * s9 - s3);
+    let de = eps / d
+        * (Simd::splat(T::from(3.0).unwrap()) * s3
+            - Simd::splat(T::from(6.0 / 5.0).unwrap()) * s9);
+
+    (e, de)
+}
+
+fn field<const N: usize, T: Float + SimdElement>(
+    f: &External<T>,
+    r: [Simd<T, N>; 3],
+) -> (Simd<T, N>, [Simd<T, N>; 3])
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let zero = Simd::splat(T::zero());
+    let half = Simd::splat(T::from(0.5).unwrap());
+
+    match *f {
+        External::Wall93 {
+            axis,
+            pos,
+            epsilon,
+            sigma,
+        } => {
+            let dr = r[axis] - Simd::splat(pos);
+            let (e, de) = wall93(epsilon, sigma, dr.abs());
+
+            let mut g = [zero; 3];
+            g[axis] = de * dr.signum();
+            (e, g)
+        }
+        External::Wall1043 {
+            axis,
+            pos,
+            epsilon,
+            sigma,
+            rho,
+            delta,
+        } => {
+            let dr = r[axis] - Simd::splat(pos);
+            let d = dr.abs();
+
+            let pre =
+                T::from(2.0 * PI).unwrap() * epsilon * rho * sigma.powi(2);
+            let pre = Simd::splat(pre * delta);
+            let delta = Simd::splat(delta);
+            let s4 = Simd::splat(sigma.powi(4));
+
+            let s = Simd::splat(sigma) / d;
+            let s2 = s * s;
+            let s4d = s2 * s2;
+            let s10 = s4d * s4d * s2;
+
+            let dd = d + Simd::splat(T::from(0.61).unwrap()) * delta;
+            let dd3 = dd * dd * dd;
+
+            let three = Simd::splat(T::from(3.0).unwrap());
+            let four = Simd::splat(T::from(4.0).unwrap());
+
+            let e = pre
+                * (Simd::splat(T::from(0.4).unwrap()) * s10
+                    - s4d
+                    - s4 / (three * delta * dd3));
+            let de = pre * (four * (s4d - s10) / d + s4 / (delta * dd3 * dd));
+
+            let mut g = [zero; 3];
+            g[axis] = de * dr.signum();
+            (e, g)
+        }
+        External::Harmonic { center, k } => {
+            let mut e = zero;
+            let mut g = [zero; 3];
+            for q in 0..3 {
+                let d = r[q] - Simd::splat(center[q]);
+                let kd = Simd::splat(k[q]) * d;
+                e += half * kd * d;
+                g[q] = kd;
+            }
+            (e, g)
+        }
+        External::Force { f } => {
+            let f = f.map(Simd::splat);
+            let e = -(f[0] * r[0] + f[1] * r[1] + f[2] * r[2]);
+            (e, f.map(|f| -f))
+        }
+        External::Sphere {
+            center,
+            radius,
+            epsilon,
+            sigma,
+        } => {
+            let dr = [0, 1, 2].map(|q| r[q] - Simd::splat(center[q]));
+            let rr = (dr[0] * dr[0] + dr[1] * dr[1] + dr[2] * dr[2]).sqrt();
+
+            let (e, de) = wall93(epsilon, sigma, Simd::splat(radius) - rr);
+
+            // The direction is undefined at the centre, where the gradient
+            // vanishes by symmetry
+            let s = rr.simd_gt(zero).select(-de / rr, zero);
+            (e, dr.map(|d| s * d))
+        }
+    }
+}
+
+fn chunk<const N: usize, T>(
+    fields: &[External<T>],
+    r: [Simd<T, N>; 3],
+) -> (Simd<T, N>, [Simd<T, N>; 3])
+where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let mut e = Simd::splat(T::zero());
+    let mut g = [e; 3];
+    for f in fields {
+        let (ef, gf) = field(f, r);
+        e += ef;
+        for (g, gf) in g.iter_mut().zip(gf) {
+            *g += gf;
+        }
+    }
+    (e, g)
+}
+
+// The remainder is padded with copies of the last particle so that it can go
+// through the same SIMD code, and only its real lanes are used
+fn pad<const N: usize, T: Float + SimdElement>(v: &[T]) -> Simd<T, N>
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let mut a = [*v.last().unwrap(); N];
+    a[..v.len()].copy_from_slice(v);
+    Simd::from_array(a)
+}
+
+pub fn external_energy<const N: usize, T>(
+    fields: &[External<T>],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    let (xcs, xr): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, yr): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, zr): (&[[_; N]], _) = z.as_chunks();
+
+    let mut es = Simd::splat(T::zero());
+    for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
+        let r = [Simd::from(*xc), Simd::from(*yc), Simd::from(*zc)];
+        es += chunk(fields, r).0;
+    }
+
+    let mut e = es.reduce_sum();
+    if !xr.is_empty() {
+        let (er, _) = chunk(fields, [pad(xr), pad(yr), pad(zr)]);
+        for &er in &er.to_array()[..xr.len()] {
+            e += er;
+        }
+    }
+
+    e
+}
+
+// Adds the external gradient to gx, gy and gz, so that it can be called after
+// a pair kernel
+pub fn external_grad<const N: usize, T>(
+    fields: &[External<T>],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    let (xcs, xr): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, yr): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, zr): (&[[_; N]], _) = z.as_chunks();
+
+    let (gxcs, gxr): (&mut [[_; N]], _) = gx.as_chunks_mut();
+    let (gycs, gyr): (&mut [[_; N]], _) = gy.as_chunks_mut();
+    let (gzcs, gzr): (&mut [[_; N]], _) = gz.as_chunks_mut();
+
+    let mut es = Simd::splat(T::zero());
+    for (((((xc, yc), zc), gxc), gyc), gzc) in xcs
+        .iter()
+        .zip(ycs)
+        .zip(zcs)
+        .zip(gxcs.iter_mut())
+        .zip(gycs.iter_mut())
+        .zip(gzcs.iter_mut())
+    {
+        let r = [Simd::from(*xc), Simd::from(*yc), Simd::from(*zc)];
+        let (e, [gxs, gys, gzs]) = chunk(fields, r);
+
+        es += e;
+
+        *gxc = *(Simd::from(*gxc) + gxs).as_array();
+        *gyc = *(Simd::from(*gyc) + gys).as_array();
+        *gzc = *(Simd::from(*gzc) + gzs).as_array();
+    }
+
+    let mut e = es.reduce_sum();
+    if !xr.is_empty() {
+        let (er, g) = chunk(fields, [pad(xr), pad(yr), pad(zr)]);
+        for &er in &er.to_array()[..xr.len()] {
+            e += er;
+        }
+
+        for (gr, g) in [gxr, gyr, gzr].into_iter().zip(g) {
+            for (gr, g) in gr.iter_mut().zip(g.to_array()) {
+                *gr += g;
+            }
+        }
+    }
+
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::dual::{gradient, Dual};
+
+    fn wall93(epsilon: f64, sigma: f64, d: Dual<f64>) -> Dual<f64> {
+        let s3 = (Dual::constant(sigma) / d).powi(3);
+        Dual::constant(epsilon) * (Dual::constant(2.0 / 15.0) * s3.powi(3) - s3)
+    }
+
+    // The potentials evaluated one particle at a time in dual numbers
+    fn reference(fields: &[External<f64>], r: &[[Dual<f64>; 3]]) -> Dual<f64> {
+        let c = Dual::constant;
+        let mut e = c(0.0);
+        for r in r {
+            for f in fields {
+                e += match *f {
+                    External::Wall93 {
+                        axis,
+                        pos,
+                        epsilon,
+                        sigma,
+                    } => wall93(epsilon, sigma, (r[axis] - c(pos)).abs()),
+                    External::Wall1043 {
+                        axis,
+                        pos,
+                        epsilon,
+                        sigma,
+                        rho,
+                        delta,
+                    } => {
+                        let d = (r[axis] - c(pos)).abs();
+                        let s = c(sigma) / d;
+                        let pre = 2.0 * PI * epsilon * rho * sigma.powi(2);
+                        c(pre * delta)
+                            * (c(0.4) * s.powi(10)
+                                - s.powi(4)
+                                - c(sigma.powi(4))
+                                    / (c(3.0 * delta)
+                                        * (d + c(0.61 * delta)).powi(3)))
+                    }
+                    External::Harmonic { center, k } => (0..3)
+                        .map(|q| c(0.5 * k[q]) * (r[q] - c(center[q])).powi(2))
+                        .sum(),
+                    External::Force { f } => {
+                        -(0..3).map(|q| c(f[q]) * r[q]).sum::<Dual<f64>>()
+                    }
+                    External::Sphere {
+                        center,
+                        radius,
+                        epsilon,
+                        sigma,
+                    } => {
+                        let rr = (0..3)
+                            .map(|q| (r[q] - c(center[q])).powi(2))
+                            .sum::<Dual<f64>>()
+                            .sqrt();
+                        wall93(epsilon, sigma, c(radius) - rr)
+                    }
+                };
+            }
+        }
+        e
+    }
+
+    // A slit pore inside a sphere, with a number of particles that leaves a
+    // remainder after the full chunks
+    #[test]
+    fn dual_gradient() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+        let r: Vec<[f64; 3]> = (0..29)
+            .map(|_| [(); 3].map(|_| rng.gen_range(1.0..4.0)))
+            .collect();
+        let [x, y, z] = crate::lennard_jones_t::to_soa(&r);
+
+        let fields = [
+            External::Wall93 {
+                axis: 2,
+                pos: 0.0,
+                epsilon: 1.0,
+                sigma: 1.0,
+            },
+            External::Wall1043 {
+                axis: 2,
+                pos: 5.0,
+                epsilon: 1.0,
+                sigma: 1.0,
+                rho: 1.0,
+                delta: 0.7,
+            },
+            External::Harmonic {
+                center: [2.5, 2.0, 3.0],
+                k: [0.5, 1.0, 0.0],
+            },
+            External::Force {
+                f: [0.1, -0.2, 0.3],
+            },
+            External::Sphere {
+                center: [2.5; 3],
+                radius: 4.0,
+                epsilon: 1.0,
+                sigma: 1.0,
+            },
+        ];
+
+        let mut g = [(); 3].map(|_| vec![0.0; r.len()]);
+        let [gx, gy, gz] = &mut g;
+        external_grad::<4, _>(&fields, &x, &y, &z, gx, gy, gz);
+
+        let g_ref = gradient(&r, |r| reference(&fields, r));
+        let scale = g_ref.iter().flatten().fold(0.0f64, |m, g| m.max(g.abs()));
+        for (i, g_ref) in g_ref.iter().enumerate() {
+            for q in 0..3 {
+                let err = (g[q][i] - g_ref[q]).abs() / scale;
+                assert!(err < 1e-12, "atom {i}, error {err:e}");
+            }
+        }
+    }
+}
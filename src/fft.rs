@@ -0,0 +1,195 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+    simd::{LaneCount, Simd, SimdElement, SupportedLaneCount},
+};
+
+use num_traits::Float;
+
+// Radix-2 FFTs of complex data stored as separate real and imaginary parts.
+// A 3D transform is three passes of 1D transforms, one along each axis, where
+// N lines at a time are gathered into the lanes of a buffer so the butterflies
+// are SIMD for every axis regardless of the stride. The forward transform is
+// X(m) = sum_k x(k) exp(-2 pi i m k / n) and the inverse uses the opposite
+// sign without normalization, so inverse(forward(x)) = n x.
+
+fn bit_reverse(i: usize, bits: u32) -> usize {
+    if bits == 0 {
+        0
+    } else {
+        i.reverse_bits() >> (usize::BITS - bits)
+    }
+}
+
+// cos and sin of 2 pi k / n for k < n / 2
+struct Twiddles<T> {
+    cos: Vec<T>,
+    sin: Vec<T>,
+}
+
+impl<T: Float> Twiddles<T> {
+    fn new(n: usize) -> Self {
+        let (sin, cos) = (0..n / 2)
+            .map(|k| {
+                let phi = 2.0 * PI * k as f64 / n as f64;
+                (T::from(phi.sin()).unwrap(), T::from(phi.cos()).unwrap())
+            })
+            .unzip();
+
+        Self { cos, sin }
+    }
+}
+
+// In place transform of N lines at once, element k of line l in lane l
+fn fft_lanes<const N: usize, T>(
+    re: &mut [Simd<T, N>],
+    im: &mut [Simd<T, N>],
+    tw: &Twiddles<T>,
+    inverse: bool,
+) where
+    T: Float + SimdElement,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>,
+{
+    let n = re.len();
+    let bits = n.trailing_zeros();
+
+    for i in 0..n {
+        let j = bit_reverse(i, bits);
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+
+    let mut len = 2;
+    while len <= n {
+        let step = n / len;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                let c = Simd::splat(tw.cos[k * step]);
+                let s = if inverse {
+                    Simd::splat(tw.sin[k * step])
+                } else {
+                    Simd::splat(-tw.sin[k * step])
+                };
+
+                let a = start + k;
+                let b = a + len / 2;
+                let br = re[b] * c - im[b] * s;
+                let bi = re[b] * s + im[b] * c;
+
+                re[b] = re[a] - br;
+                im[b] = im[a] - bi;
+                re[a] += br;
+                im[a] += bi;
+            }
+        }
+        len *= 2;
+    }
+}
+
+pub struct Fft3<T> {
+    dims: [usize; 3],
+    twiddles: [Twiddles<T>; 3],
+}
+
+impl<T: Float + SimdElement> Fft3<T> {
+    // Row major data with the last axis contiguous, every dimension a power
+    // of two
+    pub fn new(dims: [usize; 3]) -> Self {
+        for n in dims {
+            assert!(n.is_power_of_two(), "FFT sizes must be powers of two");
+        }
+
+        Self {
+            dims,
+            twiddles: dims.map(Twiddles::new),
+        }
+    }
+
+    pub fn dims(&self) -> [usize; 3] {
+        self.dims
+    }
+
+    pub fn len(&self) -> usize {
+        self.dims.iter().product()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn forward<const N: usize>(&self, re: &mut [T], im: &mut [T])
+    where
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Add<Output = Simd<T, N>>
+            + Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>,
+    {
+        self.transform::<N>(re, im, false);
+    }
+
+    pub fn inverse<const N: usize>(&self, re: &mut [T], im: &mut [T])
+    where
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Add<Output = Simd<T, N>>
+            + Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>,
+    {
+        self.transform::<N>(re, im, true);
+    }
+
+    fn transform<const N: usize>(
+        &self,
+        re: &mut [T],
+        im: &mut [T],
+        inverse: bool,
+    ) where
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Add<Output = Simd<T, N>>
+            + Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>,
+    {
+        assert_eq!(re.len(), self.len());
+        assert_eq!(im.len(), self.len());
+
+        let [n0, n1, n2] = self.dims;
+        let strides = [n1 * n2, n2, 1];
+
+        let axes = self.dims.into_iter().zip(strides).zip(&self.twiddles);
+        for ((n, stride), twiddles) in axes {
+            // Start of every line along the axis
+            let starts: Vec<_> = (0..n0 * n1 * n2)
+                .filter(|&i| (i / stride) % n == 0)
+                .collect();
+
+            let mut bre = vec![Simd::splat(T::zero()); n];
+            let mut bim = vec![Simd::splat(T::zero()); n];
+            for lines in starts.chunks(N) {
+                for k in 0..n {
+                    let mut lr = [T::zero(); N];
+                    let mut li = [T::zero(); N];
+                    for (l, &s) in lines.iter().enumerate() {
+                        lr[l] = re[s + k * stride];
+                        li[l] = im[s + k * stride];
+                    }
+                    bre[k] = Simd::from_array(lr);
+                    bim[k] = Simd::from_array(li);
+                }
+
+                fft_lanes(&mut bre, &mut bim, twiddles, inverse);
+
+                for k in 0..n {
+                    let (lr, li) = (bre[k].to_array(), bim[k].to_array());
+                    for (l, &s) in lines.iter().enumerate() {
+                        re[s + k * stride] = lr[l];
+                        im[s + k * stride] = li[l];
+                    }
+                }
+            }
+        }
+    }
+}
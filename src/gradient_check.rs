@@ -0,0 +1,188 @@
+use std::simd::SimdElement;
+
+use num_traits::Float;
+
+use crate::storage::ParticleStorage;
+
+// Largest deviation of a claimed gradient from the numerical derivative of
+// the energy. The relative error is scaled by the largest numerical component.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GradientError<T> {
+    pub atom: usize,
+    pub component: usize,
+    pub analytic: T,
+    pub numeric: T,
+    pub error: T,
+    pub relative: T,
+}
+
+// Compares grad with central differences of energy around r, Richardson
+// extrapolated from the steps h and 2h so the truncation error is O(h^4). The
+// differences are divided by the displacements actually made, (x + h) - x,
+// so rounding of the displaced coordinates does not bias them. Layouts other
+// than AoS and SoA work through ParticleStorage, and the energy function does
+// any conversion the kernel needs.
+pub fn check_gradient<T, P, const D: usize>(
+    r: &P,
+    grad: &P,
+    h: T,
+    mut energy: impl FnMut(&P) -> T,
+) -> GradientError<T>
+where
+    T: Float + SimdElement,
+    P: ParticleStorage<T, D> + Clone,
+{
+    assert_eq!(r.len(), grad.len());
+    assert!(!r.is_empty(), "there is no gradient to check");
+
+    let two = T::from(2.0).unwrap();
+    let mut derivative = |i: usize, q: usize, d: T| {
+        let mut v = [T::zero(); D];
+        let mut step = |d: T| {
+            v[q] = d;
+            let mut rd = r.clone();
+            rd.add(i, v);
+            (energy(&rd), rd.get(i)[q] - r.get(i)[q])
+        };
+        let (ep, dp) = step(d);
+        let (em, dm) = step(-d);
+        (ep - em) / (dp - dm)
+    };
+
+    let mut worst = GradientError {
+        atom: 0,
+        component: 0,
+        analytic: T::zero(),
+        numeric: T::zero(),
+        error: -T::one(),
+        relative: T::zero(),
+    };
+    let mut scale = T::zero();
+
+    for i in 0..r.len() {
+        let g = grad.get(i);
+        for (q, &analytic) in g.iter().enumerate() {
+            let d1 = derivative(i, q, h);
+            let d2 = derivative(i, q, two * h);
+            let numeric =
+                (T::from(4.0).unwrap() * d1 - d2) / T::from(3.0).unwrap();
+
+            scale = scale.max(numeric.abs());
+            let error = (analytic - numeric).abs();
+            if error > worst.error {
+                worst = GradientError {
+                    atom: i,
+                    component: q,
+                    analytic,
+                    numeric,
+                    error,
+                    relative: T::zero(),
+                };
+            }
+        }
+    }
+
+    worst.relative = worst.error / scale;
+    worst
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        lennard_jones,
+        lennard_jones_t::{self, GradWorkspace},
+        storage::{Aos, Soa},
+    };
+
+    fn energy(r: &Aos<f64, 3>) -> f64 {
+        lennard_jones::lennard_jones_naive(1.0, 1.0, &r.0)
+    }
+
+    fn energy_soa(r: &Soa<f64, 3>) -> f64 {
+        let [x, y, z] = &r.0;
+        lennard_jones_t::lennard_jones::<8, _>(1.0, 1.0, x, y, z)
+    }
+
+    fn lattice(seed: u64, n: usize) -> Vec<[f64; 3]> {
+        let mut rng = ChaCha8Rng::seed_from_u64(seed);
+        let mut r = lennard_jones::setup_cubic_lattice(n, 1.1);
+        for x in r.iter_mut().flatten() {
+            *x += rng.gen_range(-0.1..0.1);
+        }
+        r
+    }
+
+    // A step that is not a power of two, so the displaced coordinates are
+    // rounded
+    #[test]
+    fn finds_wrong_component() {
+        let r = Aos::from_aos(&lattice(6, 3));
+        let h = 1e-3;
+
+        let mut g = r.clone();
+        lennard_jones::lennard_jones_grad_naive(1.0, 1.0, &mut g.0, &r.0);
+        let err = check_gradient(&r, &g, h, energy);
+        assert!(err.relative < 1e-8, "{err:?}");
+
+        g.add(5, [0.0, 0.0, 1e-4]);
+        let err = check_gradient(&r, &g, h, energy);
+        assert_eq!((err.atom, err.component), (5, 2));
+        assert!((err.error - 1e-4).abs() < 1e-8);
+    }
+
+    // 125 atoms, so every kernel also has a remainder of 5 for N = 8
+    #[test]
+    fn lennard_jones_grad() {
+        let r = lattice(7, 5);
+        let ra = Aos::from_aos(&r);
+        let mut g = ra.clone();
+        lennard_jones::lennard_jones_grad::<8, _>(1.0, 1.0, &mut g.0, &r);
+        let err = check_gradient(&ra, &g, 2.0f64.powi(-10), energy);
+        assert!(err.relative < 1e-8, "{err:?}");
+    }
+
+    #[test]
+    fn lennard_jones_t_grad() {
+        let r = Soa::from_aos(&lattice(8, 5));
+        let mut g = r.clone();
+        let [x, y, z] = &r.0;
+        let [gx, gy, gz] = &mut g.0;
+        lennard_jones_t::lennard_jones_grad::<8, _>(
+            1.0, 1.0, x, y, z, gx, gy, gz,
+        );
+        let err = check_gradient(&r, &g, 2.0f64.powi(-10), energy_soa);
+        assert!(err.relative < 1e-8, "{err:?}");
+    }
+
+    #[test]
+    fn lennard_jones_grad_par() {
+        let r = Soa::from_aos(&lattice(9, 5));
+        let mut g = r.clone();
+        let [x, y, z] = &r.0;
+        let [gx, gy, gz] = &mut g.0;
+        lennard_jones_t::lennard_jones_grad_par::<8, _>(
+            1.0,
+            1.0,
+            x,
+            y,
+            z,
+            gx,
+            gy,
+            gz,
+            &mut GradWorkspace::new(),
+        );
+        let err = check_gradient(&r, &g, 2.0f64.powi(-10), energy_soa);
+        assert!(err.relative < 1e-8, "{err:?}");
+    }
+
+    #[test]
+    #[should_panic(expected = "there is no gradient to check")]
+    fn empty() {
+        let r = Aos::<f64, 3>::from_aos(&[]);
+        check_gradient(&r, &r.clone(), 1e-3, energy);
+    }
+}
@@ -0,0 +1,424 @@
+use std::{
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{LaneCount, Simd, SimdElement, SimdFloat, SupportedLaneCount},
+};
+
+use num_traits::Float;
+
+use crate::lennard_jones_t::sigma2;
+
+// Interaction between two groups of particles, every pair with one particle in
+// A and one in B counted once. For disjoint groups the energy of the union is
+// E(A) + E(B) + E(A, B). Passing the same slices for A and B gives the
+// all-pairs energy E(A) of A, with no self pairs.
+
+fn same_particles<T>(a: [&[T]; 3], b: [&[T]; 3]) -> bool {
+    a.iter().zip(&b).all(|(a, b)| std::ptr::eq(*a, *b))
+}
+
+// The chunks of B and the scalar range of B that particle i of A meets. For
+// A = B these are the particles before i, so every pair is taken once.
+fn partners<const N: usize>(
+    same: bool,
+    i: usize,
+    n_b: usize,
+) -> (usize, std::ops::Range<usize>) {
+    if same {
+        (i / N, N * (i / N)..i)
+    } else {
+        (n_b / N, N * (n_b / N)..n_b)
+    }
+}
+
+pub fn lennard_jones_cross<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    a: [&[T]; 3],
+    b: [&[T]; 3],
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    for q in 1..3 {
+        assert_eq!(a[q].len(), a[0].len());
+        assert_eq!(b[q].len(), b[0].len());
+    }
+
+    let same = same_particles(a, b);
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    let bcs = b.map(|b| b.as_chunks::<N>().0);
+
+    let mut es = Simd::splat(T::zero());
+    let mut e = T::zero();
+    for i in 0..a[0].len() {
+        let ri = a.map(|a| a[i]);
+        let ris = ri.map(Simd::splat);
+
+        let (n_c, js) = partners::<N>(same, i, b[0].len());
+
+        for c in 0..n_c {
+            let mut r2 = Simd::splat(T::zero());
+            for (bc, ri) in bcs.iter().zip(ris) {
+                let d = Simd::from(bc[c]) - ri;
+                r2 += d * d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+        }
+
+        for j in js {
+            let mut r2 = T::zero();
+            for (b, ri) in b.iter().zip(ri) {
+                let d = b[j] - ri;
+                r2 += d * d;
+            }
+            let sr6 = (s2 / r2).powi(3);
+
+            e += sr6 * sr6 - sr6;
+        }
+    }
+
+    (es.reduce_sum() + e) * T::from(4.0).unwrap() * e_b
+}
+
+// Overwrites ga and gb with the gradient of the cross energy with respect to
+// the particles of A and B. For A = B the gradient of particle i is split
+// between ga[i] and gb[i].
+pub fn lennard_jones_cross_grad<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    a: [&[T]; 3],
+    b: [&[T]; 3],
+    ga: [&mut [T]; 3],
+    gb: [&mut [T]; 3],
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    for q in 0..3 {
+        assert_eq!(a[q].len(), a[0].len());
+        assert_eq!(b[q].len(), b[0].len());
+        assert_eq!(ga[q].len(), a[0].len());
+        assert_eq!(gb[q].len(), b[0].len());
+    }
+
+    let same = same_particles(a, b);
+
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+    let e_b_s = Simd::splat(e_b);
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    let mut ga = ga;
+    let mut gb = gb;
+    for g in ga.iter_mut().chain(gb.iter_mut()) {
+        g.fill(T::zero());
+    }
+
+    let bcs = b.map(|b| b.as_chunks::<N>().0);
+
+    let mut es = Simd::splat(T::zero());
+    let mut e = T::zero();
+    for i in 0..a[0].len() {
+        let ri = a.map(|a| a[i]);
+        let ris = ri.map(Simd::splat);
+
+        let mut gi = [Simd::splat(T::zero()); 3];
+
+        let (n_c, js) = partners::<N>(same, i, b[0].len());
+
+        for c in 0..n_c {
+            let mut d = [Simd::splat(T::zero()); 3];
+            let mut r2 = Simd::splat(T::zero());
+            for ((d, bc), ri) in d.iter_mut().zip(&bcs).zip(ris) {
+                *d = Simd::from(bc[c]) - ri;
+                r2 += *d * *d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            for ((gi, gb), d) in gi.iter_mut().zip(gb.iter_mut()).zip(d) {
+                let gqs = gs * d;
+                *gi -= gqs;
+
+                let gbc = &mut gb[N * c..N * (c + 1)];
+                (Simd::from_slice(gbc) + gqs).copy_to_slice(gbc);
+            }
+        }
+
+        for (g, gi) in ga.iter_mut().zip(gi) {
+            g[i] += gi.reduce_sum();
+        }
+
+        for j in js {
+            let mut d = [T::zero(); 3];
+            let mut r2 = T::zero();
+            for ((d, b), ri) in d.iter_mut().zip(b).zip(ri) {
+                *d = b[j] - ri;
+                r2 += *d * *d;
+            }
+            let sr6 = (s2 / r2).powi(3);
+
+            e += sr6 * sr6 - sr6;
+
+            let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
+            for ((ga, gb), d) in ga.iter_mut().zip(gb.iter_mut()).zip(d) {
+                ga[i] -= gs * d;
+                gb[j] += gs * d;
+            }
+        }
+    }
+
+    (es.reduce_sum() + e) * T::from(4.0).unwrap() * e_b
+}
+
+pub fn mask_indices(mask: &[bool]) -> Vec<usize> {
+    mask.iter()
+        .enumerate()
+        .filter_map(|(i, &m)| m.then_some(i))
+        .collect()
+}
+
+fn pack<T: Copy>(r: [&[T]; 3], idx: &[usize]) -> [Vec<T>; 3] {
+    r.map(|r| idx.iter().map(|&i| r[i]).collect())
+}
+
+// E(A, B) for groups given as indices into r, or E(A) without b, adding the
+// gradient to g
+fn cross_indexed<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; 3],
+    a: &[usize],
+    b: Option<&[usize]>,
+    g: Option<&mut [&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let ra = pack(r, a);
+    let ra = [&ra[0][..], &ra[1], &ra[2]];
+
+    let rb = b.map(|b| pack(r, b));
+    let rb = match &rb {
+        Some(rb) => [&rb[0][..], &rb[1], &rb[2]],
+        None => ra,
+    };
+    let b = b.unwrap_or(a);
+
+    let g = match g {
+        Some(g) => g,
+        None => return lennard_jones_cross::<N, _>(r_eq, e_b, ra, rb),
+    };
+
+    let mut ga = [(); 3].map(|_| vec![T::zero(); a.len()]);
+    let mut gb = [(); 3].map(|_| vec![T::zero(); b.len()]);
+    let [gax, gay, gaz] = &mut ga;
+    let [gbx, gby, gbz] = &mut gb;
+    let e = lennard_jones_cross_grad::<N, _>(
+        r_eq,
+        e_b,
+        ra,
+        rb,
+        [gax, gay, gaz],
+        [gbx, gby, gbz],
+    );
+
+    for ((g, ga), gb) in g.iter_mut().zip(&ga).zip(&gb) {
+        for (&i, &ga) in a.iter().zip(ga) {
+            g[i] += ga;
+        }
+        for (&j, &gb) in b.iter().zip(gb) {
+            g[j] += gb;
+        }
+    }
+
+    e
+}
+
+// Groups given as indices into one set of particles, which may overlap. A pair
+// counts once if one of its particles is in A and the other in B, so A = B
+// gives the all-pairs energy of the group. The gradient is overwritten for
+// every particle, and is zero outside the groups.
+pub fn lennard_jones_groups<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; 3],
+    a: &[usize],
+    b: &[usize],
+    g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let mut in_a = vec![false; r[0].len()];
+    let mut in_b = vec![false; r[0].len()];
+    for (idx, mask) in [(a, &mut in_a), (b, &mut in_b)] {
+        for &i in idx {
+            assert!(!mask[i], "particle {i} is twice in the same group");
+            mask[i] = true;
+        }
+    }
+
+    // With S the particles in both groups, A = A' + S and B = B' + S, and
+    // E(A, B) = E(A', B) + E(S, B') + E(S)
+    let a_only: Vec<_> = a.iter().copied().filter(|&i| !in_b[i]).collect();
+    let b_only: Vec<_> = b.iter().copied().filter(|&j| !in_a[j]).collect();
+    let shared: Vec<_> = a.iter().copied().filter(|&i| in_b[i]).collect();
+
+    let mut g = g;
+    if let Some(g) = &mut g {
+        for g in g.iter_mut() {
+            g.fill(T::zero());
+        }
+    }
+
+    let mut e = T::zero();
+    for (a, b) in [
+        (&a_only[..], Some(b)),
+        (&shared, Some(&b_only[..])),
+        (&shared, None),
+    ] {
+        if a.is_empty() || b.is_some_and(|b| b.is_empty()) {
+            continue;
+        }
+        e += cross_indexed::<N, _>(r_eq, e_b, r, a, b, g.as_mut());
+    }
+
+    e
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lennard_jones_t::setup_cubic_lattice;
+
+    // Sum over unordered pairs of distinct particles with one in a and the
+    // other in b
+    fn naive(r: [&[f64]; 3], a: &[usize], b: &[usize]) -> f64 {
+        let s2 = sigma2(1.0);
+        let mut pairs = Vec::new();
+        for &i in a {
+            for &j in b {
+                if i != j {
+                    pairs.push((i.min(j), i.max(j)));
+                }
+            }
+        }
+        pairs.sort();
+        pairs.dedup();
+
+        pairs
+            .into_iter()
+            .map(|(i, j)| {
+                let r2: f64 = r.iter().map(|r| (r[i] - r[j]).powi(2)).sum();
+                let sr6 = (s2 / r2).powi(3);
+                4.0 * (sr6 * sr6 - sr6)
+            })
+            .sum()
+    }
+
+    fn lattice() -> [Vec<f64>; 3] {
+        let mut r = setup_cubic_lattice(4, 1.1);
+        for (k, x) in r.iter_mut().flatten().enumerate() {
+            *x += 0.05 * ((k * 7919 % 101) as f64 / 101.0 - 0.5);
+        }
+        r
+    }
+
+    #[test]
+    fn cross_with_itself() {
+        let r = lattice();
+        let r = [&r[0][..], &r[1], &r[2]];
+        let all: Vec<_> = (0..r[0].len()).collect();
+
+        let e = lennard_jones_cross::<8, _>(1.0, 1.0, r, r);
+        let e_ref = naive(r, &all, &all);
+        assert!((e - e_ref).abs() < 1e-12 * e_ref.abs());
+    }
+
+    #[test]
+    fn overlapping_groups() {
+        let r = lattice();
+        let r = [&r[0][..], &r[1], &r[2]];
+        let n = r[0].len();
+
+        let a: Vec<_> = (0..n).filter(|i| i % 3 != 0).collect();
+        let a_rev: Vec<_> = a.iter().rev().copied().collect();
+        let b: Vec<_> = (0..n).filter(|i| i % 2 == 0).collect();
+
+        for (a, b) in [(&a, &a_rev), (&a, &b), (&b, &a)] {
+            let mut g = [(); 3].map(|_| vec![0.0; n]);
+            let [gx, gy, gz] = &mut g;
+            let e = lennard_jones_groups::<8, _>(
+                1.0,
+                1.0,
+                r,
+                a,
+                b,
+                Some([gx, gy, gz]),
+            );
+            let e_ref = naive(r, a, b);
+            assert!((e - e_ref).abs() < 1e-12 * e_ref.abs());
+
+            // Central differences of the naive energy
+            let h = 1e-6;
+            for i in [0, 1, 5, n / 2, n - 1] {
+                for q in 0..3 {
+                    let mut rp = [r[0].to_vec(), r[1].to_vec(), r[2].to_vec()];
+                    rp[q][i] += h;
+                    let e_p = naive([&rp[0], &rp[1], &rp[2]], a, b);
+                    rp[q][i] -= 2.0 * h;
+                    let e_m = naive([&rp[0], &rp[1], &rp[2]], a, b);
+                    let g_ref = (e_p - e_m) / (2.0 * h);
+                    assert!((g[q][i] - g_ref).abs() < 1e-5);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,347 @@
+use std::simd::{
+    simd_swizzle, LaneCount, Mask, Simd, SimdElement, SupportedLaneCount,
+};
+
+// Conversion between interleaved AoS data with stride 3 (xyz) or 4 (xyzw) and
+// separate SoA arrays. S vectors of N lanes hold N particles. Stride 4 is two
+// rounds of the two vector deinterleave. Stride 3 blends the three vectors so
+// that every lane holds the right component, and then permutes the lanes of
+// each component into order.
+
+const BLOCK: usize = 1 << 12;
+
+// Lane l of vector k holds the flat element k N + l, which belongs to the
+// component (k N + l) % 3
+const fn blend3<const N: usize>(k: usize, c: usize) -> [bool; N] {
+    let mut m = [false; N];
+    let mut l = 0;
+    while l < N {
+        m[l] = (k * N + l) % 3 == c;
+        l += 1;
+    }
+    m
+}
+
+// After blending, particle m of component c is in lane (3 m + c) % N
+const fn gather3<const N: usize>(c: usize) -> [usize; N] {
+    let mut idx = [0; N];
+    let mut m = 0;
+    while m < N {
+        idx[m] = (3 * m + c) % N;
+        m += 1;
+    }
+    idx
+}
+
+const fn scatter3<const N: usize>(c: usize) -> [usize; N] {
+    let mut idx = [0; N];
+    let mut m = 0;
+    while m < N {
+        idx[(3 * m + c) % N] = m;
+        m += 1;
+    }
+    idx
+}
+
+#[inline(always)]
+fn blend<T: SimdElement, const N: usize>(
+    [a, b, c]: [Simd<T, N>; 3],
+    ma: [bool; N],
+    mb: [bool; N],
+) -> Simd<T, N>
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let ma = Mask::<T::Mask, N>::from_array(ma);
+    let mb = Mask::<T::Mask, N>::from_array(mb);
+    ma.select(a, mb.select(b, c))
+}
+
+#[inline(always)]
+pub fn deinterleave3_4<T: SimdElement>(v: [Simd<T, 4>; 3]) -> [Simd<T, 4>; 3] {
+    let x = blend(v, blend3::<4>(0, 0), blend3::<4>(1, 0));
+    let y = blend(v, blend3::<4>(0, 1), blend3::<4>(1, 1));
+    let z = blend(v, blend3::<4>(0, 2), blend3::<4>(1, 2));
+
+    [
+        simd_swizzle!(x, gather3::<4>(0)),
+        simd_swizzle!(y, gather3::<4>(1)),
+        simd_swizzle!(z, gather3::<4>(2)),
+    ]
+}
+
+#[inline(always)]
+pub fn interleave3_4<T: SimdElement>(v: [Simd<T, 4>; 3]) -> [Simd<T, 4>; 3] {
+    let r = [
+        simd_swizzle!(v[0], scatter3::<4>(0)),
+        simd_swizzle!(v[1], scatter3::<4>(1)),
+        simd_swizzle!(v[2], scatter3::<4>(2)),
+    ];
+
+    [
+        blend(r, blend3::<4>(0, 0), blend3::<4>(0, 1)),
+        blend(r, blend3::<4>(1, 0), blend3::<4>(1, 1)),
+        blend(r, blend3::<4>(2, 0), blend3::<4>(2, 1)),
+    ]
+}
+
+#[inline(always)]
+pub fn deinterleave3_8<T: SimdElement>(v: [Simd<T, 8>; 3]) -> [Simd<T, 8>; 3] {
+    let x = blend(v, blend3::<8>(0, 0), blend3::<8>(1, 0));
+    let y = blend(v, blend3::<8>(0, 1), blend3::<8>(1, 1));
+    let z = blend(v, blend3::<8>(0, 2), blend3::<8>(1, 2));
+
+    [
+        simd_swizzle!(x, gather3::<8>(0)),
+        simd_swizzle!(y, gather3::<8>(1)),
+        simd_swizzle!(z, gather3::<8>(2)),
+    ]
+}
+
+#[inline(always)]
+pub fn interleave3_8<T: SimdElement>(v: [Simd<T, 8>; 3]) -> [Simd<T, 8>; 3] {
+    let r = [
+        simd_swizzle!(v[0], scatter3::<8>(0)),
+        simd_swizzle!(v[1], scatter3::<8>(1)),
+        simd_swizzle!(v[2], scatter3::<8>(2)),
+    ];
+
+    [
+        blend(r, blend3::<8>(0, 0), blend3::<8>(0, 1)),
+        blend(r, blend3::<8>(1, 0), blend3::<8>(1, 1)),
+        blend(r, blend3::<8>(2, 0), blend3::<8>(2, 1)),
+    ]
+}
+
+#[inline(always)]
+pub fn deinterleave4<T: SimdElement, const N: usize>(
+    [a, b, c, d]: [Simd<T, N>; 4],
+) -> [Simd<T, N>; 4]
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let (xz0, yw0) = a.deinterleave(b);
+    let (xz1, yw1) = c.deinterleave(d);
+
+    let (x, z) = xz0.deinterleave(xz1);
+    let (y, w) = yw0.deinterleave(yw1);
+
+    [x, y, z, w]
+}
+
+#[inline(always)]
+pub fn interleave4<T: SimdElement, const N: usize>(
+    [x, y, z, w]: [Simd<T, N>; 4],
+) -> [Simd<T, N>; 4]
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let (xz0, xz1) = x.interleave(z);
+    let (yw0, yw1) = y.interleave(w);
+
+    let (a, b) = xz0.interleave(yw0);
+    let (c, d) = xz1.interleave(yw1);
+
+    [a, b, c, d]
+}
+
+fn deinterleave_with<T: SimdElement, const S: usize, const N: usize>(
+    src: &[T],
+    mut dst: [&mut [T]; S],
+    f: impl Fn([Simd<T, N>; S]) -> [Simd<T, N>; S],
+) where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let n = src.len() / S;
+    assert_eq!(src.len(), S * n);
+    for d in &dst {
+        assert_eq!(d.len(), n);
+    }
+
+    let chunks = src.chunks_exact(S * N);
+    let rest = chunks.remainder();
+
+    for (i, c) in chunks.enumerate() {
+        let mut v = [Simd::splat(c[0]); S];
+        for (q, v) in v.iter_mut().enumerate() {
+            *v = Simd::from_slice(&c[q * N..]);
+        }
+
+        for (d, v) in dst.iter_mut().zip(f(v)) {
+            d[i * N..(i + 1) * N].copy_from_slice(v.as_array());
+        }
+    }
+
+    let n0 = n - rest.len() / S;
+    for (i, r) in rest.chunks_exact(S).enumerate() {
+        for (d, &r) in dst.iter_mut().zip(r) {
+            d[n0 + i] = r;
+        }
+    }
+}
+
+fn interleave_with<T: SimdElement, const S: usize, const N: usize>(
+    src: [&[T]; S],
+    dst: &mut [T],
+    f: impl Fn([Simd<T, N>; S]) -> [Simd<T, N>; S],
+) where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let n = dst.len() / S;
+    assert_eq!(dst.len(), S * n);
+    for s in &src {
+        assert_eq!(s.len(), n);
+    }
+
+    let mut chunks = dst.chunks_exact_mut(S * N);
+
+    for (i, c) in (&mut chunks).enumerate() {
+        let v = src.map(|s| Simd::from_slice(&s[i * N..]));
+
+        for (q, v) in f(v).into_iter().enumerate() {
+            c[q * N..(q + 1) * N].copy_from_slice(v.as_array());
+        }
+    }
+
+    let rest = chunks.into_remainder();
+    let n0 = n - rest.len() / S;
+    for (i, r) in rest.chunks_exact_mut(S).enumerate() {
+        for (r, s) in r.iter_mut().zip(src) {
+            *r = s[n0 + i];
+        }
+    }
+}
+
+// Blocks of BLOCK particles are converted in parallel
+fn deinterleave_par_with<T, const S: usize, const N: usize>(
+    src: &[T],
+    dst: [&mut [T]; S],
+    f: impl Fn([Simd<T, N>; S]) -> [Simd<T, N>; S] + Sync,
+) where
+    T: SimdElement + Send + Sync,
+    LaneCount<N>: SupportedLaneCount,
+{
+    use rayon::prelude::*;
+
+    let n = src.len() / S;
+    assert_eq!(src.len(), S * n);
+
+    let mut blocks: Vec<Vec<&mut [T]>> = Vec::new();
+    for d in dst {
+        assert_eq!(d.len(), n);
+        for (b, d) in d.chunks_mut(BLOCK).enumerate() {
+            if b == blocks.len() {
+                blocks.push(Vec::with_capacity(S));
+            }
+            blocks[b].push(d);
+        }
+    }
+
+    blocks
+        .into_par_iter()
+        .zip_eq(src.par_chunks(S * BLOCK))
+        .for_each(|(dst, src)| {
+            let dst = dst.try_into().unwrap_or_else(|_| unreachable!());
+            deinterleave_with(src, dst, &f);
+        });
+}
+
+fn interleave_par_with<T, const S: usize, const N: usize>(
+    src: [&[T]; S],
+    dst: &mut [T],
+    f: impl Fn([Simd<T, N>; S]) -> [Simd<T, N>; S] + Sync,
+) where
+    T: SimdElement + Send + Sync,
+    LaneCount<N>: SupportedLaneCount,
+{
+    use rayon::prelude::*;
+
+    let n = dst.len() / S;
+    assert_eq!(dst.len(), S * n);
+    for s in &src {
+        assert_eq!(s.len(), n);
+    }
+
+    dst.par_chunks_mut(S * BLOCK)
+        .enumerate()
+        .for_each(|(b, dst)| {
+            let i0 = b * BLOCK;
+            let src = src.map(|s| &s[i0..i0 + dst.len() / S]);
+            interleave_with(src, dst, &f);
+        });
+}
+
+pub fn deinterleave3_f32(src: &[f32], dst: [&mut [f32]; 3]) {
+    deinterleave_with(src, dst, deinterleave3_8)
+}
+
+pub fn deinterleave3_f64(src: &[f64], dst: [&mut [f64]; 3]) {
+    deinterleave_with(src, dst, deinterleave3_4)
+}
+
+pub fn interleave3_f32(src: [&[f32]; 3], dst: &mut [f32]) {
+    interleave_with(src, dst, interleave3_8)
+}
+
+pub fn interleave3_f64(src: [&[f64]; 3], dst: &mut [f64]) {
+    interleave_with(src, dst, interleave3_4)
+}
+
+pub fn deinterleave4_f32(src: &[f32], dst: [&mut [f32]; 4]) {
+    deinterleave_with(src, dst, deinterleave4::<_, 8>)
+}
+
+pub fn deinterleave4_f64(src: &[f64], dst: [&mut [f64]; 4]) {
+    deinterleave_with(src, dst, deinterleave4::<_, 4>)
+}
+
+pub fn interleave4_f32(src: [&[f32]; 4], dst: &mut [f32]) {
+    interleave_with(src, dst, interleave4::<_, 8>)
+}
+
+pub fn interleave4_f64(src: [&[f64]; 4], dst: &mut [f64]) {
+    interleave_with(src, dst, interleave4::<_, 4>)
+}
+
+pub fn deinterleave3_f32_par(src: &[f32], dst: [&mut [f32]; 3]) {
+    deinterleave_par_with(src, dst, deinterleave3_8)
+}
+
+pub fn deinterleave3_f64_par(src: &[f64], dst: [&mut [f64]; 3]) {
+    deinterleave_par_with(src, dst, deinterleave3_4)
+}
+
+pub fn interleave3_f32_par(src: [&[f32]; 3], dst: &mut [f32]) {
+    interleave_par_with(src, dst, interleave3_8)
+}
+
+pub fn interleave3_f64_par(src: [&[f64]; 3], dst: &mut [f64]) {
+    interleave_par_with(src, dst, interleave3_4)
+}
+
+pub fn deinterleave4_f32_par(src: &[f32], dst: [&mut [f32]; 4]) {
+    deinterleave_par_with(src, dst, deinterleave4::<_, 8>)
+}
+
+pub fn deinterleave4_f64_par(src: &[f64], dst: [&mut [f64]; 4]) {
+    deinterleave_par_with(src, dst, deinterleave4::<_, 4>)
+}
+
+pub fn interleave4_f32_par(src: [&[f32]; 4], dst: &mut [f32]) {
+    interleave_par_with(src, dst, interleave4::<_, 8>)
+}
+
+pub fn interleave4_f64_par(src: [&[f64]; 4], dst: &mut [f64]) {
+    interleave_par_with(src, dst, interleave4::<_, 4>)
+}
+
+// Views a slice of points as the flat interleaved array
+pub fn flatten<T, const S: usize>(r: &[[T; S]]) -> &[T] {
+    unsafe { std::slice::from_raw_parts(r.as_ptr() as *const T, r.len() * S) }
+}
+
+pub fn flatten_mut<T, const S: usize>(r: &mut [[T; S]]) -> &mut [T] {
+    unsafe {
+        std::slice::from_raw_parts_mut(r.as_mut_ptr() as *mut T, r.len() * S)
+    }
+}
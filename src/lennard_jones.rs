@@ -7,31 +7,50 @@ use std::{
 use num_traits::Float;
 use thread_local::ThreadLocal;
 
+use crate::accumulator::{Acc, AccValue, Plain, Summation};
+
 pub fn setup_cubic_lattice<T: Float + Sum + AddAssign>(
     n: usize,
     r: T,
 ) -> Vec<[T; 3]> {
-    let mut rs = Vec::new();
-
-    for i in 0..n {
-        for j in 0..n {
-            for k in 0..n {
-                rs.push([
-                    T::from(i).unwrap() * r,
-                    T::from(j).unwrap() * r,
-                    T::from(k).unwrap() * r,
-                ]);
+    setup_lattice(n, r)
+}
+
+// Square or cubic lattice in D dimensions, with the first coordinate varying
+// slowest
+pub fn setup_lattice<const D: usize, T: Float>(n: usize, r: T) -> Vec<[T; D]> {
+    (0..n.pow(D as u32))
+        .map(|i| {
+            let mut k = i;
+            let mut ri = [T::zero(); D];
+            for x in ri.iter_mut().rev() {
+                *x = T::from(k % n).unwrap() * r;
+                k /= n;
             }
-        }
-    }
+            ri
+        })
+        .collect()
+}
 
-    rs
+pub fn lennard_jones_naive<
+    const D: usize,
+    T: Float + Sum + AddAssign + AccValue,
+>(
+    r_eq: T,
+    e_b: T,
+    r: &[[T; D]],
+) -> T {
+    lennard_jones_naive_acc::<Plain, D, _>(r_eq, e_b, r)
 }
 
-pub fn lennard_jones_naive<T: Float + Sum + AddAssign>(
+pub fn lennard_jones_naive_acc<
+    S: Summation,
+    const D: usize,
+    T: Float + Sum + AddAssign + AccValue,
+>(
     r_eq: T,
     e_b: T,
-    r: &[[T; 3]],
+    r: &[[T; D]],
 ) -> T {
     let one = T::one();
     let two = one + one;
@@ -40,7 +59,7 @@ pub fn lennard_jones_naive<T: Float + Sum + AddAssign>(
 
     let s2 = two.powf(-one / three) * r_eq.powi(2);
 
-    let mut e = T::zero();
+    let mut e = Acc::<S, T>::new();
     for (i, ri) in r.iter().enumerate() {
         for rj in r.iter().take(i) {
             let r2: T = ri.iter().zip(rj).map(|(x, y)| (*x - *y).powi(2)).sum();
@@ -48,14 +67,26 @@ pub fn lennard_jones_naive<T: Float + Sum + AddAssign>(
             let sr6 = sr2.powi(3);
             let sr12 = sr6.powi(2);
 
-            e += sr12 - sr6;
+            e.add(sr12 - sr6);
         }
     }
 
-    e * four * e_b
+    e.value() * four * e_b
 }
 
-pub fn lennard_jones<const N: usize, T: Float + Sum + AddAssign>(
+pub fn lennard_jones<const N: usize, T: Float + Sum + AddAssign + AccValue>(
+    r_eq: T,
+    e_b: T,
+    r: &[[T; 3]],
+) -> T {
+    lennard_jones_acc::<N, Plain, _>(r_eq, e_b, r)
+}
+
+pub fn lennard_jones_acc<
+    const N: usize,
+    S: Summation,
+    T: Float + Sum + AddAssign + AccValue,
+>(
     r_eq: T,
     e_b: T,
     r: &[[T; 3]],
@@ -67,7 +98,7 @@ pub fn lennard_jones<const N: usize, T: Float + Sum + AddAssign>(
 
     let s2 = two.powf(-one / three) * r_eq.powi(2);
 
-    let mut es = [T::zero(); N];
+    let mut es = [Acc::<S, T>::new(); N];
     for (i, ri) in r.iter().enumerate() {
         let (rcs, rr): (&[[_; N]], _) = r[0..i].as_chunks();
 
@@ -78,7 +109,7 @@ pub fn lennard_jones<const N: usize, T: Float + Sum + AddAssign>(
                 let sr2 = s2 / r2;
                 let sr6 = sr2.powi(3);
                 let sr12 = sr6.powi(2);
-                es[j] += sr12 - sr6;
+                es[j].add(sr12 - sr6);
             }
         }
 
@@ -88,16 +119,33 @@ pub fn lennard_jones<const N: usize, T: Float + Sum + AddAssign>(
             let sr6 = sr2.powi(3);
             let sr12 = sr6.powi(2);
 
-            es[j] += sr12 - sr6;
+            es[j].add(sr12 - sr6);
         }
     }
 
-    es.into_iter().sum::<T>() * four * e_b
+    let mut e = Acc::<S, T>::new();
+    for es in es {
+        e.merge(es);
+    }
+
+    e.value() * four * e_b
 }
 
 pub fn lennard_jones_par<
     const N: usize,
-    T: Float + Sum + AddAssign + Send + Sync,
+    T: Float + Sum + AddAssign + AccValue + Send + Sync,
+>(
+    r_eq: T,
+    e_b: T,
+    r: &[[T; 3]],
+) -> T {
+    lennard_jones_par_acc::<N, Plain, _>(r_eq, e_b, r)
+}
+
+pub fn lennard_jones_par_acc<
+    const N: usize,
+    S: Summation,
+    T: Float + Sum + AddAssign + AccValue + Send + Sync,
 >(
     r_eq: T,
     e_b: T,
@@ -116,7 +164,7 @@ pub fn lennard_jones_par<
     r.into_par_iter().enumerate().for_each(|(i, ri)| {
         let mut es = unsafe {
             tl_es
-                .get_or(|| RefCell::new(Some([T::zero(); N])))
+                .get_or(|| RefCell::new(Some([Acc::<S, T>::new(); N])))
                 .take()
                 .unwrap_unchecked()
         };
@@ -130,7 +178,7 @@ pub fn lennard_jones_par<
                 let sr2 = s2 / r2;
                 let sr6 = sr2.powi(3);
                 let sr12 = sr6.powi(2);
-                es[j] += sr12 - sr6;
+                es[j].add(sr12 - sr6);
             }
         }
 
@@ -140,25 +188,31 @@ pub fn lennard_jones_par<
             let sr6 = sr2.powi(3);
             let sr12 = sr6.powi(2);
 
-            es[j] += sr12 - sr6;
+            es[j].add(sr12 - sr6);
         }
 
         unsafe { tl_es.get().unwrap_unchecked() }.swap(&RefCell::new(Some(es)));
     });
 
-    tl_es
+    let mut e = Acc::<S, T>::new();
+    for es in tl_es
         .into_iter()
         .flat_map(|es| unsafe { es.into_inner().unwrap_unchecked() })
-        .sum::<T>()
-        * four
-        * e_b
+    {
+        e.merge(es);
+    }
+
+    e.value() * four * e_b
 }
 
-pub fn lennard_jones_grad_naive<T: Float + Sum + AddAssign + SubAssign>(
+pub fn lennard_jones_grad_naive<
+    const D: usize,
+    T: Float + Sum + AddAssign + SubAssign,
+>(
     r_eq: T,
     e_b: T,
-    g: &mut [[T; 3]],
-    r: &[[T; 3]],
+    g: &mut [[T; D]],
+    r: &[[T; D]],
 ) {
     assert_eq!(r.len(), g.len());
 
@@ -171,7 +225,7 @@ pub fn lennard_jones_grad_naive<T: Float + Sum + AddAssign + SubAssign>(
     let s2 = two.powf(-one / three) * r_eq.powi(2);
 
     for gc in g.iter_mut() {
-        *gc = [T::zero(); 3];
+        *gc = [T::zero(); D];
     }
 
     for (i, ri) in r.iter().enumerate() {
@@ -180,7 +234,7 @@ pub fn lennard_jones_grad_naive<T: Float + Sum + AddAssign + SubAssign>(
             let a = s2 / r2;
             let a3 = a.powi(3);
             let s = -twentyfour * e_b * a3 / r2 * (two * a3 - one);
-            for q in 0..3 {
+            for q in 0..D {
                 let gq = (rj[q] - ri[q]) * s;
                 g[i][q] -= gq;
                 g[j][q] += gq;
@@ -251,14 +305,16 @@ pub fn lennard_jones_grad<
             }
         }
 
-        // for (rj, buf) in rr.iter().zip(&mut bufs) {
-        //     let r2: T = ri.iter().zip(rj).map(|(x, y)| (*x - *y).powi(2)).sum();
-        //     let a = s2 / r2;
-        //     let a3 = a.powi(3);
-        //     let s = -twentyfour * e_b * a3 / r2 * (two * a3 - one);
-        //     for (b, (&ra, &rb)) in buf.iter_mut().zip(ri.iter().zip(rj)) {
-        //         *b = (rb - ra) * s;
-        //     }
-        // }
+        for (j, rj) in r.iter().enumerate().take(i).skip(rcs.len() * N) {
+            let r2: T = ri.iter().zip(rj).map(|(x, y)| (*x - *y).powi(2)).sum();
+            let a = s2 / r2;
+            let a3 = a.powi(3);
+            let s = -twentyfour * e_b * a3 / r2 * (two * a3 - one);
+            for q in 0..3 {
+                let gq = (rj[q] - ri[q]) * s;
+                g[i][q] -= gq;
+                g[j][q] += gq;
+            }
+        }
     }
 }
@@ -1,12 +1,15 @@
 use std::{
-    cell::RefCell,
     iter::Sum,
     ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
     simd::{LaneCount, Simd, SimdElement, SimdFloat, SupportedLaneCount},
 };
 
 use num_traits::Float;
-use thread_local::ThreadLocal;
+
+use crate::{
+    accumulator::{Acc, AccValue, Plain, Summation},
+    lennard_jones,
+};
 
 pub fn setup_cubic_lattice<T: Float>(n: usize, r: T) -> [Vec<T>; 3] {
     let mut xs = Vec::with_capacity(n.pow(3));
@@ -26,73 +29,60 @@ pub fn setup_cubic_lattice<T: Float>(n: usize, r: T) -> [Vec<T>; 3] {
     [xs, ys, zs]
 }
 
-fn lennard_jones_chunk<
-    const N: usize,
-    T: Float + SimdElement + Sum + AddAssign,
->(
-    s2: T,
-    x: &[T; N],
-    y: &[T; N],
-    z: &[T; N],
-) -> T
-where
-    LaneCount<N>: SupportedLaneCount,
-    Simd<T, N>: Add<Output = Simd<T, N>>
-        + Sub<Output = Simd<T, N>>
-        + Mul<Output = Simd<T, N>>
-        + Div<Output = Simd<T, N>>,
-{
-    let xi = Simd::from(*x);
-    let yi = Simd::from(*y);
-    let zi = Simd::from(*z);
+// Square or cubic lattice in D dimensions in the same ordering as
+// setup_cubic_lattice
+pub fn setup_lattice<const D: usize, T: Float>(n: usize, r: T) -> [Vec<T>; D] {
+    to_soa(&lennard_jones::setup_lattice(n, r))
+}
 
-    let mut e = T::zero();
-    for (i, ((xj, yj), zj)) in x.iter().zip(y).zip(z).enumerate() {
-        let xj = Simd::splat(*xj);
-        let yj = Simd::splat(*yj);
-        let zj = Simd::splat(*zj);
+pub fn to_soa<const D: usize, T: Copy>(r: &[[T; D]]) -> [Vec<T>; D] {
+    let mut rs = [(); D].map(|_| Vec::with_capacity(r.len()));
+    for ri in r {
+        for (v, &x) in rs.iter_mut().zip(ri) {
+            v.push(x);
+        }
+    }
+    rs
+}
 
-        let dx = xj - xi;
-        let dy = yj - yi;
-        let dz = zj - zi;
+pub fn to_aos<const D: usize, T: Float>(r: [&[T]; D]) -> Vec<[T; D]> {
+    (0..r[0].len()).map(|i| r.map(|r| r[i])).collect()
+}
 
-        let r2 = dx * dx + dy * dy + dz * dz;
-        let s2s = Simd::splat(s2);
-        let sr2 = s2s / r2;
-        let sr6 = sr2 * sr2 * sr2;
-        let sr12 = sr6 * sr6;
+pub(crate) fn sigma2<T: Float>(r_eq: T) -> T {
+    T::from(2.0)
+        .unwrap()
+        .powf(-T::one() / T::from(3.0).unwrap())
+        * r_eq.powi(2)
+}
 
-        let es = sr12 - sr6;
-        e += es.as_array().iter().cloned().take(i).sum::<T>();
+fn check_lengths<const D: usize, T>(r: [&[T]; D]) -> usize {
+    let n = r[0].len();
+    for r in r {
+        assert_eq!(r.len(), n);
     }
-    e
+    n
 }
 
-fn lennard_jones_rest<T: Float + AddAssign>(
-    s2: T,
-    x: &[T],
-    y: &[T],
-    z: &[T],
-) -> T {
-    let mut e = T::zero();
-    for (i, ((xi, yi), zi)) in x.iter().zip(y).zip(z).enumerate() {
-        for ((xj, yj), zj) in x.iter().zip(y).zip(z).take(i) {
-            let dx = *xj - *xi;
-            let dy = *yj - *yi;
-            let dz = *zj - *zi;
-
-            let r2 = dx * dx + dy * dy + dz * dz;
-            let sr2 = s2 / r2;
-            let sr6 = sr2 * sr2 * sr2;
-            let sr12 = sr6 * sr6;
-
-            e += sr12 - sr6;
+// Pairs within the same SIMD chunk and all pairs involving the remainder are
+// done in scalar code, calling f(i, j) with i > j
+pub(crate) fn scalar_pairs<const N: usize>(
+    n: usize,
+    mut f: impl FnMut(usize, usize),
+) {
+    let n_chunked = n / N * N;
+    for i in 0..n {
+        let j0 = if i < n_chunked { i / N * N } else { 0 };
+        for j in j0..i {
+            f(i, j);
         }
     }
-    e
 }
 
-pub fn lennard_jones<const N: usize, T: Float + SimdElement + Sum + AddAssign>(
+pub fn lennard_jones<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + AccValue,
+>(
     r_eq: T,
     e_b: T,
     x: &[T],
@@ -105,24 +95,42 @@ where
         + Sub<Output = Simd<T, N>>
         + Mul<Output = Simd<T, N>>
         + Div<Output = Simd<T, N>>
-        + SimdFloat<Scalar = T>,
+        + SimdFloat<Scalar = T>
+        + AccValue,
 {
-    let one = T::one();
-    let two = T::from(2.0).unwrap();
-    let three = T::from(3.0).unwrap();
-    let four = T::from(4.0).unwrap();
-
-    assert_eq!(x.len(), y.len());
-    assert_eq!(x.len(), z.len());
+    lennard_jones_acc::<N, Plain, _>(r_eq, e_b, x, y, z)
+}
 
-    let (xcs, xr): (&[[_; N]], _) = x.as_chunks();
-    let (ycs, yr): (&[[_; N]], _) = y.as_chunks();
-    let (zcs, zr): (&[[_; N]], _) = z.as_chunks();
+pub fn lennard_jones_acc<
+    const N: usize,
+    S: Summation,
+    T: Float + SimdElement + Sum + AddAssign + AccValue,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    let n = check_lengths([x, y, z]);
 
-    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2 = sigma2(r_eq);
     let s2s = Simd::splat(s2);
 
-    let mut es = Simd::splat(T::zero());
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
+
+    let mut es = Acc::<S, Simd<T, N>>::new();
     for (i, ((xc, yc), zc)) in xcs.iter().zip(ycs).zip(zcs).enumerate() {
         let xi = Simd::from(*xc);
         let yi = Simd::from(*yc);
@@ -142,36 +150,107 @@ where
             let sr6 = sr2 * sr2 * sr2;
             let sr12 = sr6 * sr6;
 
-            es += sr12 - sr6;
+            es.add(sr12 - sr6);
         }
     }
 
-    let mut e = es.reduce_sum();
+    let mut e = es.reduce();
 
-    for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
-        e += lennard_jones_chunk(s2, xc, yc, zc);
-    }
+    scalar_pairs::<N>(n, |i, j| {
+        let dx = x[j] - x[i];
+        let dy = y[j] - y[i];
+        let dz = z[j] - z[i];
 
-    e += lennard_jones_rest(s2, xr, yr, zr);
+        let r2 = dx * dx + dy * dy + dz * dz;
+        let sr6 = (s2 / r2).powi(3);
+
+        e.add(sr6 * sr6 - sr6);
+    });
 
-    e * four * e_b
+    e.value() * T::from(4.0).unwrap() * e_b
 }
 
-unsafe fn get_const_slice<'a, const N: usize, T>(
-    v: &'a [T],
-    i: usize,
-) -> &'a [T; N] {
-    v.as_chunks_unchecked().get_unchecked(i)
+// The _dim kernels are generic over the dimension D, for 2D monolayers as well
+// as 3D bulk. Coordinates are passed as [&[T]; D] and gradients as
+// [&mut [T]; D].
+pub fn lennard_jones_dim<const N: usize, const D: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; D],
+) -> T
+where
+    T: Float + SimdElement + AccValue,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    lennard_jones_dim_acc::<N, Plain, D, _>(r_eq, e_b, r)
 }
 
-unsafe fn get_const_slice_mut<'a, const N: usize, T>(
-    v: &'a mut [T],
-    i: usize,
-) -> &'a mut [T; N] {
-    v.as_chunks_unchecked_mut().get_unchecked_mut(i)
+pub fn lennard_jones_dim_acc<const N: usize, S: Summation, const D: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; D],
+) -> T
+where
+    T: Float + SimdElement + AccValue,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    let n = check_lengths(r);
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    let rcs = r.map(|r| r.as_chunks::<N>().0);
+
+    let mut es = Acc::<S, Simd<T, N>>::new();
+    for i in 0..n / N {
+        let ri = rcs.map(|rc| Simd::from(rc[i]));
+
+        for j in 0..N * i {
+            let mut r2 = Simd::splat(T::zero());
+            for (r, ri) in r.iter().zip(ri) {
+                let d = Simd::splat(r[j]) - ri;
+                r2 += d * d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es.add(sr12 - sr6);
+        }
+    }
+
+    let mut e = es.reduce();
+
+    scalar_pairs::<N>(n, |i, j| {
+        let mut r2 = T::zero();
+        for r in r {
+            let d = r[j] - r[i];
+            r2 = r2 + d * d;
+        }
+        let sr6 = (s2 / r2).powi(3);
+
+        e.add(sr6 * sr6 - sr6);
+    });
+
+    e.value() * T::from(4.0).unwrap() * e_b
 }
 
-fn lennard_jones_grad_rest<T: Float + AddAssign + SubAssign>(
+fn lennard_jones_grad_rest<
+    S: Summation,
+    T: Float + AddAssign + SubAssign + AccValue,
+>(
     s2: T,
     e_b: T,
     x: &[T],
@@ -180,16 +259,17 @@ fn lennard_jones_grad_rest<T: Float + AddAssign + SubAssign>(
     gx: &mut [T],
     gy: &mut [T],
     gz: &mut [T],
-) -> T {
+    start: usize,
+) -> Acc<S, T> {
     let one = T::one();
     let two = T::from(2.0).unwrap();
     let twentyfour = T::from(24.0).unwrap();
 
-    let mut e = T::zero();
-    for (i, ((xi, yi), zi)) in x.iter().zip(y).zip(z).enumerate() {
-        let mut gxi = gx[i];
-        let mut gyi = gy[i];
-        let mut gzi = gz[i];
+    let mut e = Acc::new();
+    for (i, ((xi, yi), zi)) in x.iter().zip(y).zip(z).enumerate().skip(start) {
+        let mut gxi = T::zero();
+        let mut gyi = T::zero();
+        let mut gzi = T::zero();
 
         for (j, ((xj, yj), zj)) in x.iter().zip(y).zip(z).enumerate().take(i) {
             let dx = *xj - *xi;
@@ -201,7 +281,7 @@ fn lennard_jones_grad_rest<T: Float + AddAssign + SubAssign>(
             let sr6 = sr2 * sr2 * sr2;
             let sr12 = sr6 * sr6;
 
-            e += sr12 - sr6;
+            e.add(sr12 - sr6);
 
             let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
 
@@ -227,7 +307,35 @@ fn lennard_jones_grad_rest<T: Float + AddAssign + SubAssign>(
 
 pub fn lennard_jones_grad<
     const N: usize,
-    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign + AccValue,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    lennard_jones_grad_acc::<N, Plain, _>(r_eq, e_b, x, y, z, gx, gy, gz)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_acc<
+    const N: usize,
+    S: Summation,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign + AccValue,
 >(
     r_eq: T,
     e_b: T,
@@ -245,12 +353,11 @@ where
         + Mul<Output = Simd<T, N>>
         + Div<Output = Simd<T, N>>
         + Neg<Output = Simd<T, N>>
-        + SimdFloat<Scalar = T>,
+        + SimdFloat<Scalar = T>
+        + AccValue,
 {
     let one = T::one();
     let two = T::from(2.0).unwrap();
-    let three = T::from(3.0).unwrap();
-    let four = T::from(4.0).unwrap();
     let twentyfour = T::from(24.0).unwrap();
 
     let one_s = Simd::splat(one);
@@ -270,37 +377,43 @@ where
     gy.fill(T::zero());
     gz.fill(T::zero());
 
-    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2 = sigma2(r_eq);
     let s2s = Simd::splat(s2);
 
-    let (xcs, xr): (&[[_; N]], _) = x.as_chunks();
-    let (ycs, yr): (&[[_; N]], _) = y.as_chunks();
-    let (zcs, zr): (&[[_; N]], _) = z.as_chunks();
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
 
-    let mut e = T::zero();
-    let mut es = Simd::splat(T::zero());
+    let mut e = Acc::<S, T>::new();
+    let mut es = Acc::<S, Simd<T, N>>::new();
     for (i, ((xc, yc), zc)) in xcs.iter().zip(ycs).zip(zcs).enumerate() {
-        e += lennard_jones_grad_rest(
-            s2,
-            e_b,
-            xc,
-            yc,
-            zc,
-            unsafe { get_const_slice_mut::<N, _>(gx, i) },
-            unsafe { get_const_slice_mut::<N, _>(gy, i) },
-            unsafe { get_const_slice_mut::<N, _>(gz, i) },
-        );
+        let (gx_j, gx_i) = gx.split_at_mut(N * i);
+        let (gy_j, gy_i) = gy.split_at_mut(N * i);
+        let (gz_j, gz_i) = gz.split_at_mut(N * i);
+
+        let gxc: &mut [T; N] = (&mut gx_i[..N]).try_into().unwrap();
+        let gyc: &mut [T; N] = (&mut gy_i[..N]).try_into().unwrap();
+        let gzc: &mut [T; N] = (&mut gz_i[..N]).try_into().unwrap();
+
+        e.merge(lennard_jones_grad_rest(
+            s2, e_b, xc, yc, zc, gxc, gyc, gzc, 0,
+        ));
 
         let xi = Simd::from(*xc);
         let yi = Simd::from(*yc);
         let zi = Simd::from(*zc);
 
-        let mut gxi = Simd::from(*unsafe { get_const_slice(gx, i) });
-        let mut gyi = Simd::from(*unsafe { get_const_slice(gy, i) });
-        let mut gzi = Simd::from(*unsafe { get_const_slice(gz, i) });
-
-        for (j, ((xj, yj), zj)) in
-            x.iter().zip(y).zip(z).enumerate().take(N * i)
+        let mut gxi = Simd::from(*gxc);
+        let mut gyi = Simd::from(*gyc);
+        let mut gzi = Simd::from(*gzc);
+
+        for (((((xj, yj), zj), gxj), gyj), gzj) in x
+            .iter()
+            .zip(y)
+            .zip(z)
+            .zip(gx_j.iter_mut())
+            .zip(gy_j.iter_mut())
+            .zip(gz_j.iter_mut())
         {
             let xj = Simd::splat(*xj);
             let yj = Simd::splat(*yj);
@@ -315,7 +428,7 @@ where
             let sr6 = sr2 * sr2 * sr2;
             let sr12 = sr6 * sr6;
 
-            es += sr12 - sr6;
+            es.add(sr12 - sr6);
 
             let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
 
@@ -327,36 +440,161 @@ where
             gyi -= gys;
             gzi -= gzs;
 
-            gx[j] += gxs.reduce_sum();
-            gy[j] += gys.reduce_sum();
-            gz[j] += gzs.reduce_sum();
+            *gxj += gxs.reduce_sum();
+            *gyj += gys.reduce_sum();
+            *gzj += gzs.reduce_sum();
         }
 
-        *unsafe { get_const_slice_mut(gx, i) } = *gxi.as_array();
-        *unsafe { get_const_slice_mut(gy, i) } = *gyi.as_array();
-        *unsafe { get_const_slice_mut(gz, i) } = *gzi.as_array();
+        *gxc = *gxi.as_array();
+        *gyc = *gyi.as_array();
+        *gzc = *gzi.as_array();
     }
 
-    e += es.reduce_sum();
+    e.merge(es.reduce());
 
-    e += lennard_jones_grad_rest(
+    e.merge(lennard_jones_grad_rest(
         s2,
         e_b,
-        xr,
-        yr,
-        zr,
-        gx.as_chunks_mut::<N>().1,
-        gy.as_chunks_mut::<N>().1,
-        gz.as_chunks_mut::<N>().1,
-    );
+        x,
+        y,
+        z,
+        gx,
+        gy,
+        gz,
+        N * xcs.len(),
+    ));
+
+    e.value() * T::from(4.0).unwrap() * e_b
+}
+
+pub fn lennard_jones_grad_dim<const N: usize, const D: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; D],
+    g: [&mut [T]; D],
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign + AccValue,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    lennard_jones_grad_dim_acc::<N, Plain, D, _>(r_eq, e_b, r, g)
+}
+
+pub fn lennard_jones_grad_dim_acc<
+    const N: usize,
+    S: Summation,
+    const D: usize,
+    T,
+>(
+    r_eq: T,
+    e_b: T,
+    r: [&[T]; D],
+    mut g: [&mut [T]; D],
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign + AccValue,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    let n = check_lengths(r);
+    for g in &g {
+        assert_eq!(g.len(), n);
+    }
+
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+    let e_b_s = Simd::splat(e_b);
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    for g in g.iter_mut() {
+        g.fill(T::zero());
+    }
+
+    let rcs = r.map(|r| r.as_chunks::<N>().0);
+
+    let mut es = Acc::<S, Simd<T, N>>::new();
+    for i in 0..n / N {
+        let ri = rcs.map(|rc| Simd::from(rc[i]));
+
+        let mut gi = [Simd::splat(T::zero()); D];
+
+        for j in 0..N * i {
+            let mut d = [Simd::splat(T::zero()); D];
+            let mut r2 = Simd::splat(T::zero());
+            for ((d, r), ri) in d.iter_mut().zip(r).zip(ri) {
+                *d = Simd::splat(r[j]) - ri;
+                r2 += *d * *d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es.add(sr12 - sr6);
 
-    e * four * e_b
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            for ((g, gi), d) in g.iter_mut().zip(&mut gi).zip(d) {
+                let gqs = gs * d;
+                *gi -= gqs;
+                g[j] += gqs.reduce_sum();
+            }
+        }
+
+        for (g, gi) in g.iter_mut().zip(gi) {
+            let gc = &mut g[N * i..N * (i + 1)];
+            for (g, gi) in gc.iter_mut().zip(gi.to_array()) {
+                *g += gi;
+            }
+        }
+    }
+
+    let mut e = es.reduce();
+
+    scalar_pairs::<N>(n, |i, j| {
+        let mut d = [T::zero(); D];
+        let mut r2 = T::zero();
+        for (d, r) in d.iter_mut().zip(r) {
+            *d = r[j] - r[i];
+            r2 += *d * *d;
+        }
+        let sr6 = (s2 / r2).powi(3);
+
+        e.add(sr6 * sr6 - sr6);
+
+        let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
+        for (g, d) in g.iter_mut().zip(d) {
+            g[i] -= gs * d;
+            g[j] += gs * d;
+        }
+    });
+
+    e.value() * T::from(4.0).unwrap() * e_b
 }
 
-pub fn lennard_jones_grad_par<
-    'a,
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_tiled<
     const N: usize,
-    T: Float + SimdElement + Sum + AddAssign + SubAssign + Send + Sync,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign + AccValue,
 >(
     r_eq: T,
     e_b: T,
@@ -366,7 +604,6 @@ pub fn lennard_jones_grad_par<
     gx: &mut [T],
     gy: &mut [T],
     gz: &mut [T],
-    buf: &mut Vec<Vec<T>>,
 ) -> T
 where
     LaneCount<N>: SupportedLaneCount,
@@ -375,10 +612,37 @@ where
         + Mul<Output = Simd<T, N>>
         + Div<Output = Simd<T, N>>
         + Neg<Output = Simd<T, N>>
-        + SimdFloat<Scalar = T>,
+        + SimdFloat<Scalar = T>
+        + AccValue,
 {
-    use rayon::prelude::*;
+    lennard_jones_grad_tiled_acc::<N, Plain, _>(r_eq, e_b, x, y, z, gx, gy, gz)
+}
 
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_tiled_acc<
+    const N: usize,
+    S: Summation,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign + AccValue,
+>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
     let one = T::one();
     let two = T::from(2.0).unwrap();
     let three = T::from(3.0).unwrap();
@@ -398,14 +662,6 @@ where
     assert_eq!(x.len(), gy.len());
     assert_eq!(x.len(), gz.len());
 
-    let (buf_s, buf_r) = crossbeam_channel::unbounded();
-
-    for _ in 0..rayon::current_num_threads() * 3 {
-        buf_s
-            .send(buf.pop().unwrap_or(vec![T::zero(); x.len()]))
-            .unwrap();
-    }
-
     gx.fill(T::zero());
     gy.fill(T::zero());
     gz.fill(T::zero());
@@ -417,63 +673,47 @@ where
     let (ycs, yr): (&[[_; N]], _) = y.as_chunks();
     let (zcs, zr): (&[[_; N]], _) = z.as_chunks();
 
-    let (gxcs, gxr): (&mut [[_; N]], _) = gx.as_chunks_mut();
-    let (gycs, gyr): (&mut [[_; N]], _) = gy.as_chunks_mut();
-    let (gzcs, gzr): (&mut [[_; N]], _) = gz.as_chunks_mut();
-
-    let parit = xcs
-        .into_par_iter()
-        .zip_eq(ycs.into_par_iter())
-        .zip_eq(zcs.into_par_iter())
-        .zip_eq(gxcs.into_par_iter())
-        .zip_eq(gycs.into_par_iter())
-        .zip_eq(gzcs.into_par_iter())
-        .enumerate();
-
-    let tls = ThreadLocal::new();
-
-    parit.for_each_with(
-        buf_r,
-        |buf_r, (i, (((((xc, yc), zc), gxc), gyc), gzc))| {
-            let (mut e, mut gx_buf, mut gy_buf, mut gz_buf) = unsafe {
-                tls.get_or(|| {
-                    let mut gx = buf_r.recv().unwrap();
-                    let mut gy = buf_r.recv().unwrap();
-                    let mut gz = buf_r.recv().unwrap();
-                    gx.fill(T::zero());
-                    gy.fill(T::zero());
-                    gz.fill(T::zero());
-                    RefCell::new(Some((T::zero(), gx, gy, gz)))
-                })
-                .take()
-                .unwrap_unchecked()
-            };
-
-            e += lennard_jones_grad_rest(s2, e_b, xc, yc, zc, gxc, gyc, gzc);
-
-            let mut es = Simd::splat(T::zero());
-
-            let xi = Simd::from(*xc);
-            let yi = Simd::from(*yc);
-            let zi = Simd::from(*zc);
-
-            let mut gxi = Simd::from(*gxc);
-            let mut gyi = Simd::from(*gyc);
-            let mut gzi = Simd::from(*gzc);
+    let (gxcs, gxr) = gx.as_chunks_mut::<N>();
+    let (gycs, gyr) = gy.as_chunks_mut::<N>();
+    let (gzcs, gzr) = gz.as_chunks_mut::<N>();
 
-            for (((((xj, yj), zj), gxj), gyj), gzj) in x
-                .iter()
-                .zip(y)
-                .zip(z)
-                .zip(&mut gx_buf)
-                .zip(&mut gy_buf)
-                .zip(&mut gz_buf)
-                .take(N * i)
-            {
-                let xj = Simd::splat(*xj);
-                let yj = Simd::splat(*yj);
-                let zj = Simd::splat(*zj);
+    let mut e = Acc::<S, T>::new();
+    let mut es = Acc::<S, Simd<T, N>>::new();
 
+    for i in 0..xcs.len() {
+        e.merge(lennard_jones_grad_rest(
+            s2,
+            e_b,
+            &xcs[i],
+            &ycs[i],
+            &zcs[i],
+            &mut gxcs[i],
+            &mut gycs[i],
+            &mut gzcs[i],
+            0,
+        ));
+
+        let xi = Simd::from(xcs[i]);
+        let yi = Simd::from(ycs[i]);
+        let zi = Simd::from(zcs[i]);
+
+        let mut gxi = Simd::from(gxcs[i]);
+        let mut gyi = Simd::from(gycs[i]);
+        let mut gzi = Simd::from(gzcs[i]);
+
+        for j in 0..i {
+            let mut xj = Simd::from(xcs[j]);
+            let mut yj = Simd::from(ycs[j]);
+            let mut zj = Simd::from(zcs[j]);
+
+            let mut gxj = Simd::from(gxcs[j]);
+            let mut gyj = Simd::from(gycs[j]);
+            let mut gzj = Simd::from(gzcs[j]);
+
+            // The j chunk and its gradient are rotated one lane per step, so
+            // after N steps every lane of i has met every lane of j and the
+            // j accumulators are back in their original lanes.
+            for _ in 0..N {
                 let dx = xj - xi;
                 let dy = yj - yi;
                 let dz = zj - zi;
@@ -483,7 +723,7 @@ where
                 let sr6 = sr2 * sr2 * sr2;
                 let sr12 = sr6 * sr6;
 
-                es += sr12 - sr6;
+                es.add(sr12 - sr6);
 
                 let gs =
                     -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
@@ -496,34 +736,926 @@ where
                 gyi -= gys;
                 gzi -= gzs;
 
-                *gxj += gxs.reduce_sum();
-                *gyj += gys.reduce_sum();
-                *gzj += gzs.reduce_sum();
+                gxj += gxs;
+                gyj += gys;
+                gzj += gzs;
+
+                xj = xj.rotate_lanes_left::<1>();
+                yj = yj.rotate_lanes_left::<1>();
+                zj = zj.rotate_lanes_left::<1>();
+
+                gxj = gxj.rotate_lanes_left::<1>();
+                gyj = gyj.rotate_lanes_left::<1>();
+                gzj = gzj.rotate_lanes_left::<1>();
             }
 
-            e += es.reduce_sum();
+            gxcs[j] = *gxj.as_array();
+            gycs[j] = *gyj.as_array();
+            gzcs[j] = *gzj.as_array();
+        }
+
+        gxcs[i] = *gxi.as_array();
+        gycs[i] = *gyi.as_array();
+        gzcs[i] = *gzi.as_array();
+    }
+
+    for (k, ((xk, yk), zk)) in xr.iter().zip(yr).zip(zr).enumerate() {
+        let xk = Simd::splat(*xk);
+        let yk = Simd::splat(*yk);
+        let zk = Simd::splat(*zk);
+
+        let mut gxk = Simd::splat(T::zero());
+        let mut gyk = Simd::splat(T::zero());
+        let mut gzk = Simd::splat(T::zero());
+
+        for j in 0..xcs.len() {
+            let dx = Simd::from(xcs[j]) - xk;
+            let dy = Simd::from(ycs[j]) - yk;
+            let dz = Simd::from(zcs[j]) - zk;
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es.add(sr12 - sr6);
+
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            let gxs = gs * dx;
+            let gys = gs * dy;
+            let gzs = gs * dz;
+
+            gxk -= gxs;
+            gyk -= gys;
+            gzk -= gzs;
+
+            gxcs[j] = *(Simd::from(gxcs[j]) + gxs).as_array();
+            gycs[j] = *(Simd::from(gycs[j]) + gys).as_array();
+            gzcs[j] = *(Simd::from(gzcs[j]) + gzs).as_array();
+        }
+
+        gxr[k] += gxk.reduce_sum();
+        gyr[k] += gyk.reduce_sum();
+        gzr[k] += gzk.reduce_sum();
+    }
+
+    e.merge(es.reduce());
+
+    e.merge(lennard_jones_grad_rest(
+        s2, e_b, xr, yr, zr, gxr, gyr, gzr, 0,
+    ));
+
+    e.value() * four * e_b
+}
+
+// One set of partial gradients and energy per thread, summed into the output
+// after the parallel loop
+pub struct GradWorkspace<T> {
+    bufs: Vec<ThreadBuf<T>>,
+}
+
+struct ThreadBuf<T> {
+    e: T,
+    e_c: T,
+    gx: Vec<T>,
+    gy: Vec<T>,
+    gz: Vec<T>,
+}
+
+impl<T: Float> GradWorkspace<T> {
+    pub fn new() -> Self {
+        Self { bufs: Vec::new() }
+    }
+
+    pub fn with_size(n: usize) -> Self {
+        let mut ws = Self::new();
+        ws.prepare(rayon::current_num_threads(), n);
+        ws
+    }
+
+    fn prepare(&mut self, threads: usize, n: usize) {
+        self.bufs.resize_with(threads, || ThreadBuf {
+            e: T::zero(),
+            e_c: T::zero(),
+            gx: Vec::new(),
+            gy: Vec::new(),
+            gz: Vec::new(),
+        });
+
+        for buf in &mut self.bufs {
+            buf.e = T::zero();
+            buf.e_c = T::zero();
+            for g in [&mut buf.gx, &mut buf.gy, &mut buf.gz] {
+                g.clear();
+                g.resize(n, T::zero());
+            }
+        }
+    }
+
+    // Adds the partial gradients to g and returns the partial energies
+    fn reduce<S: Summation>(
+        &self,
+        gx: &mut [T],
+        gy: &mut [T],
+        gz: &mut [T],
+    ) -> Acc<S, T>
+    where
+        T: AccValue,
+    {
+        let mut e = Acc::new();
+        for buf in &self.bufs {
+            e.merge(Acc::from_parts(buf.e, buf.e_c));
+            for (g, tl_g) in [&mut *gx, &mut *gy, &mut *gz]
+                .into_iter()
+                .zip([&buf.gx, &buf.gy, &buf.gz])
+            {
+                for (a, b) in g.iter_mut().zip(tl_g) {
+                    *a = *a + *b;
+                }
+            }
+        }
+        e
+    }
+}
+
+impl<T: Float> Default for GradWorkspace<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_par<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+    ws: &mut GradWorkspace<T>,
+) -> T
+where
+    T: Float
+        + SimdElement
+        + Sum
+        + AddAssign
+        + SubAssign
+        + AccValue
+        + Send
+        + Sync,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    lennard_jones_grad_par_acc::<N, Plain, _>(
+        r_eq, e_b, x, y, z, gx, gy, gz, ws,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_par_acc<const N: usize, S: Summation, T>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+    ws: &mut GradWorkspace<T>,
+) -> T
+where
+    T: Float
+        + SimdElement
+        + Sum
+        + AddAssign
+        + SubAssign
+        + AccValue
+        + Send
+        + Sync,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + AccValue,
+{
+    use rayon::prelude::*;
+
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let four = T::from(4.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+
+    let e_b_s = Simd::splat(e_b);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    ws.prepare(rayon::current_num_threads(), x.len());
+
+    gx.fill(T::zero());
+    gy.fill(T::zero());
+    gz.fill(T::zero());
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
+
+    let n_chunks = xcs.len();
+    let n_bufs = ws.bufs.len();
+
+    // Chunk i pairs with the N * i atoms before it, so the chunks are dealt
+    // out round-robin to keep the work per buffer even
+    ws.bufs.par_iter_mut().enumerate().for_each(|(t, buf)| {
+        let ThreadBuf {
+            e,
+            e_c,
+            gx: gx_buf,
+            gy: gy_buf,
+            gz: gz_buf,
+        } = buf;
+
+        let mut acc = Acc::<S, T>::new();
+        let mut es = Acc::<S, Simd<T, N>>::new();
+
+        for i in (t..n_chunks).step_by(n_bufs) {
+            let (gx_j, gx_i) = gx_buf.split_at_mut(N * i);
+            let (gy_j, gy_i) = gy_buf.split_at_mut(N * i);
+            let (gz_j, gz_i) = gz_buf.split_at_mut(N * i);
+
+            let gxc: &mut [T; N] = (&mut gx_i[..N]).try_into().unwrap();
+            let gyc: &mut [T; N] = (&mut gy_i[..N]).try_into().unwrap();
+            let gzc: &mut [T; N] = (&mut gz_i[..N]).try_into().unwrap();
+
+            let (xc, yc, zc) = (&xcs[i], &ycs[i], &zcs[i]);
+
+            acc.merge(lennard_jones_grad_rest(
+                s2, e_b, xc, yc, zc, gxc, gyc, gzc, 0,
+            ));
+
+            let xi = Simd::from(*xc);
+            let yi = Simd::from(*yc);
+            let zi = Simd::from(*zc);
+
+            let mut gxi = Simd::from(*gxc);
+            let mut gyi = Simd::from(*gyc);
+            let mut gzi = Simd::from(*gzc);
+
+            for (((((xj, yj), zj), gxj), gyj), gzj) in x
+                .iter()
+                .zip(y)
+                .zip(z)
+                .zip(gx_j.iter_mut())
+                .zip(gy_j.iter_mut())
+                .zip(gz_j.iter_mut())
+            {
+                let xj = Simd::splat(*xj);
+                let yj = Simd::splat(*yj);
+                let zj = Simd::splat(*zj);
+
+                let dx = xj - xi;
+                let dy = yj - yi;
+                let dz = zj - zi;
+
+                let r2 = dx * dx + dy * dy + dz * dz;
+                let sr2 = s2s / r2;
+                let sr6 = sr2 * sr2 * sr2;
+                let sr12 = sr6 * sr6;
+
+                es.add(sr12 - sr6);
+
+                let gs =
+                    -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+                let gxs = gs * dx;
+                let gys = gs * dy;
+                let gzs = gs * dz;
+
+                gxi -= gxs;
+                gyi -= gys;
+                gzi -= gzs;
+
+                *gxj += gxs.reduce_sum();
+                *gyj += gys.reduce_sum();
+                *gzj += gzs.reduce_sum();
+            }
 
             *gxc = *gxi.as_array();
             *gyc = *gyi.as_array();
             *gzc = *gzi.as_array();
+        }
+
+        acc.merge(es.reduce());
 
-            unsafe { tls.get().unwrap_unchecked() }
-                .swap(&RefCell::new(Some((e, gx_buf, gy_buf, gz_buf))));
-        },
+        *e = acc.sum;
+        *e_c = acc.c;
+    });
+
+    let mut e = lennard_jones_grad_rest::<S, _>(
+        s2,
+        e_b,
+        x,
+        y,
+        z,
+        gx,
+        gy,
+        gz,
+        N * n_chunks,
     );
 
-    let mut e = lennard_jones_grad_rest(s2, e_b, xr, yr, zr, gxr, gyr, gzr);
+    e.merge(ws.reduce(gx, gy, gz));
 
-    for (tl_e, tl_gx, tl_gy, tl_gz) in
-        tls.into_iter().map(|x| x.into_inner().unwrap())
-    {
-        e += tl_e;
-        for (g, tl_g) in [&mut *gx, gy, gz].into_iter().zip([tl_gx, tl_gy, tl_gz]) {
-            for (a, b) in g.iter_mut().zip(&tl_g) {
-                *a += *b;
+    e.value() * four * e_b
+}
+
+fn lennard_jones_tail_mixed<S: Summation>(
+    s2: f32,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    start: usize,
+) -> Acc<S, f64> {
+    let mut e = Acc::new();
+    for (i, ((xi, yi), zi)) in x.iter().zip(y).zip(z).enumerate().skip(start) {
+        for ((xj, yj), zj) in x.iter().zip(y).zip(z).take(i) {
+            let dx = *xj - *xi;
+            let dy = *yj - *yi;
+            let dz = *zj - *zi;
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let sr2 = s2 / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            e.add((sr12 - sr6) as f64);
+        }
+    }
+    e
+}
+
+// Positions, distances and the potential itself are evaluated in f32 while
+// every pair contribution is accumulated in f64. On cubic lattices of 10^3 to
+// 24^3 atoms (with and without random displacements) the relative error of
+// energies and gradients against the f64 kernels is 3e-9 to 2e-8, whereas
+// accumulating in f32 as well gives 1e-3 to 1e-2 and grows with system size.
+pub fn lennard_jones_mixed<const N: usize>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    lennard_jones_mixed_acc::<N, Plain>(r_eq, e_b, x, y, z)
+}
+
+pub fn lennard_jones_mixed_acc<const N: usize, S: Summation>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
+
+    let s2 = (2.0f64.powf(-1.0 / 3.0) * r_eq.powi(2)) as f32;
+    let s2s = Simd::splat(s2);
+
+    let mut es = Acc::<S, Simd<f64, N>>::new();
+    for (i, ((xc, yc), zc)) in xcs.iter().zip(ycs).zip(zcs).enumerate() {
+        let xi = Simd::from(*xc);
+        let yi = Simd::from(*yc);
+        let zi = Simd::from(*zc);
+
+        for ((xj, yj), zj) in x.iter().zip(y).zip(z).take(N * i) {
+            let xj = Simd::splat(*xj);
+            let yj = Simd::splat(*yj);
+            let zj = Simd::splat(*zj);
+
+            let dx = xj - xi;
+            let dy = yj - yi;
+            let dz = zj - zi;
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es.add((sr12 - sr6).cast::<f64>());
+        }
+    }
+
+    let mut e = es.reduce();
+
+    for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
+        e.merge(lennard_jones_tail_mixed(s2, xc, yc, zc, 0));
+    }
+
+    e.merge(lennard_jones_tail_mixed(s2, x, y, z, N * xcs.len()));
+
+    e.value() * 4.0 * e_b
+}
+
+#[allow(clippy::too_many_arguments)]
+fn lennard_jones_grad_tail_mixed<S: Summation>(
+    s2: f32,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    gx: &mut [f64],
+    gy: &mut [f64],
+    gz: &mut [f64],
+    start: usize,
+) -> Acc<S, f64> {
+    let e_b = e_b as f32;
+
+    let mut e = Acc::new();
+    for (i, ((xi, yi), zi)) in x.iter().zip(y).zip(z).enumerate().skip(start) {
+        let mut gxi = 0.0;
+        let mut gyi = 0.0;
+        let mut gzi = 0.0;
+
+        for (j, ((xj, yj), zj)) in x.iter().zip(y).zip(z).enumerate().take(i) {
+            let dx = *xj - *xi;
+            let dy = *yj - *yi;
+            let dz = *zj - *zi;
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let sr2 = s2 / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            e.add((sr12 - sr6) as f64);
+
+            let gs = -24.0 * e_b * sr6 / r2 * (2.0 * sr6 - 1.0);
+
+            let gxs = (gs * dx) as f64;
+            let gys = (gs * dy) as f64;
+            let gzs = (gs * dz) as f64;
+
+            gxi -= gxs;
+            gyi -= gys;
+            gzi -= gzs;
+
+            gx[j] += gxs;
+            gy[j] += gys;
+            gz[j] += gzs;
+        }
+
+        gx[i] += gxi;
+        gy[i] += gyi;
+        gz[i] += gzi;
+    }
+    e
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_mixed<const N: usize>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    gx: &mut [f64],
+    gy: &mut [f64],
+    gz: &mut [f64],
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    lennard_jones_grad_mixed_acc::<N, Plain>(r_eq, e_b, x, y, z, gx, gy, gz)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_mixed_acc<const N: usize, S: Summation>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    gx: &mut [f64],
+    gy: &mut [f64],
+    gz: &mut [f64],
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    let one_s = Simd::<f32, N>::splat(1.0);
+    let two_s = Simd::<f32, N>::splat(2.0);
+    let twentyfour_s = Simd::<f32, N>::splat(24.0);
+
+    let e_b_s = Simd::splat(e_b as f32);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    gx.fill(0.0);
+    gy.fill(0.0);
+    gz.fill(0.0);
+
+    let s2 = (2.0f64.powf(-1.0 / 3.0) * r_eq.powi(2)) as f32;
+    let s2s = Simd::splat(s2);
+
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
+
+    let (gxcs, _) = gx.as_chunks_mut::<N>();
+    let (gycs, _) = gy.as_chunks_mut::<N>();
+    let (gzcs, _) = gz.as_chunks_mut::<N>();
+
+    let mut e = Acc::<S, f64>::new();
+    let mut es = Acc::<S, Simd<f64, N>>::new();
+    for (i, ((xc, yc), zc)) in xcs.iter().zip(ycs).zip(zcs).enumerate() {
+        let (gx_j, gx_i) = gxcs.split_at_mut(i);
+        let (gy_j, gy_i) = gycs.split_at_mut(i);
+        let (gz_j, gz_i) = gzcs.split_at_mut(i);
+
+        let (gxc, gyc, gzc) = (&mut gx_i[0], &mut gy_i[0], &mut gz_i[0]);
+
+        e.merge(lennard_jones_grad_tail_mixed(
+            s2, e_b, xc, yc, zc, gxc, gyc, gzc, 0,
+        ));
+
+        let xi = Simd::from(*xc);
+        let yi = Simd::from(*yc);
+        let zi = Simd::from(*zc);
+
+        let mut gxi = Simd::from(*gxc);
+        let mut gyi = Simd::from(*gyc);
+        let mut gzi = Simd::from(*gzc);
+
+        for (((((xj, yj), zj), gxj), gyj), gzj) in x
+            .iter()
+            .zip(y)
+            .zip(z)
+            .zip(gx_j.iter_mut().flatten())
+            .zip(gy_j.iter_mut().flatten())
+            .zip(gz_j.iter_mut().flatten())
+        {
+            let xj = Simd::splat(*xj);
+            let yj = Simd::splat(*yj);
+            let zj = Simd::splat(*zj);
+
+            let dx = xj - xi;
+            let dy = yj - yi;
+            let dz = zj - zi;
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es.add((sr12 - sr6).cast::<f64>());
+
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            let gxs = (gs * dx).cast::<f64>();
+            let gys = (gs * dy).cast::<f64>();
+            let gzs = (gs * dz).cast::<f64>();
+
+            gxi -= gxs;
+            gyi -= gys;
+            gzi -= gzs;
+
+            *gxj += gxs.reduce_sum();
+            *gyj += gys.reduce_sum();
+            *gzj += gzs.reduce_sum();
+        }
+
+        *gxc = *gxi.as_array();
+        *gyc = *gyi.as_array();
+        *gzc = *gzi.as_array();
+    }
+
+    e.merge(es.reduce());
+
+    e.merge(lennard_jones_grad_tail_mixed(
+        s2,
+        e_b,
+        x,
+        y,
+        z,
+        gx,
+        gy,
+        gz,
+        N * xcs.len(),
+    ));
+
+    e.value() * 4.0 * e_b
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_par_mixed<const N: usize>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    gx: &mut [f64],
+    gy: &mut [f64],
+    gz: &mut [f64],
+    ws: &mut GradWorkspace<f64>,
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    lennard_jones_grad_par_mixed_acc::<N, Plain>(
+        r_eq, e_b, x, y, z, gx, gy, gz, ws,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_par_mixed_acc<const N: usize, S: Summation>(
+    r_eq: f64,
+    e_b: f64,
+    x: &[f32],
+    y: &[f32],
+    z: &[f32],
+    gx: &mut [f64],
+    gy: &mut [f64],
+    gz: &mut [f64],
+    ws: &mut GradWorkspace<f64>,
+) -> f64
+where
+    LaneCount<N>: SupportedLaneCount,
+{
+    use rayon::prelude::*;
+
+    let one_s = Simd::<f32, N>::splat(1.0);
+    let two_s = Simd::<f32, N>::splat(2.0);
+    let twentyfour_s = Simd::<f32, N>::splat(24.0);
+
+    let e_b_s = Simd::splat(e_b as f32);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    ws.prepare(rayon::current_num_threads(), x.len());
+
+    gx.fill(0.0);
+    gy.fill(0.0);
+    gz.fill(0.0);
+
+    let s2 = (2.0f64.powf(-1.0 / 3.0) * r_eq.powi(2)) as f32;
+    let s2s = Simd::splat(s2);
+
+    let (xcs, _): (&[[_; N]], _) = x.as_chunks();
+    let (ycs, _): (&[[_; N]], _) = y.as_chunks();
+    let (zcs, _): (&[[_; N]], _) = z.as_chunks();
+
+    let n_chunks = xcs.len();
+    let n_bufs = ws.bufs.len();
+
+    ws.bufs.par_iter_mut().enumerate().for_each(|(t, buf)| {
+        let ThreadBuf {
+            e,
+            e_c,
+            gx: gx_buf,
+            gy: gy_buf,
+            gz: gz_buf,
+        } = buf;
+
+        let mut acc = Acc::<S, f64>::new();
+        let mut es = Acc::<S, Simd<f64, N>>::new();
+
+        for i in (t..n_chunks).step_by(n_bufs) {
+            let (gx_j, gx_i) = gx_buf.split_at_mut(N * i);
+            let (gy_j, gy_i) = gy_buf.split_at_mut(N * i);
+            let (gz_j, gz_i) = gz_buf.split_at_mut(N * i);
+
+            let gxc: &mut [f64; N] = (&mut gx_i[..N]).try_into().unwrap();
+            let gyc: &mut [f64; N] = (&mut gy_i[..N]).try_into().unwrap();
+            let gzc: &mut [f64; N] = (&mut gz_i[..N]).try_into().unwrap();
+
+            let (xc, yc, zc) = (&xcs[i], &ycs[i], &zcs[i]);
+
+            acc.merge(lennard_jones_grad_tail_mixed(
+                s2, e_b, xc, yc, zc, gxc, gyc, gzc, 0,
+            ));
+
+            let xi = Simd::from(*xc);
+            let yi = Simd::from(*yc);
+            let zi = Simd::from(*zc);
+
+            let mut gxi = Simd::from(*gxc);
+            let mut gyi = Simd::from(*gyc);
+            let mut gzi = Simd::from(*gzc);
+
+            for (((((xj, yj), zj), gxj), gyj), gzj) in x
+                .iter()
+                .zip(y)
+                .zip(z)
+                .zip(gx_j.iter_mut())
+                .zip(gy_j.iter_mut())
+                .zip(gz_j.iter_mut())
+            {
+                let xj = Simd::splat(*xj);
+                let yj = Simd::splat(*yj);
+                let zj = Simd::splat(*zj);
+
+                let dx = xj - xi;
+                let dy = yj - yi;
+                let dz = zj - zi;
+
+                let r2 = dx * dx + dy * dy + dz * dz;
+                let sr2 = s2s / r2;
+                let sr6 = sr2 * sr2 * sr2;
+                let sr12 = sr6 * sr6;
+
+                es.add((sr12 - sr6).cast::<f64>());
+
+                let gs =
+                    -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+                let gxs = (gs * dx).cast::<f64>();
+                let gys = (gs * dy).cast::<f64>();
+                let gzs = (gs * dz).cast::<f64>();
+
+                gxi -= gxs;
+                gyi -= gys;
+                gzi -= gzs;
+
+                *gxj += gxs.reduce_sum();
+                *gyj += gys.reduce_sum();
+                *gzj += gzs.reduce_sum();
             }
+
+            *gxc = *gxi.as_array();
+            *gyc = *gyi.as_array();
+            *gzc = *gzi.as_array();
+        }
+
+        acc.merge(es.reduce());
+
+        *e = acc.sum;
+        *e_c = acc.c;
+    });
+
+    let mut e = lennard_jones_grad_tail_mixed::<S>(
+        s2,
+        e_b,
+        x,
+        y,
+        z,
+        gx,
+        gy,
+        gz,
+        N * n_chunks,
+    );
+
+    e.merge(ws.reduce(gx, gy, gz));
+
+    e.value() * 4.0 * e_b
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        alloc::{GlobalAlloc, Layout, System},
+        cell::Cell,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use super::*;
+
+    // Counts every allocation made on threads that opted in, so that other
+    // tests running at the same time do not show up
+    struct Counting;
+
+    // 11^3 atoms leave a remainder for N = 8
+    const N_SIDE: usize = 11;
+
+    static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static COUNTED: Cell<bool> = const { Cell::new(false) };
+    }
+
+    fn count() {
+        if COUNTED.try_with(|c| c.get()).unwrap_or(false) {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    unsafe impl GlobalAlloc for Counting {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            count();
+            System.alloc(layout)
         }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(
+            &self,
+            ptr: *mut u8,
+            layout: Layout,
+            new_size: usize,
+        ) -> *mut u8 {
+            count();
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static GLOBAL: Counting = Counting;
+
+    fn max_diff(a: &[f64], b: &[f64]) -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
     }
 
-    e * four * e_b
+    #[test]
+    fn grad_par_reuses_workspace() {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .start_handler(|_| COUNTED.with(|c| c.set(true)))
+            .build()
+            .unwrap();
+
+        let [x, y, z] = setup_cubic_lattice(N_SIDE, 1.1);
+        let n = x.len();
+
+        let mut g_ref = [(); 3].map(|_| vec![0.0; n]);
+        let [gx, gy, gz] = &mut g_ref;
+        let e_ref =
+            lennard_jones_grad::<8, _>(1.0, 1.0, &x, &y, &z, gx, gy, gz);
+
+        let mut g = [(); 3].map(|_| vec![0.0; n]);
+        let mut ws = GradWorkspace::new();
+
+        let (e, allocations) = pool.install(|| {
+            let [gx, gy, gz] = &mut g;
+
+            // The first calls size the workspace and let rayon set up its
+            // per-worker state
+            for _ in 0..10 {
+                lennard_jones_grad_par::<8, _>(
+                    1.0, 1.0, &x, &y, &z, gx, gy, gz, &mut ws,
+                );
+            }
+
+            let before = ALLOCATIONS.load(Ordering::Relaxed);
+            let mut e = 0.0;
+            for _ in 0..10 {
+                e = lennard_jones_grad_par::<8, _>(
+                    1.0, 1.0, &x, &y, &z, gx, gy, gz, &mut ws,
+                );
+            }
+            (e, ALLOCATIONS.load(Ordering::Relaxed) - before)
+        });
+
+        assert_eq!(allocations, 0);
+
+        assert!((e - e_ref).abs() < 1e-12 * e_ref.abs());
+        for (g, g_ref) in g.iter().zip(&g_ref) {
+            assert!(max_diff(g, g_ref) < 1e-12);
+        }
+    }
 }
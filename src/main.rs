@@ -4,15 +4,42 @@
 
 use std::{env::Args, time::Instant};
 
-use rand::Rng;
+use rand::{seq::SliceRandom, Rng};
 
 pub mod linalg;
 
+pub mod accumulator;
+pub mod dual;
+pub mod gradient_check;
+
+pub mod cell_list;
+pub mod reorder;
+pub mod triclinic;
+
+pub mod bonded;
+pub mod checkpoint;
+pub mod constraints;
+pub mod ewald;
+pub mod external;
+pub mod fft;
+pub mod mc;
+pub mod md;
+pub mod observables;
+pub mod spme;
+pub mod tail;
+pub mod tempering;
+
 pub mod colatz;
 
 pub mod lennard_jones;
 pub mod lennard_jones_t;
+pub mod storage;
+
+pub mod clusters;
+pub mod groups;
+pub mod per_atom;
 
+pub mod interleave;
 pub mod transpose_u8;
 
 fn set_threads(args: &mut Args) -> usize {
@@ -30,6 +57,20 @@ fn set_threads(args: &mut Args) -> usize {
     threads
 }
 
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Precision {
+    F64,
+    Mixed,
+}
+
+fn get_precision(args: &mut Args) -> Precision {
+    match args.next().as_deref() {
+        None | Some("f64") => Precision::F64,
+        Some("mixed") | Some("f32") => Precision::Mixed,
+        Some(p) => panic!("Unknown precision: {p}"),
+    }
+}
+
 fn main() {
     let mut args = std::env::args();
     args.next();
@@ -384,6 +425,54 @@ fn main() {
 
             let n = args.next().unwrap().parse().unwrap();
 
+            if get_precision(&mut args) == Precision::Mixed {
+                let [x, y, z] = setup_cubic_lattice::<f32>(n, 1.0);
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<2>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("    2: {e} \t\t took {t:?}");
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<4>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("    4: {e} \t\t took {t:?}");
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<8>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("    8: {e} \t\t took {t:?}");
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<16>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("   16: {e} \t\t took {t:?}");
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<32>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("   32: {e} \t\t took {t:?}");
+
+                let t = Instant::now();
+                let e = lennard_jones_mixed::<64>(1.0, 1.0, &x, &y, &z);
+                let t = t.elapsed();
+
+                println!("   64: {e} \t\t took {t:?}");
+
+                let [x, y, z] = setup_cubic_lattice::<f64>(n, 1.0);
+                let e_ref = lennard_jones::<1, _>(1.0, 1.0, &x, &y, &z);
+
+                println!("  f64: {e_ref}");
+                println!("rel err: {:e}", ((e - e_ref) / e_ref).abs());
+
+                return;
+            }
+
             let [x, y, z] = setup_cubic_lattice(n, 1.0);
 
             let t = Instant::now();
@@ -428,11 +517,596 @@ fn main() {
 
             println!("   64: {e} \t\t took {t:?}");
         }
+        "lennard-jones-dims" => {
+            use lennard_jones_t::*;
+            use rand::SeedableRng;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            // The generic kernels with D = 3 against the naive AoS ones
+            let r_aos = lennard_jones::setup_cubic_lattice(n, 1.0);
+            let r = setup_lattice::<3, f64>(n, 1.0);
+            assert_eq!(r, to_soa(&r_aos));
+            let r = [&r[0][..], &r[1], &r[2]];
+
+            let t = Instant::now();
+            let e_naive = lennard_jones::lennard_jones_naive(1.0, 1.0, &r_aos);
+            let t = t.elapsed();
+            println!("  3D naive: {e_naive} \t\t took {t:?}");
+
+            let t = Instant::now();
+            let e = lennard_jones_dim::<8, 3, _>(1.0, 1.0, r);
+            let t = t.elapsed();
+            println!("Generic: {e} \t\t took {t:?}");
+
+            let mut g_naive = vec![[0.0; 3]; r_aos.len()];
+            lennard_jones::lennard_jones_grad_naive(
+                1.0,
+                1.0,
+                &mut g_naive,
+                &r_aos,
+            );
+
+            let mut g = [(); 3].map(|_| vec![0.0; r_aos.len()]);
+
+            let t = Instant::now();
+            let [gx, gy, gz] = &mut g;
+            let e_g =
+                lennard_jones_grad_dim::<8, 3, _>(1.0, 1.0, r, [gx, gy, gz]);
+            let t = t.elapsed();
+            println!("Generic grad: {e_g} \t\t took {t:?}");
+
+            assert!((e - e_naive).abs() < 1e-12 * e_naive.abs());
+            assert!((e_g - e_naive).abs() < 1e-12 * e_naive.abs());
+            let max_diff = g_naive
+                .iter()
+                .zip(to_aos([&g[0], &g[1], &g[2]]))
+                .flat_map(|(a, b)| a.iter().zip(b))
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f64::max);
+            assert!(max_diff < 1e-10);
+
+            // A disordered 2D square lattice of about as many atoms
+            let m = (n as f64).powf(1.5) as usize;
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(2);
+            let mut r = lennard_jones::setup_lattice::<2, f64>(m, 1.0);
+            for ri in &mut r {
+                for x in ri {
+                    *x += rng.gen_range(-0.1..0.1);
+                }
+            }
+
+            let e_naive = lennard_jones::lennard_jones_naive(1.0, 1.0, &r);
+            let mut g_naive = vec![[0.0; 2]; r.len()];
+            lennard_jones::lennard_jones_grad_naive(1.0, 1.0, &mut g_naive, &r);
+
+            let [x, y] = to_soa(&r);
+            let e = lennard_jones_dim::<8, 2, _>(1.0, 1.0, [&x, &y]);
+            let [mut gx, mut gy] = [(); 2].map(|_| vec![0.0; x.len()]);
+            let e_g = lennard_jones_grad_dim::<8, 2, _>(
+                1.0,
+                1.0,
+                [&x, &y],
+                [&mut gx, &mut gy],
+            );
+
+            println!("2D ({} atoms): {e_naive} {e} {e_g}", x.len());
+
+            assert!((e - e_naive).abs() < 1e-10 * e_naive.abs());
+            assert!((e_g - e_naive).abs() < 1e-10 * e_naive.abs());
+            for (gn, g) in g_naive.iter().zip(to_aos([&gx, &gy])) {
+                for (a, b) in gn.iter().zip(g) {
+                    assert!((a - b).abs() < 1e-8);
+                }
+            }
+        }
+        "lennard-jones-storage" => {
+            use storage::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            let mut rng = rand::thread_rng();
+            let mut r = lennard_jones::setup_lattice::<3, f64>(n, 1.0);
+            for ri in &mut r {
+                for x in ri {
+                    *x += rng.gen_range(-0.1..0.1);
+                }
+            }
+
+            fn bench<P: ParticleStorage<f64, 3>>(
+                name: &str,
+                r: &[[f64; 3]],
+            ) -> (f64, Vec<[f64; 3]>) {
+                let rs = P::from_aos(r);
+                let mut g = P::from_aos(r);
+
+                let t = Instant::now();
+                let e = lennard_jones::<8, 3, _, _>(1.0, 1.0, &rs);
+                let t = t.elapsed();
+
+                let t_g = Instant::now();
+                let e_g =
+                    lennard_jones_grad::<8, 3, _, _>(1.0, 1.0, &rs, &mut g);
+                let t_g = t_g.elapsed();
+
+                println!("{name:>6}: {e} \t took {t:?}, grad took {t_g:?}");
+                assert_eq!(e, e_g);
+
+                (e, g.to_aos())
+            }
+
+            println!("{} particles", r.len());
+            let (e_aos, g_aos) = bench::<Aos<_, 3>>("AoS", &r);
+            let (e_soa, g_soa) = bench::<Soa<_, 3>>("SoA", &r);
+            let (e_8, g_8) = bench::<AoSoA<_, 3, 8>>("AoSoA8", &r);
+            let (e_16, g_16) = bench::<AoSoA<_, 3, 16>>("AoSoA16", &r);
+
+            // Every layout visits the pairs in the same order
+            assert_eq!(e_aos, e_soa);
+            assert_eq!(e_aos, e_8);
+            assert_eq!(e_aos, e_16);
+            assert_eq!(g_aos, g_soa);
+            assert_eq!(g_aos, g_8);
+            assert_eq!(g_aos, g_16);
+        }
+        "lennard-jones-per-atom" => {
+            use per_atom::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            // A disordered lattice with a vacancy in the middle
+            let mut rng = rand::thread_rng();
+            let [mut x, mut y, mut z] =
+                lennard_jones_t::setup_cubic_lattice(n, 1.0);
+            let vacancy = n.pow(3) / 2;
+            for v in [&mut x, &mut y, &mut z] {
+                v.remove(vacancy);
+                for r in v.iter_mut() {
+                    *r += rng.gen_range(-0.05..0.05);
+                }
+            }
+            let n_atoms = x.len();
+
+            let mut e = vec![0.0; n_atoms];
+            let mut w = vec![[0.0; 6]; n_atoms];
+
+            let t = Instant::now();
+            let e_tot = lennard_jones_per_atom::<8, _>(
+                1.0,
+                1.0,
+                &x,
+                &y,
+                &z,
+                &mut e,
+                Some(&mut w),
+            );
+            let t = t.elapsed();
+            println!("Serial:   {e_tot} \t\t took {t:?}");
+
+            let mut e_par = vec![0.0; n_atoms];
+            let mut w_par = vec![[0.0; 6]; n_atoms];
+            let mut ws = PerAtomWorkspace::new();
+
+            let t = Instant::now();
+            let e_tot_par = lennard_jones_per_atom_par::<8, _>(
+                1.0,
+                1.0,
+                &x,
+                &y,
+                &z,
+                &mut e_par,
+                Some(&mut w_par),
+                &mut ws,
+            );
+            let t = t.elapsed();
+            println!("Parallel: {e_tot_par} \t\t took {t:?}");
+
+            // The virial summed over atoms is -sum_i r_i (x) g_i
+            let r = [&x[..], &y, &z];
+            let mut g = [(); 3].map(|_| vec![0.0; n_atoms]);
+            let [gx, gy, gz] = &mut g;
+            let e_ref = lennard_jones_t::lennard_jones_grad_dim::<8, 3, _>(
+                1.0,
+                1.0,
+                r,
+                [gx, gy, gz],
+            );
+
+            let mut w_ref = [0.0; 6];
+            for (k, (a, b)) in [(0, 0), (1, 1), (2, 2), (0, 1), (0, 2), (1, 2)]
+                .into_iter()
+                .enumerate()
+            {
+                w_ref[k] =
+                    -r[a].iter().zip(&g[b]).map(|(r, g)| r * g).sum::<f64>();
+            }
+
+            let e_sum: f64 = e.iter().sum();
+            let mut w_sum = [0.0; 6];
+            for wi in &w {
+                for (s, wi) in w_sum.iter_mut().zip(wi) {
+                    *s += wi;
+                }
+            }
+
+            println!("Sum of per-atom energies: {e_sum}");
+            println!("Virial: {w_sum:?}");
+            println!("   Ref: {w_ref:?}");
+
+            let (i_max, e_max) =
+                e.iter().enumerate().fold((0, f64::MIN), |a, (i, &e)| {
+                    if e > a.1 {
+                        (i, e)
+                    } else {
+                        a
+                    }
+                });
+            println!(
+                "Highest atom energy {e_max} at ({}, {}, {})",
+                x[i_max], y[i_max], z[i_max]
+            );
+
+            let scale = e_ref.abs();
+            assert!((e_tot - e_ref).abs() < 1e-12 * scale);
+            assert!((e_tot_par - e_ref).abs() < 1e-12 * scale);
+            assert!((e_sum - e_tot).abs() < 1e-12 * scale);
+            for (a, b) in w_sum.iter().zip(w_ref) {
+                assert!((a - b).abs() < 1e-10 * scale);
+            }
+            for (a, b) in e.iter().zip(&e_par) {
+                assert!((a - b).abs() < 1e-12);
+            }
+            for (a, b) in w.iter().zip(&w_par) {
+                for (a, b) in a.iter().zip(b) {
+                    assert!((a - b).abs() < 1e-12);
+                }
+            }
+        }
+        "lennard-jones-groups" => {
+            use groups::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            // A surface slab in the lower half of the lattice and adsorbed
+            // particles in the upper half
+            let mut rng = rand::thread_rng();
+            let [mut x, mut y, mut z] =
+                lennard_jones_t::setup_cubic_lattice(n, 1.0);
+            for v in [&mut x, &mut y, &mut z] {
+                for r in v.iter_mut() {
+                    *r += rng.gen_range(-0.05..0.05);
+                }
+            }
+            let r = [&x[..], &y, &z];
+            let n_atoms = x.len();
+
+            let surface = mask_indices(
+                &z.iter().map(|&z| z < n as f64 / 2.0).collect::<Vec<_>>(),
+            );
+            let adsorbate = mask_indices(
+                &z.iter().map(|&z| z >= n as f64 / 2.0).collect::<Vec<_>>(),
+            );
+            let all: Vec<_> = (0..n_atoms).collect();
+
+            let grads = || [(); 3].map(|_| vec![0.0; n_atoms]);
+            fn views(g: &mut [Vec<f64>; 3]) -> [&mut [f64]; 3] {
+                let [x, y, z] = g;
+                [x, y, z]
+            }
+
+            let mut g_ss = grads();
+            let mut g_aa = grads();
+            let mut g_sa = grads();
+            let mut g_all = grads();
+
+            let e_ss = lennard_jones_groups::<8, _>(
+                1.0,
+                1.0,
+                r,
+                &surface,
+                &surface,
+                Some(views(&mut g_ss)),
+            );
+            let e_aa = lennard_jones_groups::<8, _>(
+                1.0,
+                1.0,
+                r,
+                &adsorbate,
+                &adsorbate,
+                Some(views(&mut g_aa)),
+            );
+
+            let t = Instant::now();
+            let e_sa = lennard_jones_groups::<8, _>(
+                1.0,
+                1.0,
+                r,
+                &surface,
+                &adsorbate,
+                Some(views(&mut g_sa)),
+            );
+            let t = t.elapsed();
+
+            let e_all = lennard_jones_groups::<8, _>(
+                1.0,
+                1.0,
+                r,
+                &all,
+                &all,
+                Some(views(&mut g_all)),
+            );
+
+            let e_ref =
+                lennard_jones_t::lennard_jones_dim::<8, 3, _>(1.0, 1.0, r);
+
+            println!(
+                "{} surface and {} adsorbed particles",
+                surface.len(),
+                adsorbate.len()
+            );
+            println!("Surface:    {e_ss}");
+            println!("Adsorbate:  {e_aa}");
+            println!("Cross:      {e_sa} \t\t took {t:?}");
+            println!("Sum:        {}", e_ss + e_aa + e_sa);
+            println!("All pairs:  {e_all}");
+
+            let e_naive = surface
+                .iter()
+                .flat_map(|&i| adsorbate.iter().map(move |&j| (i, j)))
+                .map(|(i, j)| {
+                    let r2: f64 = r.iter().map(|r| (r[i] - r[j]).powi(2)).sum();
+                    let sr6 = (2f64.powf(-1.0 / 3.0) / r2).powi(3);
+                    4.0 * (sr6 * sr6 - sr6)
+                })
+                .sum::<f64>();
+            println!("Naive cross: {e_naive}");
+
+            let scale = e_ref.abs();
+            assert!((e_all - e_ref).abs() < 1e-12 * scale);
+            assert!((e_ss + e_aa + e_sa - e_ref).abs() < 1e-12 * scale);
+            assert!((e_sa - e_naive).abs() < 1e-12 * scale);
+
+            for q in 0..3 {
+                for i in 0..n_atoms {
+                    let g = g_ss[q][i] + g_aa[q][i] + g_sa[q][i];
+                    assert!((g - g_all[q][i]).abs() < 1e-10);
+                }
+            }
+        }
+        "lennard-jones-clusters" => {
+            use clusters::*;
+
+            let k: usize = args.next().unwrap().parse().unwrap();
+            let n_reps: usize = args.next().unwrap().parse().unwrap();
+            set_threads(&mut args);
+
+            let mut rng = rand::thread_rng();
+
+            for n_atoms in [13, 38, 55] {
+                // The sites of a cubic lattice closest to its center, jiggled
+                let [x, y, z] = lennard_jones_t::setup_cubic_lattice(5, 1.1);
+                let mut sites: Vec<[f64; 3]> =
+                    (0..x.len()).map(|i| [x[i], y[i], z[i]]).collect();
+                let d2 = |p: &[f64; 3]| -> f64 {
+                    p.iter().map(|x| (x - 2.2).powi(2)).sum()
+                };
+                sites.sort_by(|a, b| d2(a).partial_cmp(&d2(b)).unwrap());
+                sites.truncate(n_atoms);
+
+                let soa: Vec<[Vec<f64>; 3]> = (0..k)
+                    .map(|_| {
+                        [0, 1, 2].map(|q| {
+                            sites
+                                .iter()
+                                .map(|p| p[q] + 0.1 * (rng.gen::<f64>() - 0.5))
+                                .collect()
+                        })
+                    })
+                    .collect();
+
+                let r = Clusters::<f64, 8>::from_soa(&soa);
+                let mut e = vec![0.0; k];
+                let mut g = Clusters::zeros(n_atoms, k);
+
+                let t = Instant::now();
+                for _ in 0..n_reps {
+                    lennard_jones_grad_clusters(1.0, 1.0, &r, &mut e, &mut g);
+                }
+                let t_batched = t.elapsed() / n_reps as u32;
+
+                let mut e_par = vec![0.0; k];
+                let mut g_par = Clusters::zeros(n_atoms, k);
+                let t = Instant::now();
+                for _ in 0..n_reps {
+                    lennard_jones_grad_clusters_par(
+                        1.0, 1.0, &r, &mut e_par, &mut g_par,
+                    );
+                }
+                let t_par = t.elapsed() / n_reps as u32;
+
+                // One cluster at a time with the all-pairs kernels
+                let mut e_ref = vec![0.0; k];
+                let mut g_ref = vec![[(); 3].map(|_| vec![0.0; n_atoms]); k];
+                let t = Instant::now();
+                for _ in 0..n_reps {
+                    let refs = soa.iter().zip(&mut e_ref).zip(&mut g_ref);
+                    for ((c, e), g) in refs {
+                        let [gx, gy, gz] = g;
+                        *e = lennard_jones_t::lennard_jones_grad_dim::<8, 3, _>(
+                            1.0,
+                            1.0,
+                            [&c[0], &c[1], &c[2]],
+                            [gx, gy, gz],
+                        );
+                    }
+                }
+                let t_single = t.elapsed() / n_reps as u32;
+
+                let mut max_err: f64 = 0.0;
+                for c in 0..k {
+                    max_err = max_err.max((e[c] - e_ref[c]).abs());
+                    assert_eq!(e[c].to_bits(), e_par[c].to_bits());
+
+                    let (gc, gc_par) = (g.cluster(c), g_par.cluster(c));
+                    for q in 0..3 {
+                        for i in 0..n_atoms {
+                            let (gi, gi_par) = (gc[q][i], gc_par[q][i]);
+                            max_err = max_err.max((gi - g_ref[c][q][i]).abs());
+                            assert_eq!(gi.to_bits(), gi_par.to_bits());
+                        }
+                    }
+                }
+
+                println!(
+                    "{k} clusters of {n_atoms}: one at a time {t_single:?}, \
+                     batched {t_batched:?}, parallel {t_par:?}, max error \
+                     {max_err:e}"
+                );
+                assert!(max_err < 1e-10);
+            }
+        }
+        "lennard-jones-T-acc" => {
+            use accumulator::*;
+            use lennard_jones_t::*;
+
+            let n = args.next().unwrap().parse().unwrap();
+
+            let [x, y, z] = setup_cubic_lattice::<f32>(n, 1.0);
+
+            // Same f32 pair terms as the kernels below, summed in f64. The
+            // accumulator test checks them against an independent reference.
+            let t = Instant::now();
+            let e_ref =
+                lennard_jones_mixed_acc::<16, Neumaier>(1.0, 1.0, &x, &y, &z);
+            let t = t.elapsed();
+
+            println!("   reference: {e_ref} \t\t took {t:?}");
+
+            let rel_err = |e: f32| ((e as f64 - e_ref) / e_ref).abs();
+
+            let t = Instant::now();
+            let e_plain =
+                lennard_jones_acc::<16, Plain, _>(1.0, 1.0, &x, &y, &z);
+            let t = t.elapsed();
+
+            println!(
+                "   f32 Plain: {e_plain} \t\t took {t:?}, rel err {:e}",
+                rel_err(e_plain)
+            );
+
+            let t = Instant::now();
+            let e_kahan =
+                lennard_jones_acc::<16, Kahan, _>(1.0, 1.0, &x, &y, &z);
+            let t = t.elapsed();
+
+            println!(
+                "   f32 Kahan: {e_kahan} \t\t took {t:?}, rel err {:e}",
+                rel_err(e_kahan)
+            );
+
+            let t = Instant::now();
+            let e_neumaier =
+                lennard_jones_acc::<16, Neumaier, _>(1.0, 1.0, &x, &y, &z);
+            let t = t.elapsed();
+
+            println!(
+                "f32 Neumaier: {e_neumaier} \t\t took {t:?}, rel err {:e}",
+                rel_err(e_neumaier)
+            );
+        }
         "lennard-jones-T-grad" => {
             use lennard_jones_t::*;
 
             let n = args.next().unwrap().parse().unwrap();
 
+            if get_precision(&mut args) == Precision::Mixed {
+                let [x, y, z] = setup_cubic_lattice::<f32>(n, 1.0);
+                let mut gx = vec![0.0; n.pow(3)];
+                let mut gy = vec![0.0; n.pow(3)];
+                let mut gz = vec![0.0; n.pow(3)];
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<2>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("    2: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<4>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("    4: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<8>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("    8: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<16>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("   16: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<32>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("   32: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_mixed::<64>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+                );
+                let t = t.elapsed();
+
+                println!("   64: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let [x, y, z] = setup_cubic_lattice::<f64>(n, 1.0);
+                let mut gx_ref = vec![0.0; n.pow(3)];
+                let mut gy_ref = vec![0.0; n.pow(3)];
+                let mut gz_ref = vec![0.0; n.pow(3)];
+                let e_ref = lennard_jones_grad::<1, _>(
+                    1.0,
+                    1.0,
+                    &x,
+                    &y,
+                    &z,
+                    &mut gx_ref,
+                    &mut gy_ref,
+                    &mut gz_ref,
+                );
+
+                let g_max = gx_ref.iter().fold(0.0f64, |m, g| m.max(g.abs()));
+                let g_err = gx
+                    .iter()
+                    .zip(&gx_ref)
+                    .fold(0.0f64, |m, (a, b)| m.max((a - b).abs()));
+
+                println!("  f64: {e_ref}");
+                println!("rel err: {:e}", ((e - e_ref) / e_ref).abs());
+                println!("rel gx err: {:e}", g_err / g_max);
+
+                return;
+            }
+
             let [x, y, z] = setup_cubic_lattice(n, 1.0);
             let mut gx = vec![0.0; n.pow(3)];
             let mut gy = vec![0.0; n.pow(3)];
@@ -508,15 +1182,79 @@ fn main() {
 
             set_threads(&mut args);
 
+            if get_precision(&mut args) == Precision::Mixed {
+                let [x, y, z] = setup_cubic_lattice::<f32>(n, 1.0);
+                let mut gx = vec![0.0; n.pow(3)];
+                let mut gy = vec![0.0; n.pow(3)];
+                let mut gz = vec![0.0; n.pow(3)];
+                let mut ws = GradWorkspace::new();
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<2>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("    2: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<4>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("    4: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<8>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("    8: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<16>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("   16: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<32>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("   32: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_par_mixed::<64>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+                let t = t.elapsed();
+
+                println!("   64: {e} \t\t took {t:?}");
+                println!("gx: {:8.4?}", &gx[0..4]);
+
+                return;
+            }
+
             let [x, y, z] = setup_cubic_lattice(n, 1.0);
             let mut gx = vec![0.0; n.pow(3)];
             let mut gy = vec![0.0; n.pow(3)];
             let mut gz = vec![0.0; n.pow(3)];
-            let mut buf = Vec::new();
+            let mut ws = GradWorkspace::new();
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<1, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -525,7 +1263,7 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<2, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -534,7 +1272,7 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<4, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -543,7 +1281,7 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<8, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -552,7 +1290,7 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<16, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -561,7 +1299,7 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<32, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
@@ -570,13 +1308,1605 @@ fn main() {
 
             let t = Instant::now();
             let e = lennard_jones_grad_par::<64, _>(
-                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut buf,
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
             );
             let t = t.elapsed();
 
             println!("   64: {e} \t\t took {t:?}");
             println!("gx: {:8.4?}", &gx[0..4]);
         }
+        "lennard-jones-T-grad-tiled" => {
+            use lennard_jones_t::*;
+
+            let n = args.next().unwrap().parse().unwrap();
+
+            let [x, y, z] = setup_cubic_lattice(n, 1.0);
+            let mut gx = vec![0.0; n.pow(3)];
+            let mut gy = vec![0.0; n.pow(3)];
+            let mut gz = vec![0.0; n.pow(3)];
+            let mut gx_t = vec![0.0; n.pow(3)];
+            let mut gy_t = vec![0.0; n.pow(3)];
+            let mut gz_t = vec![0.0; n.pow(3)];
+
+            // Both kernels against the naive AoS gradient, relative to the
+            // largest component
+            let r: Vec<_> = (0..x.len()).map(|i| [x[i], y[i], z[i]]).collect();
+            let mut g_ref = vec![[0.0f64; 3]; r.len()];
+            lennard_jones::lennard_jones_grad_naive(1.0, 1.0, &mut g_ref, &r);
+            let scale =
+                g_ref.iter().flatten().fold(0.0f64, |m, g| m.max(g.abs()));
+
+            let max_err = |gx: &[f64], gy: &[f64], gz: &[f64]| {
+                g_ref
+                    .iter()
+                    .enumerate()
+                    .flat_map(|(i, g)| {
+                        [gx[i] - g[0], gy[i] - g[1], gz[i] - g[2]]
+                    })
+                    .fold(0.0, |m: f64, d| m.max(d.abs()))
+                    / scale
+            };
+
+            let t = Instant::now();
+            let e = lennard_jones_grad::<4, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+            );
+            let t = t.elapsed();
+
+            println!("    4: {e} \t\t took {t:?}");
+            let err = max_err(&gx, &gy, &gz);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+
+            let t = Instant::now();
+            let e = lennard_jones_grad_tiled::<4, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx_t, &mut gy_t, &mut gz_t,
+            );
+            let t = t.elapsed();
+
+            println!("    4t: {e} \t\t took {t:?}");
+            let err = max_err(&gx_t, &gy_t, &gz_t);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+
+            let t = Instant::now();
+            let e = lennard_jones_grad::<8, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+            );
+            let t = t.elapsed();
+
+            println!("    8: {e} \t\t took {t:?}");
+            let err = max_err(&gx, &gy, &gz);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+
+            let t = Instant::now();
+            let e = lennard_jones_grad_tiled::<8, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx_t, &mut gy_t, &mut gz_t,
+            );
+            let t = t.elapsed();
+
+            println!("    8t: {e} \t\t took {t:?}");
+            let err = max_err(&gx_t, &gy_t, &gz_t);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+
+            let t = Instant::now();
+            let e = lennard_jones_grad::<16, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz,
+            );
+            let t = t.elapsed();
+
+            println!("   16: {e} \t\t took {t:?}");
+            let err = max_err(&gx, &gy, &gz);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+
+            let t = Instant::now();
+            let e = lennard_jones_grad_tiled::<16, _>(
+                1.0, 1.0, &x, &y, &z, &mut gx_t, &mut gy_t, &mut gz_t,
+            );
+            let t = t.elapsed();
+
+            println!("   16t: {e} \t\t took {t:?}");
+            let err = max_err(&gx_t, &gy_t, &gz_t);
+            println!("max err: {err:e}");
+            assert!(err < 1e-12);
+        }
+        "lennard-jones-T-grad-steps" => {
+            use lennard_jones_t::*;
+
+            let n = args.next().unwrap().parse().unwrap();
+            let n_steps: usize = args.next().unwrap().parse().unwrap();
+
+            set_threads(&mut args);
+
+            let [x, y, z] = setup_cubic_lattice(n, 1.0);
+            let mut gx = vec![0.0; n.pow(3)];
+            let mut gy = vec![0.0; n.pow(3)];
+            let mut gz = vec![0.0; n.pow(3)];
+            let mut ws = GradWorkspace::with_size(n.pow(3));
+
+            let t = Instant::now();
+            for _ in 0..n_steps {
+                lennard_jones_grad_par::<8, _>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &mut ws,
+                );
+            }
+            let t = t.elapsed();
+
+            println!("{n_steps} steps took {t:?}");
+        }
+        "lennard-jones-cells-reorder" => {
+            use cell_list::*;
+            use reorder::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            let a = 1.1;
+            let box_l = [a * n as f64; 3];
+
+            let [mut x, mut y, mut z] =
+                lennard_jones_t::setup_cubic_lattice(n, a);
+            let mut gx = vec![0.0; n.pow(3)];
+            let mut gy = vec![0.0; n.pow(3)];
+            let mut gz = vec![0.0; n.pow(3)];
+
+            let mut rng = rand::thread_rng();
+            for v in [&mut x, &mut y, &mut z] {
+                for r in v.iter_mut() {
+                    *r += rng.gen_range(-0.1..0.1);
+                }
+            }
+            wrap_positions(box_l, &mut x, &mut y, &mut z);
+
+            let mut perm: Vec<_> = (0..n.pow(3)).collect();
+            perm.shuffle(&mut rng);
+            permute(&perm, &mut x);
+            permute(&perm, &mut y);
+            permute(&perm, &mut z);
+
+            let mut cells = CellList::new(box_l, 2.5);
+
+            for (name, curve) in [
+                ("Shuffled", None),
+                ("  Morton", Some(Curve::Morton)),
+                (" Hilbert", Some(Curve::Hilbert)),
+            ] {
+                if let Some(curve) = curve {
+                    let t = Instant::now();
+                    reorder(curve, box_l, &mut x, &mut y, &mut z);
+                    let t = t.elapsed();
+
+                    println!("Reordering took {t:?}");
+                }
+
+                cells.build(&x, &y, &z);
+
+                let t = Instant::now();
+                let e = lennard_jones_grad_cells::<8, _>(
+                    1.0, 1.0, &x, &y, &z, &mut gx, &mut gy, &mut gz, &cells,
+                );
+                let t = t.elapsed();
+
+                println!("{name}: {e} \t\t took {t:?}");
+            }
+        }
+        "md-checkpoint" => {
+            use md::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            let a = 1.1;
+            let box_l = [a * n as f64; 3];
+
+            let path = std::env::temp_dir().join("md-checkpoint.bin");
+
+            for thermostat in [
+                Thermostat::None,
+                Thermostat::Langevin {
+                    temperature: 0.5,
+                    gamma: 1.0,
+                },
+                Thermostat::NoseHoover {
+                    temperature: 0.5,
+                    q: 10.0,
+                    xi: 0.0,
+                },
+            ] {
+                let new_md = || {
+                    let sys = System::new(
+                        box_l,
+                        lennard_jones_t::setup_cubic_lattice(n, a),
+                    );
+                    let mut md = Md::with_seed(
+                        sys, 1.0, 1.0, 2.5, 0.005, thermostat, 42,
+                    );
+                    md.init_velocities(0.5);
+                    md
+                };
+
+                let mut md = new_md();
+                let t = Instant::now();
+                md.run(2 * steps);
+                let t = t.elapsed();
+
+                let mut md2 = new_md();
+                md2.run(steps);
+                md2.save_file(&path).unwrap();
+                drop(md2);
+
+                let mut md2 = Md::load_file(&path).unwrap();
+                md2.run(steps);
+
+                println!(
+                    "{thermostat:?}: step {}, E = {}, restarted E = {}, \
+                     T = {} \t took {t:?}",
+                    md.step,
+                    md.e_pot + md.kinetic_energy(),
+                    md2.e_pot + md2.kinetic_energy(),
+                    md.temperature(),
+                );
+            }
+
+            std::fs::remove_file(&path).unwrap();
+        }
+        "bonded-polymer" => {
+            use bonded::*;
+            use md::*;
+
+            let n_chains: usize = args.next().unwrap().parse().unwrap();
+            let len: usize = args.next().unwrap().parse().unwrap();
+
+            let m = (n_chains as f64).sqrt().ceil() as usize;
+            let (a, b) = (1.2, 0.97);
+            let box_l = [
+                (m as f64 * a).max(8.0),
+                (m as f64 * a).max(8.0),
+                (len as f64 * b + 1.0).max(8.0),
+            ];
+
+            let mut rng = rand::thread_rng();
+            let mut r = [Vec::new(), Vec::new(), Vec::new()];
+            for c in 0..n_chains {
+                for i in 0..len {
+                    let p =
+                        [(c % m) as f64 * a, (c / m) as f64 * a, i as f64 * b];
+                    for (r, p) in r.iter_mut().zip(p) {
+                        r.push(p + rng.gen_range(-0.1..0.1));
+                    }
+                }
+            }
+            let n = r[0].len();
+
+            let mut topology = Topology::new(
+                BondPotential::Harmonic { k: 400.0, r0: 0.97 },
+                1.5,
+                DihedralPotential {
+                    k: 0.5,
+                    d: 1.0,
+                    n: 3,
+                },
+            );
+            topology.add_chains(0, n_chains, len);
+            topology.exclude(1);
+
+            let [x, y, z] = &r;
+            let mut g = [vec![0.0; n], vec![0.0; n], vec![0.0; n]];
+            let [gx, gy, gz] = &mut g;
+
+            let e = topology.energy::<4>(
+                1.0,
+                1.0,
+                2.5,
+                box_l,
+                x,
+                y,
+                z,
+                Some((gx, gy, gz)),
+                None,
+            );
+
+            let h = 1e-6;
+            let mut max_err: f64 = 0.0;
+            for i in 0..n.min(64) {
+                for q in 0..3 {
+                    let mut e_pm = [0.0; 2];
+                    for (e, s) in e_pm.iter_mut().zip([h, -h]) {
+                        let mut r = r.clone();
+                        r[q][i] += s;
+                        let [x, y, z] = &r;
+                        *e = topology.energy::<4>(
+                            1.0, 1.0, 2.5, box_l, x, y, z, None, None,
+                        );
+                    }
+                    let fd = (e_pm[0] - e_pm[1]) / (2.0 * h);
+                    max_err = max_err.max((fd - g[q][i]).abs());
+                }
+            }
+
+            println!("Bonded energy: {e}, max gradient error: {max_err:e}");
+            assert!(max_err < 1e-5);
+
+            let mut md = Md::with_seed(
+                System::new(box_l, r),
+                1.0,
+                1.0,
+                2.5,
+                0.002,
+                Thermostat::Langevin {
+                    temperature: 1.0,
+                    gamma: 1.0,
+                },
+                42,
+            );
+            md.set_topology(topology);
+            md.init_velocities(1.0);
+
+            let t = Instant::now();
+            md.run(1000);
+            let t = t.elapsed();
+
+            println!(
+                "After {} steps: E_pot = {}, T = {} \t took {t:?}",
+                md.step,
+                md.e_pot,
+                md.temperature(),
+            );
+
+            let mut buf = Vec::new();
+            md.save(&mut buf).unwrap();
+            let md2 = Md::load(&buf[..]).unwrap();
+
+            assert_eq!(md.topology, md2.topology);
+            assert_eq!(md.e_pot.to_bits(), md2.e_pot.to_bits());
+        }
+        "md-constraints" => {
+            use bonded::*;
+            use constraints::*;
+            use md::*;
+            use rand::SeedableRng;
+
+            let n_chains: usize = args.next().unwrap().parse().unwrap();
+            let len: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            let m = (n_chains as f64).sqrt().ceil() as usize;
+            let (a, b) = (1.5, 0.97);
+            let box_l = [
+                (m as f64 * a).max(8.0),
+                (m as f64 * a).max(8.0),
+                (len as f64 * b + 1.0).max(8.0),
+            ];
+
+            // Chains as random walks with exact bond lengths, so that they
+            // start out satisfying the constraints. The random heights keep
+            // pairs of chain ends off the cutoff.
+            let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(7);
+            let mut r = [Vec::new(), Vec::new(), Vec::new()];
+            for c in 0..n_chains {
+                let mut p = [
+                    (c % m) as f64 * a,
+                    (c / m) as f64 * a,
+                    rng.gen_range(0.0..0.5),
+                ];
+                for _ in 0..len {
+                    for (r, p) in r.iter_mut().zip(p) {
+                        r.push(p);
+                    }
+                    let u: [f64; 2] = [
+                        rng.gen_range(-0.15..0.15),
+                        rng.gen_range(-0.15..0.15),
+                    ];
+                    let s = b / (u[0] * u[0] + u[1] * u[1] + 1.0).sqrt();
+                    p = [p[0] + s * u[0], p[1] + s * u[1], p[2] + s];
+                }
+            }
+
+            let mut topology = Topology::new(
+                BondPotential::Harmonic { k: 400.0, r0: b },
+                1.5,
+                DihedralPotential {
+                    k: 0.5,
+                    d: 1.0,
+                    n: 3,
+                },
+            );
+            topology.add_chains(0, n_chains, len);
+            // The chains are close to straight, where dihedrals are ill defined
+            topology.dihedrals.clear();
+            topology.exclude(1);
+
+            let new_md = |s: f64, topology: Topology<f64>| {
+                let r =
+                    r.clone().map(|v| v.into_iter().map(|x| x * s).collect());
+                let mut md = Md::with_seed(
+                    System::new(box_l.map(|l| l * s), r),
+                    1.0,
+                    1.0,
+                    2.5,
+                    0.002,
+                    Thermostat::None,
+                    42,
+                );
+                md.set_topology(topology);
+                md
+            };
+
+            // Without a shifted potential, pairs crossing the cutoff make the
+            // energy jump by u(r_c). The forces are those of the potential
+            // shifted by -u(r_c), with the energy U - u(r_c) n_pairs.
+            let sr6 = (2f64.powf(-1.0 / 6.0) / 2.5).powi(6);
+            let u_c = 4.0 * (sr6 * sr6 - sr6);
+            let shifted_e_pot = |md: &Md| {
+                let l = md.sys.box_l;
+                let r: Vec<_> = (0..md.sys.len())
+                    .map(|i| [md.sys.x[i], md.sys.y[i], md.sys.z[i]])
+                    .collect();
+                let mut n_pairs = 0;
+                for (i, ri) in r.iter().enumerate() {
+                    for rj in &r[..i] {
+                        let r2: f64 = (0..3)
+                            .map(|q| {
+                                let d = rj[q] - ri[q];
+                                (d - (d / l[q]).round() * l[q]).powi(2)
+                            })
+                            .sum();
+                        if r2 < md.r_cut * md.r_cut {
+                            n_pairs += 1;
+                        }
+                    }
+                }
+                md.e_pot - u_c * n_pairs as f64
+            };
+
+            // The virial is minus the derivative of the energy with respect
+            // to a uniform scaling of the system
+            let h = 1e-6;
+            let w = new_md(1.0, topology.clone()).virial();
+            let w_fd = -(shifted_e_pot(&new_md(1.0 + h, topology.clone()))
+                - shifted_e_pot(&new_md(1.0 - h, topology.clone())))
+                / (2.0 * h);
+
+            println!("Virial: {w}, finite difference: {w_fd}");
+            assert!((w - w_fd).abs() < 1e-4 * w.abs().max(1.0));
+
+            let bonds = std::mem::take(&mut topology.bonds);
+            let constraints = Constraints::from_bonds(&bonds, b, 1e-10, 500);
+
+            let mut md = new_md(1.0, topology);
+            md.set_constraints(constraints);
+            md.init_velocities(1.0);
+
+            let e0 = md.e_pot + md.kinetic_energy();
+            let e0_shifted = shifted_e_pot(&md) + md.kinetic_energy();
+
+            let t = Instant::now();
+            for _ in 0..10 {
+                md.run(steps / 10);
+                println!(
+                    "step {}: E = {}, T = {}, P = {}",
+                    md.step,
+                    md.e_pot + md.kinetic_energy(),
+                    md.temperature(),
+                    md.pressure(),
+                );
+            }
+            let t = t.elapsed();
+
+            let max_dev = bonds
+                .iter()
+                .map(|&[i, j]| {
+                    let [x, y, z] = [&md.sys.x, &md.sys.y, &md.sys.z];
+                    let d = [x[j] - x[i], y[j] - y[i], z[j] - z[i]];
+                    let d = [0, 1, 2]
+                        .map(|q| d[q] - (d[q] / box_l[q]).round() * box_l[q]);
+                    ((d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt() - b).abs()
+                })
+                .fold(0.0, f64::max);
+
+            // The shifted energy is conserved up to the integration error
+            let drift = shifted_e_pot(&md) + md.kinetic_energy() - e0_shifted;
+            println!(
+                "Energy drift: {:e}, {drift:e} with the shifted potential, \
+                 max bond length error: {max_dev:e}",
+                md.e_pot + md.kinetic_energy() - e0
+            );
+            println!("Took {t:?}");
+            assert!(max_dev < 1e-8);
+            assert!(drift.abs() < 2e-3 * md.sys.len() as f64);
+
+            let mut buf = Vec::new();
+            md.save(&mut buf).unwrap();
+            let mut md2 = Md::load(&buf[..]).unwrap();
+
+            md.run(steps / 10);
+            md2.run(steps / 10);
+
+            assert_eq!(md.constraints, md2.constraints);
+            assert!(md
+                .sys
+                .x
+                .iter()
+                .zip(&md2.sys.x)
+                .all(|(a, b)| a.to_bits() == b.to_bits()));
+        }
+        "external-fields" => {
+            use external::*;
+            use md::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            let a = 1.1;
+            let box_l = [a * n as f64, a * n as f64, a * (n - 1) as f64 + 3.0];
+
+            let fields = [
+                External::Wall93 {
+                    axis: 2,
+                    pos: 0.0,
+                    epsilon: 1.0,
+                    sigma: 1.0,
+                },
+                External::Wall1043 {
+                    axis: 2,
+                    pos: box_l[2],
+                    epsilon: 1.0,
+                    sigma: 1.0,
+                    rho: 1.0,
+                    delta: 0.7,
+                },
+                External::Harmonic {
+                    center: box_l.map(|l| l / 2.0),
+                    k: [0.1, 0.2, 0.0],
+                },
+                External::Force {
+                    f: [0.0, 0.0, -0.1],
+                },
+                External::Sphere {
+                    center: box_l.map(|l| l / 2.0),
+                    radius: box_l[0],
+                    epsilon: 1.0,
+                    sigma: 1.0,
+                },
+            ];
+
+            let [mut x, mut y, mut z] =
+                lennard_jones_t::setup_cubic_lattice(n, a);
+            for z in z.iter_mut() {
+                *z += 1.5;
+            }
+
+            // Leaves a remainder for the SIMD chunks
+            x.pop();
+            y.pop();
+            z.pop();
+            let m = x.len();
+
+            let h = 1e-6;
+            for f in &fields {
+                let f = std::slice::from_ref(f);
+
+                let mut g = [vec![0.0; m], vec![0.0; m], vec![0.0; m]];
+                let [gx, gy, gz] = &mut g;
+                let e = external_grad::<8, _>(f, &x, &y, &z, gx, gy, gz);
+                let e2 = external_energy::<8, _>(f, &x, &y, &z);
+                assert!((e - e2).abs() < 1e-9);
+
+                let mut max_err: f64 = 0.0;
+                for i in (0..m).step_by(7) {
+                    for q in 0..3 {
+                        let mut e_pm = [0.0; 2];
+                        for (e, s) in e_pm.iter_mut().zip([h, -h]) {
+                            let mut r = [x.clone(), y.clone(), z.clone()];
+                            r[q][i] += s;
+                            let [x, y, z] = &r;
+                            *e = external_energy::<8, _>(f, x, y, z);
+                        }
+                        let fd = (e_pm[0] - e_pm[1]) / (2.0 * h);
+                        max_err = max_err.max((fd - g[q][i]).abs());
+                    }
+                }
+
+                println!("{:?}", f[0]);
+                println!("    E = {e}, max gradient error: {max_err:e}");
+                assert!(max_err < 1e-5);
+            }
+
+            // LJ fluid in a slit pore between the two walls
+            let mut md = Md::with_seed(
+                System::new(box_l, [x, y, z]),
+                1.0,
+                1.0,
+                2.5,
+                0.002,
+                Thermostat::Langevin {
+                    temperature: 1.0,
+                    gamma: 1.0,
+                },
+                42,
+            );
+            md.set_external(fields[..2].to_vec());
+            md.init_velocities(1.0);
+
+            let t = Instant::now();
+            md.run(steps);
+            let t = t.elapsed();
+
+            let (z_min, z_max) =
+                md.sys.z.iter().fold((f64::MAX, f64::MIN), |(a, b), &z| {
+                    (a.min(z), b.max(z))
+                });
+
+            println!(
+                "Slit pore: E_pot = {}, T = {}, z in [{z_min}, {z_max}]",
+                md.e_pot,
+                md.temperature(),
+            );
+            println!("Took {t:?}");
+            assert!(z_min > 0.0 && z_max < box_l[2]);
+
+            let mut buf = Vec::new();
+            md.save(&mut buf).unwrap();
+            let md2 = Md::load(&buf[..]).unwrap();
+
+            assert_eq!(md.external, md2.external);
+            assert_eq!(md.e_pot.to_bits(), md2.e_pot.to_bits());
+        }
+        "lennard-jones-triclinic" => {
+            use cell_list::*;
+            use triclinic::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            let a0 = 1.5;
+            let r_cut = 2.5;
+            let fcc = [
+                [0.0, 0.0, 0.0],
+                [0.5, 0.5, 0.0],
+                [0.5, 0.0, 0.5],
+                [0.0, 0.5, 0.5],
+            ];
+
+            let energies = |bx: &TriclinicBox<f64>, r: &[Vec<f64>; 3]| {
+                let [x, y, z] = r;
+                let mut cells = CellList::new_triclinic(bx, r_cut);
+                cells.build(x, y, z);
+
+                let e = lennard_jones_triclinic::<8, _>(
+                    1.0, 1.0, r_cut, bx, x, y, z,
+                );
+                let e_cells =
+                    lennard_jones_cells::<8, _>(1.0, 1.0, x, y, z, &cells);
+
+                (e / x.len() as f64, e_cells / x.len() as f64)
+            };
+
+            let orth = TriclinicBox::orthogonal([a0 * n as f64; 3]);
+            let r = setup_lattice(n, &TriclinicBox::orthogonal([a0; 3]), &fcc);
+            let (e_orth, e_orth_cells) = energies(&orth, &r);
+
+            // The same crystal in a cell sheared by one lattice constant along
+            // every tilt, which is still a lattice vector of the crystal
+            let sheared = TriclinicBox::new([a0 * n as f64; 3], [a0; 3]);
+            let mut r_sheared = r.clone();
+            {
+                let [x, y, z] = &mut r_sheared;
+                sheared.wrap(x, y, z);
+            }
+            let (e_sheared, e_sheared_cells) = energies(&sheared, &r_sheared);
+
+            // The rhombohedral primitive cell, a quarter of the volume
+            let h = a0 / 2.0;
+            let prim = TriclinicBox::from_vectors(
+                [0.0, h, h],
+                [h, 0.0, h],
+                [h, h, 0.0],
+            );
+            let m = (3.0 * r_cut / prim.widths()[0]).ceil() as usize;
+            let prim_super = TriclinicBox::new(
+                prim.l.map(|l| l * m as f64),
+                prim.tilt.map(|t| t * m as f64),
+            );
+            let r_prim = setup_lattice(m, &prim, &[[0.0; 3]]);
+            let (e_prim, e_prim_cells) = energies(&prim_super, &r_prim);
+
+            println!("Energy per atom");
+            println!("  orthogonal: {e_orth} {e_orth_cells}");
+            println!("     sheared: {e_sheared} {e_sheared_cells}");
+            println!(
+                "   primitive: {e_prim} {e_prim_cells} ({} atoms)",
+                m.pow(3)
+            );
+
+            // Gradients of a disordered configuration in the sheared cell
+            let mut rng = rand::thread_rng();
+            let [mut x, mut y, mut z] = r_sheared;
+            for v in [&mut x, &mut y, &mut z] {
+                for r in v.iter_mut() {
+                    *r += rng.gen_range(-0.1..0.1);
+                }
+            }
+            sheared.wrap(&mut x, &mut y, &mut z);
+
+            let n_atoms = x.len();
+            let mut g = [(); 3].map(|_| vec![0.0; n_atoms]);
+            let mut g_cells = g.clone();
+
+            let t = Instant::now();
+            let [gx, gy, gz] = &mut g;
+            let e = lennard_jones_grad_triclinic::<8, _>(
+                1.0, 1.0, r_cut, &sheared, &x, &y, &z, gx, gy, gz,
+            );
+            let t = t.elapsed();
+
+            let mut cells = CellList::new_triclinic(&sheared, r_cut);
+            cells.build(&x, &y, &z);
+
+            let t_cells = Instant::now();
+            let [gx, gy, gz] = &mut g_cells;
+            let e_cells = lennard_jones_grad_cells::<8, _>(
+                1.0, 1.0, &x, &y, &z, gx, gy, gz, &cells,
+            );
+            let t_cells = t_cells.elapsed();
+
+            let max_diff = g
+                .iter()
+                .zip(&g_cells)
+                .flat_map(|(a, b)| a.iter().zip(b))
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f64::max);
+
+            println!("All pairs: {e} \t\t took {t:?}");
+            println!("    Cells: {e_cells} \t\t took {t_cells:?}");
+            println!("Max gradient difference: {max_diff:e}");
+        }
+        "interleave" => {
+            use interleave::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            fn bench<T: Copy + Default + PartialEq, const S: usize>(
+                name: &str,
+                flat: &[T],
+                deinterleave: fn(&[T], [&mut [T]; S]),
+                deinterleave_par: fn(&[T], [&mut [T]; S]),
+                interleave: fn([&[T]; S], &mut [T]),
+                interleave_par: fn([&[T]; S], &mut [T]),
+            ) {
+                fn views<T, const S: usize>(
+                    v: &mut [Vec<T>; S],
+                ) -> [&mut [T]; S] {
+                    let mut it = v.iter_mut();
+                    [(); S].map(|_| &mut it.next().unwrap()[..])
+                }
+
+                let n = flat.len() / S;
+
+                // The buffers are cleared before every run so that page faults
+                // are not part of the timings
+                let mut soa = [(); S].map(|_| vec![T::default(); n]);
+
+                soa.iter_mut().for_each(|v| v.fill(T::default()));
+                let t = Instant::now();
+                for (i, r) in flat.chunks_exact(S).enumerate() {
+                    for (d, &r) in soa.iter_mut().zip(r) {
+                        d[i] = r;
+                    }
+                }
+                let t_naive = t.elapsed();
+                let naive = soa.clone();
+
+                soa.iter_mut().for_each(|v| v.fill(T::default()));
+                let t = Instant::now();
+                deinterleave(flat, views(&mut soa));
+                let t_simd = t.elapsed();
+                assert!(soa == naive);
+
+                soa.iter_mut().for_each(|v| v.fill(T::default()));
+                let t = Instant::now();
+                deinterleave_par(flat, views(&mut soa));
+                let t_par = t.elapsed();
+                assert!(soa == naive);
+
+                println!(
+                    "{name} deinterleave: naive {t_naive:?}, \
+                     simd {t_simd:?}, par {t_par:?}"
+                );
+
+                let mut it = soa.iter();
+                let src = [(); S].map(|_| &it.next().unwrap()[..]);
+
+                let mut aos = vec![T::default(); flat.len()];
+
+                aos.fill(T::default());
+                let t = Instant::now();
+                for (i, r) in aos.chunks_exact_mut(S).enumerate() {
+                    for (r, s) in r.iter_mut().zip(src) {
+                        *r = s[i];
+                    }
+                }
+                let t_naive = t.elapsed();
+                assert!(aos == flat);
+
+                aos.fill(T::default());
+                let t = Instant::now();
+                interleave(src, &mut aos);
+                let t_simd = t.elapsed();
+                assert!(aos == flat);
+
+                aos.fill(T::default());
+                let t = Instant::now();
+                interleave_par(src, &mut aos);
+                let t_par = t.elapsed();
+                assert!(aos == flat);
+
+                println!(
+                    "{name}   interleave: naive {t_naive:?}, \
+                     simd {t_simd:?}, par {t_par:?}"
+                );
+            }
+
+            let mut rng = rand::thread_rng();
+
+            let r: Vec<[f64; 3]> = (0..n).map(|_| rng.gen()).collect();
+            bench(
+                "f64x3",
+                flatten(&r),
+                deinterleave3_f64,
+                deinterleave3_f64_par,
+                interleave3_f64,
+                interleave3_f64_par,
+            );
+
+            let r: Vec<[f32; 3]> = (0..n).map(|_| rng.gen()).collect();
+            bench(
+                "f32x3",
+                flatten(&r),
+                deinterleave3_f32,
+                deinterleave3_f32_par,
+                interleave3_f32,
+                interleave3_f32_par,
+            );
+
+            let r: Vec<[f64; 4]> = (0..n).map(|_| rng.gen()).collect();
+            bench(
+                "f64x4",
+                flatten(&r),
+                deinterleave4_f64,
+                deinterleave4_f64_par,
+                interleave4_f64,
+                interleave4_f64_par,
+            );
+
+            let r: Vec<[f32; 4]> = (0..n).map(|_| rng.gen()).collect();
+            bench(
+                "f32x4",
+                flatten(&r),
+                deinterleave4_f32,
+                deinterleave4_f32_par,
+                interleave4_f32,
+                interleave4_f32_par,
+            );
+        }
+        "ewald-spme" => {
+            use ewald::Ewald;
+            use spme::Spme;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            assert!(n % 2 == 0, "the rock salt lattice needs an even n");
+            assert!(
+                n >= 12,
+                "SPME only pays off beyond about a thousand ions, so n must \
+                 be at least 12"
+            );
+
+            // Rock salt of side m with random displacements
+            let mut rng = rand::thread_rng();
+            let mut rock_salt = |m: usize| {
+                let mut q = Vec::new();
+                let mut r = [Vec::new(), Vec::new(), Vec::new()];
+                for i in 0..m * m * m {
+                    let c = [i / (m * m), i / m % m, i % m];
+                    let even = c.iter().sum::<usize>() % 2 == 0;
+                    q.push(if even { 1.0 } else { -1.0 });
+                    for (r, x) in r.iter_mut().zip(c) {
+                        r.push(x as f64 + 0.4 * (rng.gen::<f64>() - 0.5));
+                    }
+                }
+                (q, r)
+            };
+
+            type Gradient<'a> = Option<[&'a mut [f64]; 3]>;
+            let grad = |f: &dyn Fn(Gradient) -> f64, n_ions| {
+                let mut g = [(); 3].map(|_| vec![0.0; n_ions]);
+                let [gx, gy, gz] = &mut g;
+                let e = f(Some([gx, gy, gz]));
+                (e, g)
+            };
+
+            // With a fixed r_cut the plain Ewald sum costs O(N^2) in both
+            // spaces, while SPME is O(N) in real space and O(N log N) in
+            // reciprocal space
+            let r_cut = 3.0;
+
+            let (q, r) = rock_salt(n);
+            let rs = [&r[0][..], &r[1], &r[2]];
+            let n_ions = q.len();
+            let box_l = [n as f64; 3];
+
+            for accuracy in [1e-4, 1e-6, 1e-8] {
+                let ew = Ewald::new(box_l, r_cut, accuracy);
+                let sp = Spme::new(box_l, r_cut, accuracy, 8);
+
+                let (e_ew, g_ew) =
+                    grad(&|g| ewald::ewald::<4, _>(&ew, &q, rs, g), n_ions);
+                let (e_sp, g_sp) =
+                    grad(&|g| spme::spme::<4, _>(&sp, &q, rs, g), n_ions);
+
+                let mut g_rms = 0.0;
+                let mut g_err: f64 = 0.0;
+                for (g_ew, g_sp) in g_ew.iter().zip(&g_sp) {
+                    for (g_ew, g_sp) in g_ew.iter().zip(g_sp) {
+                        g_rms += g_ew * g_ew;
+                        g_err = g_err.max((g_sp - g_ew).abs());
+                    }
+                }
+                let g_rms = (g_rms / (3 * n_ions) as f64).sqrt();
+                let e_err = ((e_sp - e_ew) / e_ew).abs();
+
+                println!(
+                    "accuracy {accuracy:e}, grid {:?}: E = {e_ew}, relative \
+                     error {e_err:e}, max gradient error {:e} of rms",
+                    sp.grid(),
+                    g_err / g_rms
+                );
+                assert!(e_err < 10.0 * accuracy);
+                assert!(g_err < 10.0 * accuracy * g_rms.max(1.0));
+            }
+
+            let accuracy = 1e-6;
+            let mut times = Vec::new();
+            for m in (6..=n).step_by(2) {
+                let (q, r) = rock_salt(m);
+                let rs = [&r[0][..], &r[1], &r[2]];
+                let box_l = [m as f64; 3];
+                let ew = Ewald::new(box_l, r_cut, accuracy);
+                let sp = Spme::new(box_l, r_cut, accuracy, 8);
+
+                let t = Instant::now();
+                grad(&|g| ewald::ewald::<4, _>(&ew, &q, rs, g), q.len());
+                let t_ew = t.elapsed();
+
+                let t = Instant::now();
+                grad(&|g| spme::spme::<4, _>(&sp, &q, rs, g), q.len());
+                let t_sp = t.elapsed();
+
+                println!(
+                    "{} ions: Ewald {t_ew:?}, SPME {t_sp:?}, ratio {:.1}",
+                    q.len(),
+                    t_ew.as_secs_f64() / t_sp.as_secs_f64()
+                );
+                times.push((t_ew, t_sp));
+            }
+
+            let (t_ew, t_sp) = times[times.len() - 1];
+            assert!(t_sp < t_ew, "SPME should beat Ewald at {n_ions} ions");
+        }
+        "md-npt" => {
+            use md::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            // Starting from the lattice the Berendsen barostat needs about
+            // 4000 steps to relax the volume, and shorter runs average over
+            // the drift
+            assert!(steps >= 10000, "md-npt needs at least 10000 steps");
+
+            let a = 1.1;
+            let temperature = 2.0;
+            let pressure = 1.0;
+            let thermostat = Thermostat::Langevin {
+                temperature,
+                gamma: 1.0,
+            };
+            let sys = System::new(
+                [a * n as f64; 3],
+                lennard_jones_t::setup_cubic_lattice(n, a),
+            );
+
+            let barostats = [
+                Barostat::Berendsen {
+                    pressure,
+                    tau: 0.5,
+                    compressibility: 0.05,
+                },
+                Barostat::MonteCarlo {
+                    pressure,
+                    temperature,
+                    max_dlnv: 0.03,
+                    interval: 5,
+                },
+            ];
+
+            for barostat in barostats {
+                let mut md = Md::with_seed(
+                    sys.clone(),
+                    1.0,
+                    1.0,
+                    2.5,
+                    0.005,
+                    thermostat,
+                    42,
+                );
+                md.init_velocities(temperature);
+                md.set_barostat(barostat);
+
+                println!("{barostat:?}");
+                println!("step \t T \t\t P \t\t V \t\t H");
+
+                // Block averages of P, V and H sampled every step, with the
+                // second half of the blocks used for the averages and the
+                // error bar
+                let blocks = 10;
+                let block_steps = steps / blocks;
+                let mut means = Vec::new();
+                for b in 0..blocks {
+                    let mut block = [0.0; 3];
+                    for _ in 0..block_steps {
+                        md.step();
+                        let x = [md.pressure(), md.volume(), md.enthalpy()];
+                        for (block, x) in block.iter_mut().zip(x) {
+                            *block += x / block_steps as f64;
+                        }
+                    }
+
+                    let [p, v, h] = block;
+                    println!(
+                        "{} \t {:.4} \t {p:.4} \t {v:.2} \t {h:.2}",
+                        md.step,
+                        md.temperature()
+                    );
+                    if b >= blocks / 2 {
+                        means.push(block);
+                    }
+                }
+
+                let n_b = means.len() as f64;
+                let avg: [f64; 3] = std::array::from_fn(|k| {
+                    means.iter().map(|m| m[k]).sum::<f64>() / n_b
+                });
+                let p_err = (means
+                    .iter()
+                    .map(|m| (m[0] - avg[0]).powi(2))
+                    .sum::<f64>()
+                    / (n_b * (n_b - 1.0)))
+                    .sqrt();
+
+                let rho = md.sys.len() as f64 / avg[1];
+                println!(
+                    "<P> = {:.4} +- {p_err:.4}, <V> = {:.2}, <H> = {:.2}, \
+                     rho = {rho:.4}",
+                    avg[0], avg[1], avg[2]
+                );
+                if md.volume_moves > 0 {
+                    println!(
+                        "Accepted {} of {} volume moves",
+                        md.volume_accepted, md.volume_moves
+                    );
+                }
+                // The Monte Carlo barostat sees the jump of the truncated
+                // energy at the cutoff, which adds the impulsive term
+                // 2 pi / 3 rho^2 r_c^3 u(r_c) to the virial pressure
+                let p_imp = if let Barostat::MonteCarlo { .. } = barostat {
+                    let sr6 = (2f64.powf(-1.0 / 6.0) / md.r_cut).powi(6);
+                    let u_c = 4.0 * (sr6 * sr6 - sr6);
+                    2.0 * std::f64::consts::PI / 3.0
+                        * rho
+                        * rho
+                        * md.r_cut.powi(3)
+                        * u_c
+                } else {
+                    0.0
+                };
+                println!("<P> with impulsive correction = {}", avg[0] + p_imp);
+                assert!((avg[0] + p_imp - pressure).abs() < 4.0 * p_err);
+
+                // The barostat survives a restart bit for bit
+                let path = std::env::temp_dir().join("md-npt.bin");
+                md.save_file(&path).unwrap();
+                let mut md2 = Md::load_file(&path).unwrap();
+                assert_eq!(md2.barostat, md.barostat);
+                md.run(50);
+                md2.run(50);
+                assert_eq!(md.sys.box_l, md2.sys.box_l);
+                assert_eq!(md.e_pot.to_bits(), md2.e_pot.to_bits());
+                assert_eq!(md.volume_accepted, md2.volume_accepted);
+            }
+        }
+        "parallel-tempering" => {
+            use mc::Mc;
+            use md::*;
+            use tempering::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let k: usize = args.next().unwrap().parse().unwrap();
+            let exchanges: usize = args.next().unwrap().parse().unwrap();
+
+            let a = 1.1;
+            let box_l = [a * n as f64; 3];
+            let r = lennard_jones_t::setup_cubic_lattice(n, a);
+            let n_atoms = r[0].len() as f64;
+            let ladder = geometric_ladder(0.6, 1.6, k);
+
+            // The MC moves use the minimum image
+            let r_cut = f64::min(2.5, box_l[0] / 2.0);
+
+            fn report<R: Replica>(
+                name: &str,
+                pt: &ParallelTempering<R>,
+                n_atoms: f64,
+            ) {
+                println!("{name}: T \t <U>/N \t\t C_v/N \t\t swap");
+                let acc = pt.acceptance();
+                for (i, t) in pt.temperatures.iter().enumerate() {
+                    println!(
+                        "{t:.4} \t {:.6} \t {:.6} \t {}",
+                        pt.mean_energy(i) / n_atoms,
+                        pt.heat_capacity(i) / n_atoms,
+                        acc.get(i).map_or(String::new(), |a| format!("{a:.3}"))
+                    );
+                }
+                println!("Replicas by temperature: {:?}", pt.ids);
+            }
+
+            let replicas: Vec<_> = ladder
+                .iter()
+                .enumerate()
+                .map(|(i, &t)| {
+                    let r = r.clone();
+                    let mut mc =
+                        Mc::new(box_l, r, 1.0, 1.0, r_cut, t, i as u64);
+                    mc.max_disp = 0.15;
+                    mc
+                })
+                .collect();
+            let mut pt = ParallelTempering::new(replicas, 5, 42);
+
+            let t = Instant::now();
+            pt.run(exchanges / 5);
+            pt.reset_observables();
+            pt.run(exchanges);
+            println!("MC replicas took {:?}", t.elapsed());
+            report("MC", &pt, n_atoms);
+
+            for (i, mc) in pt.replicas.iter().enumerate() {
+                assert_eq!(mc.temperature, pt.temperatures[i]);
+                assert!((mc.e_pot - mc.energy()).abs() < 1e-8 * n_atoms);
+            }
+            for i in 1..k {
+                assert!(pt.mean_energy(i) > pt.mean_energy(i - 1));
+            }
+
+            // MD replicas with the velocities rescaled on every swap
+            let thermostat = Thermostat::Langevin {
+                temperature: 1.0,
+                gamma: 1.0,
+            };
+            let replicas: Vec<_> = ladder
+                .iter()
+                .enumerate()
+                .map(|(i, &t)| {
+                    let sys = System::new(box_l, r.clone());
+                    let mut md = Md::with_seed(
+                        sys, 1.0, 1.0, r_cut, 0.005, thermostat, i as u64,
+                    );
+                    md.set_temperature(t);
+                    md.init_velocities(t);
+                    md
+                })
+                .collect();
+            let mut pt = ParallelTempering::new(replicas, 50, 42);
+
+            let t = Instant::now();
+            pt.run(exchanges / 5);
+            pt.reset_observables();
+            pt.run(exchanges);
+            println!("MD replicas took {:?}", t.elapsed());
+            report("MD", &pt, n_atoms);
+
+            for (i, md) in pt.replicas.iter().enumerate() {
+                let t = md.thermostat_temperature();
+                assert_eq!(t, Some(pt.temperatures[i]));
+            }
+            for i in 1..k {
+                assert!(pt.mean_energy(i) > pt.mean_energy(i - 1));
+            }
+
+            // Swaps between equal temperatures are always accepted
+            let replicas: Vec<_> = (0..4)
+                .map(|i| Mc::new(box_l, r.clone(), 1.0, 1.0, r_cut, 1.0, i))
+                .collect();
+            let mut pt = ParallelTempering::new(replicas, 1, 0);
+            pt.run(10);
+            assert_eq!(pt.swap_accepted, pt.swap_attempts);
+        }
+        "gcmc-widom" => {
+            use mc::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let sweeps: usize = args.next().unwrap().parse().unwrap();
+
+            let temperature = 2.0;
+            let rho: f64 = 0.5;
+            let a = rho.powf(-1.0 / 3.0);
+            let l = a * n as f64;
+            let box_l = [l; 3];
+            let volume = l.powi(3);
+
+            // An ideal gas has <N> = z V
+            let z = 50.0 / volume;
+            let empty = || [(); 3].map(|_| Vec::new());
+            let mc = Mc::new(box_l, empty(), 1.0, 0.0, 2.5, temperature, 1);
+            let mut gcmc = Gcmc::new(mc, z);
+            gcmc.mc.reserve(200);
+
+            let mut n_avg = 0.0;
+            for _ in 0..sweeps {
+                gcmc.run(100);
+                n_avg += gcmc.mc.len() as f64 / sweeps as f64;
+            }
+            println!("Ideal gas: <N> = {n_avg:.3}, z V = {:.3}", z * volume);
+            assert!((n_avg / (z * volume) - 1.0).abs() < 0.1);
+
+            // Widom insertion in the canonical ensemble at rho
+            let r = lennard_jones_t::setup_cubic_lattice(n, a);
+            let mut mc = Mc::new(box_l, r, 1.0, 1.0, 2.5, temperature, 2);
+            mc.max_disp = 0.3;
+            mc.run(sweeps / 5);
+
+            let mut widom = Widom::default();
+            let t = Instant::now();
+            for _ in 0..sweeps {
+                mc.sweep();
+                widom.sample(&mut mc, 200);
+            }
+            let mu_ex = widom.excess_chemical_potential(temperature);
+            println!(
+                "NVT rho = {rho}, T = {temperature}: mu_ex = {mu_ex:.4} from \
+                 {} insertions, acceptance {:.3}, took {:?}",
+                widom.samples,
+                mc.acceptance(),
+                t.elapsed()
+            );
+            assert!((mc.e_pot - mc.energy()).abs() < 1e-8 * mc.len() as f64);
+
+            // The grand canonical ensemble at that chemical potential gives
+            // back the density, starting from an empty box
+            let mu = temperature * rho.ln() + mu_ex;
+            let mc = Mc::new(box_l, empty(), 1.0, 1.0, 2.5, temperature, 3);
+            let mut gcmc = Gcmc::new(mc, Gcmc::activity_from(mu, temperature));
+            gcmc.mc.max_disp = 0.3;
+            gcmc.mc.reserve(2 * n.pow(3));
+            let capacity = gcmc.mc.x.capacity();
+
+            let steps = 4 * n.pow(3);
+            let t = Instant::now();
+            gcmc.run(sweeps / 5 * steps);
+            let mut rho_avg = 0.0;
+            for _ in 0..sweeps {
+                gcmc.run(steps);
+                rho_avg += gcmc.density() / sweeps as f64;
+            }
+            println!(
+                "GCMC at mu = {mu:.4}: <rho> = {rho_avg:.4}, accepted {} of \
+                 {} insertions and {} of {} deletions, took {:?}",
+                gcmc.insertions[1],
+                gcmc.insertions[0],
+                gcmc.deletions[1],
+                gcmc.deletions[0],
+                t.elapsed()
+            );
+            assert_eq!(gcmc.mc.x.capacity(), capacity);
+            let e = gcmc.mc.energy();
+            assert!((gcmc.mc.e_pot - e).abs() < 1e-8 * gcmc.mc.len() as f64);
+            assert!((rho_avg / rho - 1.0).abs() < 0.05);
+        }
+        "gradient-check" => {
+            use gradient_check::*;
+            use lennard_jones_t::GradWorkspace;
+            use storage::{Aos, ParticleStorage, Soa};
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+
+            let mut rng = rand::thread_rng();
+            let mut r = lennard_jones::setup_cubic_lattice(n, 1.1);
+            for x in r.iter_mut().flatten() {
+                *x += rng.gen_range(-0.1..0.1);
+            }
+            let len = r.len();
+            let h = 2.0f64.powi(-10);
+
+            let report = |name: &str, err: GradientError<f64>| {
+                println!(
+                    "{name:>28}: worst atom {} component {}, analytic \
+                     {:.10e}, numeric {:.10e}, relative error {:.2e}",
+                    err.atom,
+                    err.component,
+                    err.analytic,
+                    err.numeric,
+                    err.relative
+                );
+            };
+
+            let ra = Aos::from_aos(&r);
+            let mut ga = Aos::from_aos(&r);
+            lennard_jones::lennard_jones_grad::<8, _>(1.0, 1.0, &mut ga.0, &r);
+            let err = check_gradient(&ra, &ga, h, |r: &Aos<f64, 3>| {
+                lennard_jones::lennard_jones_naive(1.0, 1.0, &r.0)
+            });
+            report("lennard_jones::grad", err);
+
+            // The same check has to find a wrong component
+            ga.add(len / 3, [0.0, 1e-4, 0.0]);
+            let err = check_gradient(&ra, &ga, h, |r: &Aos<f64, 3>| {
+                lennard_jones::lennard_jones_naive(1.0, 1.0, &r.0)
+            });
+            report("perturbed", err);
+
+            let energy = |r: &Soa<f64, 3>| {
+                let [x, y, z] = &r.0;
+                lennard_jones_t::lennard_jones::<8, _>(1.0, 1.0, x, y, z)
+            };
+
+            let rs = Soa::from_aos(&r);
+            let mut gs = Soa::from_aos(&r);
+            let [x, y, z] = &rs.0;
+            let [gx, gy, gz] = &mut gs.0;
+            lennard_jones_t::lennard_jones_grad::<8, _>(
+                1.0, 1.0, x, y, z, gx, gy, gz,
+            );
+            let err = check_gradient(&rs, &gs, h, energy);
+            report("lennard_jones_t::grad", err);
+
+            let mut ws = GradWorkspace::new();
+            gs.clear();
+            let [gx, gy, gz] = &mut gs.0;
+            lennard_jones_t::lennard_jones_grad_par::<8, _>(
+                1.0, 1.0, x, y, z, gx, gy, gz, &mut ws,
+            );
+            let err = check_gradient(&rs, &gs, h, energy);
+            report("lennard_jones_t::grad_par", err);
+        }
+        "observables" => {
+            use md::*;
+            use observables::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            // An AR(1) process with unit variance has the statistical
+            // inefficiency (1 + phi) / (1 - phi)
+            let phi: f64 = 0.9;
+            let samples = 1 << 20;
+            let mut rng = rand::thread_rng();
+            let mut blocking = Blocking::new();
+            let mut fluctuation = Fluctuation::new();
+            let mut x = gaussian(&mut rng);
+            for _ in 0..samples {
+                blocking.push(x);
+                fluctuation.push(x);
+                x = phi * x + (1.0 - phi * phi).sqrt() * gaussian(&mut rng);
+            }
+
+            println!("block size \t error \t\t uncertainty");
+            for (b, e, de) in blocking.errors().iter().take(14) {
+                println!("{b} \t\t {e:.3e} \t {de:.3e}");
+            }
+
+            let s = (1.0 + phi) / (1.0 - phi);
+            let exact = (s / samples as f64).sqrt();
+            let mean = blocking.estimate();
+            let var = fluctuation.variance();
+            let tau = blocking.correlation_time();
+            println!(
+                "AR(1): mean {:.5} +- {:.5} (exact error {exact:.5}), tau \
+                 {tau:.3} (exact {:.3}), variance {:.4} +- {:.4}",
+                mean.value,
+                mean.error,
+                (s - 1.0) / 2.0,
+                var.value,
+                var.error
+            );
+            assert!(mean.value.abs() < 5.0 * exact);
+            assert!((mean.error / exact - 1.0).abs() < 0.2);
+            assert!((tau / ((s - 1.0) / 2.0) - 1.0).abs() < 0.25);
+            assert!((var.value - 1.0).abs() < 5.0 * var.error);
+
+            let a = 1.1;
+            let sys = System::new(
+                [a * n as f64; 3],
+                lennard_jones_t::setup_cubic_lattice(n, a),
+            );
+            let run = |temperature: f64, barostat: Barostat| {
+                let thermostat = Thermostat::Langevin {
+                    temperature,
+                    gamma: 1.0,
+                };
+                let mut md = Md::with_seed(
+                    sys.clone(),
+                    1.0,
+                    1.0,
+                    2.5,
+                    0.005,
+                    thermostat,
+                    42,
+                );
+                md.init_velocities(temperature);
+                md.set_barostat(barostat);
+                md.run(steps / 5);
+
+                let mut obs = Observables::new();
+                for _ in 0..steps / 5 {
+                    md.run(5);
+                    obs.sample(&md);
+                }
+                obs
+            };
+            let check = |name: &str, a: Estimate, b: Estimate| {
+                println!(
+                    "{name}: {:.4} +- {:.4} from fluctuations, {:.4} +- {:.4} \
+                     from finite differences",
+                    a.value, a.error, b.value, b.error
+                );
+                let err = a.error.hypot(b.error);
+                assert!((a.value - b.value).abs() < 4.0 * err);
+            };
+
+            // C_V from energy fluctuations against d<E>/dT
+            let (t, dt) = (2.0, 0.2);
+            let obs = [t - dt, t, t + dt].map(|t| run(t, Barostat::None));
+            let o = &obs[1];
+            let [tm, pm, um, em] =
+                [&o.temperature, &o.pressure, &o.potential, &o.energy]
+                    .map(|b| b.estimate());
+            println!(
+                "NVT T = {t}: <T> = {:.4} +- {:.4}, <P> = {:.4} +- {:.4}, \
+                 <U> = {:.3} +- {:.3}, <E> = {:.3} +- {:.3} with tau_E = {:.1} \
+                 samples",
+                tm.value,
+                tm.error,
+                pm.value,
+                pm.error,
+                um.value,
+                um.error,
+                em.value,
+                em.error,
+                o.energy.correlation_time()
+            );
+            assert!((tm.value - t).abs() < 4.0 * tm.error + 0.01 * t);
+
+            let [lo, hi] = [&obs[0], &obs[2]].map(|o| o.energy.estimate());
+            let fd = Estimate {
+                value: (hi.value - lo.value) / (2.0 * dt),
+                error: hi.error.hypot(lo.error) / (2.0 * dt),
+            };
+            check("C_V", o.heat_capacity(t), fd);
+
+            // kappa_T from volume fluctuations against -d ln<V>/dP
+            let (p, dp) = (1.0, 0.2);
+            let obs = [p - dp, p, p + dp].map(|pressure| {
+                run(
+                    t,
+                    Barostat::MonteCarlo {
+                        pressure,
+                        temperature: t,
+                        max_dlnv: 0.03,
+                        interval: 5,
+                    },
+                )
+            });
+            let [lo, v, hi] =
+                [&obs[0], &obs[1], &obs[2]].map(|o| o.volume.estimate());
+            println!(
+                "NPT P = {p}: <V> = {:.2} +- {:.2} with tau_V = {:.1} samples",
+                v.value,
+                v.error,
+                obs[1].volume.correlation_time()
+            );
+            let fd = Estimate {
+                value: -(hi.value - lo.value) / (2.0 * dp * v.value),
+                error: hi.error.hypot(lo.error) / (2.0 * dp * v.value),
+            };
+            check("kappa_T", obs[1].compressibility(t), fd);
+        }
+        "lennard-jones-tail" => {
+            use md::*;
+
+            let n: usize = args.next().unwrap().parse().unwrap();
+            let steps: usize = args.next().unwrap().parse().unwrap();
+
+            let a = 1.1;
+            let box_l = [a * n as f64; 3];
+            let thermostat = Thermostat::Langevin {
+                temperature: 1.5,
+                gamma: 1.0,
+            };
+
+            let sys =
+                System::new(box_l, lennard_jones_t::setup_cubic_lattice(n, a));
+            let mut md =
+                Md::with_seed(sys, 1.0, 1.0, 2.5, 0.005, thermostat, 42);
+            md.init_velocities(1.5);
+            md.run(steps);
+
+            let n_atoms = md.sys.len() as f64;
+            let sigma = 2f64.powf(-1.0 / 6.0);
+            println!(
+                "{n_atoms} particles at rho sigma^3 = {}, T = {}",
+                n_atoms * sigma.powi(3) / md.volume(),
+                md.temperature()
+            );
+            println!("r_cut \t E/N \t\t\t E/N tail \t\t P \t\t\t P tail");
+
+            // The same configuration with increasing cutoffs, where the
+            // corrected values should depend much less on the cutoff
+            let mut results = Vec::new();
+            for r_cut in [2.5, 3.0, 3.5, 4.0] {
+                let mut md = Md::with_seed(
+                    md.sys.clone(),
+                    1.0,
+                    1.0,
+                    r_cut,
+                    0.005,
+                    thermostat,
+                    0,
+                );
+                let (e, p) = (md.e_pot / n_atoms, md.pressure());
+
+                md.set_tail_correction(true);
+                let (e_tail, p_tail) = (md.e_pot / n_atoms, md.pressure());
+
+                println!("{r_cut} \t {e} \t {e_tail} \t {p} \t {p_tail}");
+                results.push([e, e_tail, p, p_tail]);
+            }
+
+            let spread = |k: usize| {
+                let v = results.iter().map(|r| r[k]);
+                v.clone().fold(f64::MIN, f64::max) - v.fold(f64::MAX, f64::min)
+            };
+            println!("Spread of E/N: {} -> {}", spread(0), spread(1));
+            println!("Spread of P:   {} -> {}", spread(2), spread(3));
+            assert!(spread(1) < spread(0) / 3.0);
+            assert!(spread(3) < spread(2) / 3.0);
+
+            // A mixture of two species with identical parameters gives the
+            // single component correction
+            let mut species = md.sys.species.clone();
+            for s in species.iter_mut().step_by(3) {
+                *s = 1;
+            }
+            let single = md.tail_corrections();
+            let mixed = tail::tail_corrections(
+                |_, _| (1.0, 1.0),
+                md.r_cut,
+                &species,
+                md.volume(),
+            );
+            assert!((single.0 - mixed.0).abs() < 1e-12 * single.0.abs());
+            assert!((single.1 - mixed.1).abs() < 1e-12 * single.1.abs());
+
+            // The switch survives a restart
+            md.set_tail_correction(true);
+            let path = std::env::temp_dir().join("md-tail.bin");
+            md.save_file(&path).unwrap();
+            let md2 = Md::load_file(&path).unwrap();
+            assert!(md2.tail_correction);
+            assert_eq!(md.e_pot.to_bits(), md2.e_pot.to_bits());
+            assert_eq!(md.pressure().to_bits(), md2.pressure().to_bits());
+        }
         "transpose-u8-8" => {
             use transpose_u8::{naive, transpose_64x8_u8};
             use rand::thread_rng;
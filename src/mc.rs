@@ -0,0 +1,435 @@
+use std::{
+    ops::{Add, AddAssign, Div, Mul, Sub},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::lennard_jones_t::sigma2;
+
+fn min_image<T: Float>(d: T, l: T) -> T {
+    d - (d / l).round() * l
+}
+
+// Energy of a particle at p with every particle in r under the minimum image
+// convention, truncated (and not shifted) at r_cut like the cell list kernels
+pub fn particle_energy<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    box_l: [T; 3],
+    r: [&[T]; 3],
+    p: [T; 3],
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    for q in 1..3 {
+        assert_eq!(r[q].len(), r[0].len());
+    }
+
+    let s2 = sigma2(r_eq);
+    let rc2 = r_cut * r_cut;
+
+    let s2s = Simd::splat(s2);
+    let rc2s = Simd::splat(rc2);
+    let inf = Simd::splat(T::infinity());
+    let ls = box_l.map(Simd::splat);
+    let ps = p.map(Simd::splat);
+
+    let (rcs, rr): ([&[[T; N]]; 3], [&[T]; 3]) = {
+        let [x, y, z] = r.map(|r| r.as_chunks::<N>());
+        ([x.0, y.0, z.0], [x.1, y.1, z.1])
+    };
+
+    let mut es = Simd::splat(T::zero());
+    for ((x, y), z) in rcs[0].iter().zip(rcs[1]).zip(rcs[2]) {
+        let mut r2 = Simd::splat(T::zero());
+        for ((&r, p), l) in [x, y, z].into_iter().zip(ps).zip(ls) {
+            let d = Simd::from(r) - p;
+            let d = d - (d / l).round() * l;
+            r2 += d * d;
+        }
+        // Outside the cutoff every term vanishes at an infinite distance
+        let r2 = r2.simd_lt(rc2s).select(r2, inf);
+
+        let sr2 = s2s / r2;
+        let sr6 = sr2 * sr2 * sr2;
+        es += sr6 * sr6 - sr6;
+    }
+
+    let mut e = es.reduce_sum();
+    for ((&x, &y), &z) in rr[0].iter().zip(rr[1]).zip(rr[2]) {
+        let mut r2 = T::zero();
+        for ((r, p), l) in [x, y, z].into_iter().zip(p).zip(box_l) {
+            let d = min_image(r - p, l);
+            r2 += d * d;
+        }
+        if r2 < rc2 {
+            let sr6 = (s2 / r2).powi(3);
+            e += sr6 * sr6 - sr6;
+        }
+    }
+
+    e * T::from(4.0).unwrap() * e_b
+}
+
+// Energy of particle i at p with every other particle
+pub fn particle_energy_except<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    box_l: [T; 3],
+    r: [&[T]; 3],
+    i: usize,
+    p: [T; 3],
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let [x, y, z] = r;
+    let before = [&x[..i], &y[..i], &z[..i]];
+    let after = [&x[i + 1..], &y[i + 1..], &z[i + 1..]];
+
+    particle_energy::<N, _>(r_eq, e_b, r_cut, box_l, before, p)
+        + particle_energy::<N, _>(r_eq, e_b, r_cut, box_l, after, p)
+}
+
+// Metropolis Monte Carlo in the canonical ensemble with single particle
+// displacements of at most max_disp along every axis, for unit k_B. max_disp
+// starts at a tenth of r_eq.
+pub struct Mc {
+    pub box_l: [f64; 3],
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub z: Vec<f64>,
+    pub r_eq: f64,
+    pub e_b: f64,
+    pub r_cut: f64,
+    pub temperature: f64,
+    pub max_disp: f64,
+    pub rng: ChaCha8Rng,
+    pub e_pot: f64,
+    pub attempts: u64,
+    pub accepted: u64,
+}
+
+impl Mc {
+    pub fn new(
+        box_l: [f64; 3],
+        [x, y, z]: [Vec<f64>; 3],
+        r_eq: f64,
+        e_b: f64,
+        r_cut: f64,
+        temperature: f64,
+        seed: u64,
+    ) -> Self {
+        assert_eq!(x.len(), y.len());
+        assert_eq!(x.len(), z.len());
+        for l in box_l {
+            assert!(2.0 * r_cut <= l, "r_cut must be at most half the box");
+        }
+
+        let mut mc = Self {
+            box_l,
+            x,
+            y,
+            z,
+            r_eq,
+            e_b,
+            r_cut,
+            temperature,
+            max_disp: 0.1 * r_eq,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            e_pot: 0.0,
+            attempts: 0,
+            accepted: 0,
+        };
+        mc.e_pot = mc.energy();
+
+        mc
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.box_l.iter().product()
+    }
+
+    pub fn positions(&self) -> [&[f64]; 3] {
+        [&self.x, &self.y, &self.z]
+    }
+
+    pub fn position(&self, i: usize) -> [f64; 3] {
+        [self.x[i], self.y[i], self.z[i]]
+    }
+
+    // Energy of particle i moved to p
+    pub fn energy_of(&self, i: usize, p: [f64; 3]) -> f64 {
+        particle_energy_except::<8, _>(
+            self.r_eq,
+            self.e_b,
+            self.r_cut,
+            self.box_l,
+            self.positions(),
+            i,
+            p,
+        )
+    }
+
+    // Total energy from scratch, every particle against those before it
+    pub fn energy(&self) -> f64 {
+        (0..self.len())
+            .map(|i| {
+                let [x, y, z] = self.positions();
+                particle_energy::<8, _>(
+                    self.r_eq,
+                    self.e_b,
+                    self.r_cut,
+                    self.box_l,
+                    [&x[..i], &y[..i], &z[..i]],
+                    self.position(i),
+                )
+            })
+            .sum()
+    }
+
+    fn accept(&mut self, de: f64) -> bool {
+        de <= 0.0 || self.rng.gen::<f64>() < (-de / self.temperature).exp()
+    }
+
+    pub fn displace(&mut self) -> bool {
+        let i = self.rng.gen_range(0..self.len());
+        let old = self.position(i);
+
+        let mut new = old;
+        for (q, r) in new.iter_mut().enumerate() {
+            *r += self.max_disp * (2.0 * self.rng.gen::<f64>() - 1.0);
+            *r -= (*r / self.box_l[q]).floor() * self.box_l[q];
+        }
+
+        let de = self.energy_of(i, new) - self.energy_of(i, old);
+
+        self.attempts += 1;
+        let accepted = self.accept(de);
+        if accepted {
+            self.x[i] = new[0];
+            self.y[i] = new[1];
+            self.z[i] = new[2];
+            self.e_pot += de;
+            self.accepted += 1;
+        }
+
+        accepted
+    }
+
+    // One attempted displacement per particle
+    pub fn sweep(&mut self) {
+        for _ in 0..self.len() {
+            self.displace();
+        }
+    }
+
+    pub fn run(&mut self, sweeps: usize) {
+        for _ in 0..sweeps {
+            self.sweep();
+        }
+    }
+
+    pub fn acceptance(&self) -> f64 {
+        self.accepted as f64 / self.attempts.max(1) as f64
+    }
+
+    // Room for n more particles, so that insertions up to that number never
+    // reallocate. Deletions keep the capacity.
+    pub fn reserve(&mut self, n: usize) {
+        self.x.reserve(n);
+        self.y.reserve(n);
+        self.z.reserve(n);
+    }
+
+    pub fn random_position(&mut self) -> [f64; 3] {
+        self.box_l.map(|l| l * self.rng.gen::<f64>())
+    }
+
+    // Energy of a new particle at p with every particle
+    pub fn insertion_energy(&self, p: [f64; 3]) -> f64 {
+        particle_energy::<8, _>(
+            self.r_eq,
+            self.e_b,
+            self.r_cut,
+            self.box_l,
+            self.positions(),
+            p,
+        )
+    }
+
+    // Boltzmann factor exp(-dU / T) of a test particle at a random position,
+    // which is not inserted
+    pub fn widom_insertion(&mut self) -> f64 {
+        let p = self.random_position();
+        (-self.insertion_energy(p) / self.temperature).exp()
+    }
+
+    fn push(&mut self, p: [f64; 3]) {
+        self.x.push(p[0]);
+        self.y.push(p[1]);
+        self.z.push(p[2]);
+    }
+
+    // Moves the last particle into slot i
+    fn swap_remove(&mut self, i: usize) {
+        self.x.swap_remove(i);
+        self.y.swap_remove(i);
+        self.z.swap_remove(i);
+    }
+}
+
+// Widom test particle insertion, mu_ex = -T ln <exp(-dU / T)>
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Widom {
+    pub samples: u64,
+    pub sum: f64,
+}
+
+impl Widom {
+    pub fn sample(&mut self, mc: &mut Mc, insertions: usize) {
+        for _ in 0..insertions {
+            self.sum += mc.widom_insertion();
+            self.samples += 1;
+        }
+    }
+
+    pub fn excess_chemical_potential(&self, temperature: f64) -> f64 {
+        -temperature * (self.sum / self.samples as f64).ln()
+    }
+}
+
+// Grand canonical Monte Carlo at fixed activity z = exp(mu / T) / Lambda^3.
+// Insertions at a random position and deletions of a random particle are
+// accepted with probabilities
+//
+//   min(1, z V / (N + 1) exp(-dU / T))    and    min(1, N / (z V) exp(-dU / T))
+pub struct Gcmc {
+    pub mc: Mc,
+    pub activity: f64,
+    // Attempted and accepted moves
+    pub insertions: [u64; 2],
+    pub deletions: [u64; 2],
+}
+
+impl Gcmc {
+    pub fn new(mc: Mc, activity: f64) -> Self {
+        Self {
+            mc,
+            activity,
+            insertions: [0; 2],
+            deletions: [0; 2],
+        }
+    }
+
+    // Activity for the chemical potential mu with Lambda = 1, which splits
+    // into the ideal gas part T ln(rho) and the excess part from Widom
+    // insertion
+    pub fn activity_from(mu: f64, temperature: f64) -> f64 {
+        (mu / temperature).exp()
+    }
+
+    fn metropolis(&mut self, ratio: f64) -> bool {
+        ratio >= 1.0 || self.mc.rng.gen::<f64>() < ratio
+    }
+
+    pub fn insert(&mut self) -> bool {
+        let mc = &mut self.mc;
+        let p = mc.random_position();
+        let de = mc.insertion_energy(p);
+
+        let ratio = self.activity * mc.volume() / (mc.len() + 1) as f64
+            * (-de / mc.temperature).exp();
+
+        self.insertions[0] += 1;
+        let accepted = self.metropolis(ratio);
+        if accepted {
+            self.mc.push(p);
+            self.mc.e_pot += de;
+            self.insertions[1] += 1;
+        }
+
+        accepted
+    }
+
+    pub fn delete(&mut self) -> bool {
+        let mc = &mut self.mc;
+        if mc.is_empty() {
+            return false;
+        }
+
+        let i = mc.rng.gen_range(0..mc.len());
+        let de = -mc.energy_of(i, mc.position(i));
+
+        let ratio = mc.len() as f64 / (self.activity * mc.volume())
+            * (-de / mc.temperature).exp();
+
+        self.deletions[0] += 1;
+        let accepted = self.metropolis(ratio);
+        if accepted {
+            self.mc.swap_remove(i);
+            self.mc.e_pot += de;
+            self.deletions[1] += 1;
+        }
+
+        accepted
+    }
+
+    // A displacement with probability 1/2, otherwise an insertion or a
+    // deletion with equal probability
+    pub fn step(&mut self) {
+        let u: f64 = self.mc.rng.gen();
+        if u < 0.5 {
+            if !self.mc.is_empty() {
+                self.mc.displace();
+            }
+        } else if u < 0.75 {
+            self.insert();
+        } else {
+            self.delete();
+        }
+    }
+
+    pub fn run(&mut self, steps: usize) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    pub fn density(&self) -> f64 {
+        self.mc.len() as f64 / self.mc.volume()
+    }
+}
@@ -0,0 +1,795 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{
+    bonded::Topology,
+    cell_list::{
+        lennard_jones_grad_cells, lennard_jones_virial_cells, wrap_positions,
+        CellList,
+    },
+    constraints::Constraints,
+    external::{external_grad, External},
+    tail::tail_corrections,
+};
+
+#[derive(Clone)]
+pub struct System {
+    pub box_l: [f64; 3],
+    pub x: Vec<f64>,
+    pub y: Vec<f64>,
+    pub z: Vec<f64>,
+    pub vx: Vec<f64>,
+    pub vy: Vec<f64>,
+    pub vz: Vec<f64>,
+    pub species: Vec<u8>,
+}
+
+impl System {
+    pub fn new(box_l: [f64; 3], [x, y, z]: [Vec<f64>; 3]) -> Self {
+        let n = x.len();
+
+        assert_eq!(n, y.len());
+        assert_eq!(n, z.len());
+
+        Self {
+            box_l,
+            x,
+            y,
+            z,
+            vx: vec![0.0; n],
+            vy: vec![0.0; n],
+            vz: vec![0.0; n],
+            species: vec![0; n],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.x.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.x.is_empty()
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Thermostat {
+    None,
+    Langevin { temperature: f64, gamma: f64 },
+    NoseHoover { temperature: f64, q: f64, xi: f64 },
+}
+
+// Isotropic barostats acting on the box and the positions at the end of every
+// step. Berendsen scales the box by (1 - compressibility dt / tau (pressure -
+// P))^(1/3). The Monte Carlo barostat attempts a random walk in ln V of at
+// most max_dlnv every interval steps, which is accepted with probability
+// min(1, exp(-(dU + pressure dV - (N + 1) T ln(V' / V)) / T)). It samples the
+// truncated energy including its jump at the cutoff, so its virial pressure
+// differs from the target by the impulsive term 2 pi / 3 rho^2 r_c^3 u(r_c).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Barostat {
+    None,
+    Berendsen {
+        pressure: f64,
+        tau: f64,
+        compressibility: f64,
+    },
+    MonteCarlo {
+        pressure: f64,
+        temperature: f64,
+        max_dlnv: f64,
+        interval: u64,
+    },
+}
+
+pub fn gaussian<R: Rng>(rng: &mut R) -> f64 {
+    let u1: f64 = 1.0 - rng.gen::<f64>();
+    let u2: f64 = rng.gen();
+    (-2.0 * u1.ln()).sqrt() * (std::f64::consts::TAU * u2).cos()
+}
+
+// Velocity Verlet for unit masses with k_B = 1, using the cell list kernel for
+// the forces. Everything is serial, so a run is reproducible bit for bit from
+// the same state.
+pub struct Md {
+    pub sys: System,
+    pub r_eq: f64,
+    pub e_b: f64,
+    pub r_cut: f64,
+    pub dt: f64,
+    pub thermostat: Thermostat,
+    pub topology: Option<Topology<f64>>,
+    // Acts on the wrapped positions, so walls should keep the particles away
+    // from the periodic boundary along their axis
+    pub external: Vec<External<f64>>,
+    pub constraints: Option<Constraints>,
+    pub constraint_virial: f64,
+    // Adds the homogeneous tail corrections to e_pot and the pressure. Set it
+    // with set_tail_correction so that e_pot is updated.
+    pub tail_correction: bool,
+    // Positions are scaled as atoms, so a barostat can not be combined with
+    // constraints. Set it with set_barostat.
+    pub barostat: Barostat,
+    pub volume_moves: u64,
+    pub volume_accepted: u64,
+    pub rng: ChaCha8Rng,
+    pub step: u64,
+    pub e_pot: f64,
+    cells: CellList<f64>,
+    gx: Vec<f64>,
+    gy: Vec<f64>,
+    gz: Vec<f64>,
+    r_old: [Vec<f64>; 3],
+}
+
+impl Md {
+    pub fn new(
+        sys: System,
+        r_eq: f64,
+        e_b: f64,
+        r_cut: f64,
+        dt: f64,
+        thermostat: Thermostat,
+        rng: ChaCha8Rng,
+    ) -> Self {
+        let n = sys.len();
+
+        let mut md = Self {
+            cells: CellList::new(sys.box_l, r_cut),
+            sys,
+            r_eq,
+            e_b,
+            r_cut,
+            dt,
+            thermostat,
+            topology: None,
+            external: Vec::new(),
+            constraints: None,
+            constraint_virial: 0.0,
+            tail_correction: false,
+            barostat: Barostat::None,
+            volume_moves: 0,
+            volume_accepted: 0,
+            rng,
+            step: 0,
+            e_pot: 0.0,
+            gx: vec![0.0; n],
+            gy: vec![0.0; n],
+            gz: vec![0.0; n],
+            r_old: [vec![0.0; n], vec![0.0; n], vec![0.0; n]],
+        };
+
+        md.compute_forces();
+
+        md
+    }
+
+    pub fn with_seed(
+        sys: System,
+        r_eq: f64,
+        e_b: f64,
+        r_cut: f64,
+        dt: f64,
+        thermostat: Thermostat,
+        seed: u64,
+    ) -> Self {
+        let rng = ChaCha8Rng::seed_from_u64(seed);
+        Self::new(sys, r_eq, e_b, r_cut, dt, thermostat, rng)
+    }
+
+    pub fn set_topology(&mut self, topology: Topology<f64>) {
+        self.topology = Some(topology);
+        self.compute_forces();
+    }
+
+    pub fn set_external(&mut self, external: Vec<External<f64>>) {
+        self.external = external;
+        self.compute_forces();
+    }
+
+    pub fn set_tail_correction(&mut self, tail_correction: bool) {
+        self.tail_correction = tail_correction;
+        self.compute_forces();
+    }
+
+    pub fn set_barostat(&mut self, barostat: Barostat) {
+        assert!(
+            self.constraints.is_none() || barostat == Barostat::None,
+            "barostats do not support constraints"
+        );
+        if let Barostat::MonteCarlo { interval, .. } = barostat {
+            assert!(interval > 0, "the volume move interval must be positive");
+        }
+        self.barostat = barostat;
+    }
+
+    // The current positions must already satisfy the constraints, velocities
+    // are projected onto them
+    pub fn set_constraints(&mut self, constraints: Constraints) {
+        assert!(
+            self.barostat == Barostat::None,
+            "barostats do not support constraints"
+        );
+        self.constraints = Some(constraints);
+        self.rattle();
+    }
+
+    pub fn init_velocities(&mut self, temperature: f64) {
+        let s = temperature.sqrt();
+        for v in [&mut self.sys.vx, &mut self.sys.vy, &mut self.sys.vz] {
+            for x in v.iter_mut() {
+                *x = s * gaussian(&mut self.rng);
+            }
+
+            let mean = v.iter().sum::<f64>() / v.len() as f64;
+            for x in v.iter_mut() {
+                *x -= mean;
+            }
+        }
+
+        self.rattle();
+    }
+
+    // Target temperature of the thermostat
+    pub fn thermostat_temperature(&self) -> Option<f64> {
+        match self.thermostat {
+            Thermostat::None => None,
+            Thermostat::Langevin { temperature, .. }
+            | Thermostat::NoseHoover { temperature, .. } => Some(temperature),
+        }
+    }
+
+    // Changes the target temperature of the thermostat and rescales the
+    // velocities by sqrt(T_new / T_old)
+    pub fn set_temperature(&mut self, t_new: f64) {
+        let t_old = match &mut self.thermostat {
+            Thermostat::None => panic!("the thermostat has no temperature"),
+            Thermostat::Langevin { temperature, .. }
+            | Thermostat::NoseHoover { temperature, .. } => {
+                std::mem::replace(temperature, t_new)
+            }
+        };
+
+        self.scale_velocities((t_new / t_old).sqrt());
+    }
+
+    pub fn kinetic_energy(&self) -> f64 {
+        [&self.sys.vx, &self.sys.vy, &self.sys.vz]
+            .into_iter()
+            .flat_map(|v| v.iter())
+            .map(|v| v * v)
+            .sum::<f64>()
+            / 2.0
+    }
+
+    pub fn degrees_of_freedom(&self) -> f64 {
+        let n_c = self.constraints.as_ref().map_or(0, |c| c.len());
+        (3 * self.sys.len() - 3 - n_c) as f64
+    }
+
+    pub fn temperature(&self) -> f64 {
+        2.0 * self.kinetic_energy() / self.degrees_of_freedom()
+    }
+
+    pub fn volume(&self) -> f64 {
+        self.sys.box_l.iter().product()
+    }
+
+    // Sum of r_ij . f_ij over the pair, bonded and constraint forces
+    pub fn virial(&self) -> f64 {
+        let System { box_l, x, y, z, .. } = &self.sys;
+
+        let mut w = lennard_jones_virial_cells::<8, _>(
+            self.r_eq,
+            self.e_b,
+            x,
+            y,
+            z,
+            &self.cells,
+        );
+
+        if let Some(topology) = &self.topology {
+            topology.energy::<8>(
+                self.r_eq,
+                self.e_b,
+                self.r_cut,
+                *box_l,
+                x,
+                y,
+                z,
+                None,
+                Some(&mut w),
+            );
+        }
+
+        w + self.constraint_virial
+    }
+
+    // Energy and pressure tail corrections, with the same parameters for every
+    // pair of species
+    pub fn tail_corrections(&self) -> (f64, f64) {
+        tail_corrections(
+            |_, _| (self.r_eq, self.e_b),
+            self.r_cut,
+            &self.sys.species,
+            self.volume(),
+        )
+    }
+
+    pub fn pressure(&self) -> f64 {
+        let p = (2.0 * self.kinetic_energy() + self.virial())
+            / (3.0 * self.volume());
+
+        if self.tail_correction {
+            p + self.tail_corrections().1
+        } else {
+            p
+        }
+    }
+
+    // K + U + P V, with P the target pressure of the barostat or the
+    // instantaneous pressure without one
+    pub fn enthalpy(&self) -> f64 {
+        let p = match self.barostat {
+            Barostat::None => self.pressure(),
+            Barostat::Berendsen { pressure, .. }
+            | Barostat::MonteCarlo { pressure, .. } => pressure,
+        };
+
+        self.kinetic_energy() + self.e_pot + p * self.volume()
+    }
+
+    pub fn gradient(&self) -> [&[f64]; 3] {
+        [&self.gx, &self.gy, &self.gz]
+    }
+
+    pub fn compute_forces(&mut self) {
+        let System { box_l, x, y, z, .. } = &mut self.sys;
+
+        wrap_positions(*box_l, x, y, z);
+
+        self.cells.build(x, y, z);
+        self.e_pot = lennard_jones_grad_cells::<8, _>(
+            self.r_eq,
+            self.e_b,
+            x,
+            y,
+            z,
+            &mut self.gx,
+            &mut self.gy,
+            &mut self.gz,
+            &self.cells,
+        );
+
+        if let Some(topology) = &self.topology {
+            self.e_pot += topology.energy::<8>(
+                self.r_eq,
+                self.e_b,
+                self.r_cut,
+                *box_l,
+                x,
+                y,
+                z,
+                Some((&mut self.gx, &mut self.gy, &mut self.gz)),
+                None,
+            );
+        }
+
+        if !self.external.is_empty() {
+            self.e_pot += external_grad::<8, _>(
+                &self.external,
+                x,
+                y,
+                z,
+                &mut self.gx,
+                &mut self.gy,
+                &mut self.gz,
+            );
+        }
+
+        if self.tail_correction {
+            self.e_pot += self.tail_corrections().0;
+        }
+    }
+
+    // Makes a new cell list only if the number of cells changes
+    fn set_box(&mut self, box_l: [f64; 3]) {
+        self.sys.box_l = box_l;
+
+        let n_cells = box_l.map(|l| (l / self.r_cut).floor() as usize);
+        if n_cells == self.cells.n_cells {
+            self.cells.box_l = box_l;
+        } else {
+            self.cells = CellList::new(box_l, self.r_cut);
+        }
+    }
+
+    // Scales the box and the positions by s and recomputes the forces
+    pub fn scale_box(&mut self, s: f64) {
+        self.set_box(self.sys.box_l.map(|l| l * s));
+        for r in [&mut self.sys.x, &mut self.sys.y, &mut self.sys.z] {
+            for r in r.iter_mut() {
+                *r *= s;
+            }
+        }
+
+        self.compute_forces();
+    }
+
+    fn berendsen(&mut self) {
+        if let Barostat::Berendsen {
+            pressure,
+            tau,
+            compressibility,
+        } = self.barostat
+        {
+            let mu = 1.0
+                - compressibility * self.dt / tau
+                    * (pressure - self.pressure());
+            self.scale_box(mu.cbrt());
+        }
+    }
+
+    fn volume_move(&mut self) {
+        if let Barostat::MonteCarlo {
+            pressure,
+            temperature,
+            max_dlnv,
+            interval,
+        } = self.barostat
+        {
+            if self.step % interval != 0 {
+                return;
+            }
+
+            let box_old = self.sys.box_l;
+            let r_old = [&self.sys.x, &self.sys.y, &self.sys.z].map(Vec::clone);
+            let v_old = self.volume();
+            let u_old = self.e_pot;
+
+            let dlnv = max_dlnv * (2.0 * self.rng.gen::<f64>() - 1.0);
+            self.scale_box((dlnv / 3.0).exp());
+
+            let n = self.sys.len() as f64;
+            let dh = self.e_pot - u_old + pressure * (self.volume() - v_old)
+                - (n + 1.0) * temperature * dlnv;
+
+            self.volume_moves += 1;
+            if dh <= 0.0 || self.rng.gen::<f64>() < (-dh / temperature).exp() {
+                self.volume_accepted += 1;
+            } else {
+                let [x, y, z] = r_old;
+                self.sys.x = x;
+                self.sys.y = y;
+                self.sys.z = z;
+                self.set_box(box_old);
+                self.compute_forces();
+            }
+        }
+    }
+
+    fn kick(&mut self, h: f64) {
+        for (v, g) in [
+            (&mut self.sys.vx, &self.gx),
+            (&mut self.sys.vy, &self.gy),
+            (&mut self.sys.vz, &self.gz),
+        ] {
+            for (v, g) in v.iter_mut().zip(g) {
+                *v -= h * g;
+            }
+        }
+    }
+
+    fn drift(&mut self, dt: f64) {
+        for (r, v) in [
+            (&mut self.sys.x, &self.sys.vx),
+            (&mut self.sys.y, &self.sys.vy),
+            (&mut self.sys.z, &self.sys.vz),
+        ] {
+            for (r, v) in r.iter_mut().zip(v) {
+                *r += dt * v;
+            }
+        }
+    }
+
+    // Returns the virial of the constraint forces over the first half step
+    fn shake(&mut self) -> f64 {
+        if let Some(c) = &self.constraints {
+            let System {
+                box_l,
+                x,
+                y,
+                z,
+                vx,
+                vy,
+                vz,
+                ..
+            } = &mut self.sys;
+            let [xo, yo, zo] = &self.r_old;
+
+            c.shake(*box_l, [xo, yo, zo], [x, y, z], [vx, vy, vz], self.dt)
+        } else {
+            0.0
+        }
+    }
+
+    fn rattle(&mut self) {
+        if let Some(c) = &self.constraints {
+            let System {
+                box_l,
+                x,
+                y,
+                z,
+                vx,
+                vy,
+                vz,
+                ..
+            } = &mut self.sys;
+
+            self.constraint_virial =
+                c.rattle(*box_l, [x, y, z], [vx, vy, vz], self.dt);
+        }
+    }
+
+    fn scale_velocities(&mut self, s: f64) {
+        for v in [&mut self.sys.vx, &mut self.sys.vy, &mut self.sys.vz] {
+            for v in v.iter_mut() {
+                *v *= s;
+            }
+        }
+    }
+
+    fn nose_hoover_half_step(&mut self, h: f64) {
+        if let Thermostat::NoseHoover { temperature, q, .. } = self.thermostat {
+            let g = self.degrees_of_freedom();
+            let ke2 = 2.0 * self.kinetic_energy();
+
+            if let Thermostat::NoseHoover { xi, .. } = &mut self.thermostat {
+                *xi += h * (ke2 - g * temperature) / q;
+                let s = (-*xi * h).exp();
+                self.scale_velocities(s);
+            }
+        }
+    }
+
+    fn nose_hoover_half_step_rev(&mut self, h: f64) {
+        if let Thermostat::NoseHoover { temperature, q, xi } = self.thermostat {
+            self.scale_velocities((-xi * h).exp());
+
+            let g = self.degrees_of_freedom();
+            let ke2 = 2.0 * self.kinetic_energy();
+
+            if let Thermostat::NoseHoover { xi, .. } = &mut self.thermostat {
+                *xi += h * (ke2 - g * temperature) / q;
+            }
+        }
+    }
+
+    fn langevin(&mut self) {
+        if let Thermostat::Langevin { temperature, gamma } = self.thermostat {
+            let c1 = (-gamma * self.dt).exp();
+            let c2 = ((1.0 - c1 * c1) * temperature).sqrt();
+
+            for v in [&mut self.sys.vx, &mut self.sys.vy, &mut self.sys.vz] {
+                for v in v.iter_mut() {
+                    *v = c1 * *v + c2 * gaussian(&mut self.rng);
+                }
+            }
+        }
+    }
+
+    pub fn step(&mut self) {
+        let h = self.dt / 2.0;
+
+        self.nose_hoover_half_step(h);
+        self.kick(h);
+        if self.constraints.is_some() {
+            self.r_old[0].copy_from_slice(&self.sys.x);
+            self.r_old[1].copy_from_slice(&self.sys.y);
+            self.r_old[2].copy_from_slice(&self.sys.z);
+        }
+        self.drift(self.dt);
+        let w_shake = self.shake();
+        self.compute_forces();
+        self.kick(h);
+        self.rattle();
+        self.nose_hoover_half_step_rev(h);
+
+        // SHAKE and RATTLE each see the constraint forces acting over half of
+        // the step, at its start and end
+        if self.constraints.is_some() {
+            self.constraint_virial = 0.5 * (w_shake + self.constraint_virial);
+        }
+
+        // The random kicks break the velocity constraints again. The virial
+        // from this second projection is thermostat noise and is not kept.
+        if let Thermostat::Langevin { .. } = self.thermostat {
+            self.langevin();
+            if self.constraints.is_some() {
+                let w = self.constraint_virial;
+                self.rattle();
+                self.constraint_virial = w;
+            }
+        }
+
+        self.berendsen();
+
+        self.step += 1;
+
+        self.volume_move();
+    }
+
+    pub fn run(&mut self, n_steps: usize) {
+        for _ in 0..n_steps {
+            self.step();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::Constraints;
+
+    // A jittered lattice of 3^3 atoms in a box with a single cell per axis,
+    // and the same atoms repeated twice along every axis, which needs three
+    // cells per axis
+    fn small_and_replicated() -> (System, System) {
+        let mut rng = ChaCha8Rng::seed_from_u64(1);
+        let l = 4.5;
+
+        let mut r = [(); 3].map(|_| Vec::new());
+        for k in 0..27 {
+            let ri = [k / 9, k / 3 % 3, k % 3]
+                .map(|k| (k as f64 + 0.5) * l / 3.0 + rng.gen_range(-0.1..0.1));
+            for (r, x) in r.iter_mut().zip(ri) {
+                r.push(x);
+            }
+        }
+
+        let mut r_rep = [(); 3].map(|_| Vec::new());
+        for k in 0..8 {
+            let o = [k / 4, k / 2 % 2, k % 2].map(|o| o as f64 * l);
+            for ((r_rep, r), o) in r_rep.iter_mut().zip(&r).zip(o) {
+                r_rep.extend(r.iter().map(|x| x + o));
+            }
+        }
+
+        (System::new([l; 3], r), System::new([2.0 * l; 3], r_rep))
+    }
+
+    fn nve(sys: System) -> Md {
+        Md::with_seed(sys, 1.0, 1.0, 2.5, 0.002, Thermostat::None, 1)
+    }
+
+    #[test]
+    fn small_box() {
+        let (sys, sys_rep) = small_and_replicated();
+
+        let mut md = nve(sys);
+        let md_rep = nve(sys_rep);
+
+        assert_eq!(md.cells.n_cells, [1; 3]);
+        assert_eq!(md_rep.cells.n_cells, [3; 3]);
+
+        assert!((8.0 * md.e_pot - md_rep.e_pot).abs() < 1e-10);
+        assert!((8.0 * md.virial() - md_rep.virial()).abs() < 1e-10);
+        for (g, g_rep) in md.gradient().iter().zip(md_rep.gradient()) {
+            for (i, g) in g.iter().enumerate() {
+                assert!((g - g_rep[i]).abs() < 1e-12);
+            }
+        }
+
+        md.init_velocities(0.5);
+        let e0 = md.kinetic_energy() + md.e_pot;
+        md.run(1000);
+        let e1 = md.kinetic_energy() + md.e_pot;
+        assert!((e1 - e0).abs() < 1e-2 * e0.abs());
+    }
+
+    #[test]
+    fn shrink_below_three_cells() {
+        let (_, sys_rep) = small_and_replicated();
+
+        let mut md = nve(sys_rep);
+        assert_eq!(md.cells.n_cells, [3; 3]);
+
+        // As a barostat would, down to two cells per axis
+        md.scale_box(0.75);
+        assert_eq!(md.cells.n_cells, [2; 3]);
+
+        let md_ref = nve(md.sys.clone());
+        assert_eq!(md.e_pot, md_ref.e_pot);
+        assert_eq!(md.virial(), md_ref.virial());
+    }
+
+    // Exact virial of the constraint forces at the current state. The dimers
+    // are independent, so the force lambda s on j follows from
+    // d^2/dt^2 |s|^2 = 2 |u|^2 + 2 s . (f_ij + 2 lambda s) = 0 for each one.
+    fn exact_constraint_virial(md: &Md) -> f64 {
+        let dot = |a: [f64; 3], b: [f64; 3]| -> f64 {
+            a.iter().zip(b).map(|(a, b)| a * b).sum()
+        };
+
+        let System {
+            box_l,
+            x,
+            y,
+            z,
+            vx,
+            vy,
+            vz,
+            ..
+        } = &md.sys;
+        let [gx, gy, gz] = md.gradient();
+
+        let pairs = &md.constraints.as_ref().unwrap().pairs;
+        pairs
+            .iter()
+            .map(|&[i, j]| {
+                let s = [x[j] - x[i], y[j] - y[i], z[j] - z[i]];
+                let s = [0, 1, 2]
+                    .map(|q| s[q] - (s[q] / box_l[q]).round() * box_l[q]);
+                let u = [vx[j] - vx[i], vy[j] - vy[i], vz[j] - vz[i]];
+                let f = [gx[i] - gx[j], gy[i] - gy[j], gz[i] - gz[j]];
+
+                -(dot(u, u) + dot(s, f)) / 2.0
+            })
+            .sum()
+    }
+
+    #[test]
+    fn constraint_pressure() {
+        let mut rng = ChaCha8Rng::seed_from_u64(2);
+
+        // Rigid dimers with random orientations on a cubic lattice
+        let (n, a, b) = (4, 2.0, 1.0);
+        let mut r = [(); 3].map(|_| Vec::new());
+        let mut constraints = Constraints::new(1e-10, 500);
+        for k in 0..n * n * n {
+            let c =
+                [k / (n * n), k / n % n, k % n].map(|c| (c as f64 + 0.5) * a);
+            let u = [(); 3].map(|_| gaussian(&mut rng));
+            let norm = u.iter().map(|u| u * u).sum::<f64>().sqrt();
+            for side in [-0.5, 0.5] {
+                for ((r, c), u) in r.iter_mut().zip(c).zip(u) {
+                    r.push(c + side * b * u / norm);
+                }
+            }
+            constraints.add(2 * k, 2 * k + 1, b);
+        }
+
+        let l = n as f64 * a;
+        let mut md = nve(System::new([l; 3], r));
+        md.set_constraints(constraints);
+        md.init_velocities(1.5);
+        md.run(500);
+
+        let samples = 2000;
+        let (mut p, mut p_ref) = (0.0, 0.0);
+        for _ in 0..samples {
+            md.step();
+            p += md.pressure() / samples as f64;
+
+            let w = md.virial() - md.constraint_virial;
+            let w_ref = w + exact_constraint_virial(&md);
+            p_ref += (2.0 * md.kinetic_energy() + w_ref)
+                / (3.0 * md.volume())
+                / samples as f64;
+        }
+
+        assert!((p - p_ref).abs() < 1e-3 * p_ref.abs());
+    }
+
+    #[test]
+    #[should_panic(expected = "the volume move interval must be positive")]
+    fn zero_volume_move_interval() {
+        let (sys, _) = small_and_replicated();
+        nve(sys).set_barostat(Barostat::MonteCarlo {
+            pressure: 1.0,
+            temperature: 1.0,
+            max_dlnv: 0.01,
+            interval: 0,
+        });
+    }
+}
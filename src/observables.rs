@@ -0,0 +1,335 @@
+use crate::md::{Barostat, Md};
+
+// Value with its standard error
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Estimate {
+    pub value: f64,
+    pub error: f64,
+}
+
+// One level of the blocking transformation, whose samples are averages of
+// 2^k consecutive samples, with the Welford running mean and sum of squares
+#[derive(Clone, Copy, Debug, Default)]
+struct Level {
+    n: u64,
+    mean: f64,
+    m2: f64,
+    pending: Option<f64>,
+}
+
+impl Level {
+    fn add(&mut self, x: f64) {
+        self.n += 1;
+        let d = x - self.mean;
+        self.mean += d / self.n as f64;
+        self.m2 += d * (x - self.mean);
+    }
+
+    // Standard error of the mean taking the blocks as independent
+    fn error(&self) -> f64 {
+        let n = self.n as f64;
+        (self.m2 / (n - 1.0) / n).sqrt()
+    }
+}
+
+// Streaming mean with an error bar for correlated samples (Flyvbjerg and
+// Petersen 1989). Every level averages pairs of samples of the level below,
+// so memory is logarithmic in the number of samples. The naive error of a
+// level grows with the block size until the blocks are longer than the
+// correlation time, and the plateau is taken as the smallest block size B
+// with B^3 > 2 n (e_B / e_1)^4 (Lee et al. 2011).
+#[derive(Clone, Debug, Default)]
+pub struct Blocking {
+    levels: Vec<Level>,
+}
+
+impl Blocking {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, mut x: f64) {
+        let mut k = 0;
+        loop {
+            if k == self.levels.len() {
+                self.levels.push(Level::default());
+            }
+
+            let level = &mut self.levels[k];
+            level.add(x);
+            match level.pending.take() {
+                Some(p) => x = 0.5 * (p + x),
+                None => {
+                    level.pending = Some(x);
+                    return;
+                }
+            }
+            k += 1;
+        }
+    }
+
+    pub fn len(&self) -> u64 {
+        self.levels.first().map_or(0, |l| l.n)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.levels.first().map_or(f64::NAN, |l| l.mean)
+    }
+
+    pub fn variance(&self) -> f64 {
+        self.levels
+            .first()
+            .map_or(f64::NAN, |l| l.m2 / (l.n as f64 - 1.0))
+    }
+
+    // Block size, standard error and the uncertainty of that error for every
+    // level with at least two blocks
+    pub fn errors(&self) -> Vec<(u64, f64, f64)> {
+        self.levels
+            .iter()
+            .enumerate()
+            .take_while(|(_, l)| l.n >= 2)
+            .map(|(k, l)| {
+                let e = l.error();
+                (1 << k, e, e / (2.0 * (l.n as f64 - 1.0)).sqrt())
+            })
+            .collect()
+    }
+
+    // None if the samples are too few to reach the plateau
+    pub fn optimal_level(&self) -> Option<usize> {
+        let errors = self.errors();
+        let e1 = errors.first()?.1;
+        if e1 == 0.0 {
+            return Some(0);
+        }
+
+        let n = self.len() as f64;
+        errors.iter().position(|&(b, e, _)| {
+            (b as f64).powi(3) > 2.0 * n * (e / e1).powi(4)
+        })
+    }
+
+    // Without a plateau this is the error of the largest blocks, which is
+    // then an underestimate
+    pub fn standard_error(&self) -> f64 {
+        let errors = self.errors();
+        match self.optimal_level() {
+            Some(k) => errors[k].1,
+            None => errors.last().map_or(f64::NAN, |e| e.1),
+        }
+    }
+
+    pub fn estimate(&self) -> Estimate {
+        Estimate {
+            value: self.mean(),
+            error: self.standard_error(),
+        }
+    }
+
+    // Statistical inefficiency s = (e_B / e_1)^2, the number of samples per
+    // independent sample
+    pub fn inefficiency(&self) -> f64 {
+        match self.errors().first() {
+            Some(&(_, e1, _)) if e1 > 0.0 => {
+                (self.standard_error() / e1).powi(2)
+            }
+            _ => 1.0,
+        }
+    }
+
+    // Integrated autocorrelation time in samples, from s = 1 + 2 tau
+    pub fn correlation_time(&self) -> f64 {
+        (0.5 * (self.inefficiency() - 1.0)).max(0.0)
+    }
+}
+
+// Number of samples, sum and sum of squares of contiguous blocks
+#[derive(Clone, Copy, Debug, Default)]
+struct Block {
+    n: u64,
+    sum: f64,
+    sum2: f64,
+}
+
+// Estimators that depend on the variance, like heat capacities, with errors
+// from the jackknife over contiguous blocks. There are at most MAX_BLOCKS of
+// them, and when they are full adjacent pairs are merged so the block length
+// doubles and stays long compared to the correlation time. The samples are
+// shifted by the first one to avoid cancellation in the sums of squares.
+#[derive(Clone, Debug)]
+pub struct Fluctuation {
+    block_len: u64,
+    shift: f64,
+    blocks: Vec<Block>,
+}
+
+impl Default for Fluctuation {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Fluctuation {
+    pub const MAX_BLOCKS: usize = 64;
+
+    pub fn new() -> Self {
+        Self {
+            block_len: 1,
+            shift: 0.0,
+            blocks: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, x: f64) {
+        if self.blocks.is_empty() {
+            self.shift = x;
+        }
+
+        if self.blocks.last().map_or(true, |b| b.n == self.block_len) {
+            if self.blocks.len() == Self::MAX_BLOCKS {
+                self.blocks = self
+                    .blocks
+                    .chunks(2)
+                    .map(|b| Block {
+                        n: b[0].n + b[1].n,
+                        sum: b[0].sum + b[1].sum,
+                        sum2: b[0].sum2 + b[1].sum2,
+                    })
+                    .collect();
+                self.block_len *= 2;
+            }
+            self.blocks.push(Block::default());
+        }
+
+        let d = x - self.shift;
+        let b = self.blocks.last_mut().unwrap();
+        b.n += 1;
+        b.sum += d;
+        b.sum2 += d * d;
+    }
+
+    pub fn len(&self) -> u64 {
+        self.blocks.iter().map(|b| b.n).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    // Estimate of f(mean, variance) with the jackknife error
+    pub fn jackknife(&self, f: impl Fn(f64, f64) -> f64) -> Estimate {
+        let total = self.blocks.iter().fold(Block::default(), |a, b| Block {
+            n: a.n + b.n,
+            sum: a.sum + b.sum,
+            sum2: a.sum2 + b.sum2,
+        });
+        let theta = |n: u64, sum: f64, sum2: f64| {
+            let n = n as f64;
+            let mean = sum / n;
+            f(mean + self.shift, (sum2 - sum * mean) / (n - 1.0))
+        };
+
+        let m = self.blocks.len() as f64;
+        let thetas: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|b| {
+                theta(total.n - b.n, total.sum - b.sum, total.sum2 - b.sum2)
+            })
+            .collect();
+        let mean = thetas.iter().sum::<f64>() / m;
+        let var = thetas.iter().map(|t| (t - mean).powi(2)).sum::<f64>();
+
+        Estimate {
+            value: theta(total.n, total.sum, total.sum2),
+            error: ((m - 1.0) / m * var).sqrt(),
+        }
+    }
+
+    pub fn mean(&self) -> Estimate {
+        self.jackknife(|mean, _| mean)
+    }
+
+    pub fn variance(&self) -> Estimate {
+        self.jackknife(|_, var| var)
+    }
+}
+
+// Running averages of the thermodynamic state with k_B = 1. The heat capacity
+// is Var(E) / T^2 of the sampled energy, which is C_V from the total energy at
+// constant volume and C_P from the enthalpy under a barostat. For MC, which
+// only samples the potential energy, the kinetic 3/2 N has to be added to get
+// C_V. Volume fluctuations give the isothermal compressibility
+// Var(V) / (T <V>).
+#[derive(Clone, Debug, Default)]
+pub struct Observables {
+    pub temperature: Blocking,
+    pub pressure: Blocking,
+    pub potential: Blocking,
+    pub energy: Blocking,
+    pub volume: Blocking,
+    energy_fluctuation: Fluctuation,
+    volume_fluctuation: Fluctuation,
+}
+
+impl Observables {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(
+        &mut self,
+        temperature: f64,
+        pressure: f64,
+        potential: f64,
+        energy: f64,
+        volume: f64,
+    ) {
+        self.temperature.push(temperature);
+        self.pressure.push(pressure);
+        self.potential.push(potential);
+        self.energy.push(energy);
+        self.volume.push(volume);
+        self.energy_fluctuation.push(energy);
+        self.volume_fluctuation.push(volume);
+    }
+
+    // The energy is the enthalpy if there is a barostat
+    pub fn sample(&mut self, md: &Md) {
+        let energy = match md.barostat {
+            Barostat::None => md.kinetic_energy() + md.e_pot,
+            _ => md.enthalpy(),
+        };
+
+        self.push(
+            md.temperature(),
+            md.pressure(),
+            md.e_pot,
+            energy,
+            md.volume(),
+        );
+    }
+
+    pub fn len(&self) -> u64 {
+        self.energy.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.energy.is_empty()
+    }
+
+    pub fn heat_capacity(&self, temperature: f64) -> Estimate {
+        self.energy_fluctuation
+            .jackknife(|_, var| var / (temperature * temperature))
+    }
+
+    pub fn compressibility(&self, temperature: f64) -> Estimate {
+        self.volume_fluctuation
+            .jackknife(|mean, var| var / (temperature * mean))
+    }
+}
@@ -0,0 +1,290 @@
+use std::{
+    ops::{Add, AddAssign, Div, Mul, Sub},
+    simd::{LaneCount, Simd, SimdElement, SimdFloat, SupportedLaneCount},
+};
+
+use num_traits::Float;
+
+use crate::lennard_jones_t::{scalar_pairs, sigma2};
+
+// Per-atom energies and virials of the SoA kernels. Every pair contributes
+// half of its energy and virial to each of its atoms, so the per-atom values
+// sum to the totals. The virial tensor of atom i is
+// 1/2 sum_j r_ij (x) f_ij with r_ij = r_i - r_j and f_ij the force on i,
+// stored in the order xx, yy, zz, xy, xz, yz.
+
+const VOIGT: [(usize, usize); 6] =
+    [(0, 0), (1, 1), (2, 2), (0, 1), (0, 2), (1, 2)];
+
+// Chunk i against every particle before it
+fn chunk_pairs<const N: usize, T>(
+    s2: T,
+    e_b: T,
+    i: usize,
+    r: [&[T]; 3],
+    e: &mut [T],
+    mut w: Option<&mut [[T; 6]]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let zero = Simd::splat(T::zero());
+    let one = Simd::splat(T::one());
+    let two = Simd::splat(T::from(2.0).unwrap());
+    let half = Simd::splat(T::from(0.5).unwrap());
+    let four_e_b = Simd::splat(T::from(4.0).unwrap() * e_b);
+    let twentyfour_e_b = Simd::splat(T::from(24.0).unwrap() * e_b);
+    let s2s = Simd::splat(s2);
+
+    let ri = r.map(|r| Simd::from_slice(&r[N * i..]));
+
+    let mut es = zero;
+    let mut ei = zero;
+    let mut wi = [zero; 6];
+
+    for j in 0..N * i {
+        let d = [0, 1, 2].map(|q| Simd::splat(r[q][j]) - ri[q]);
+
+        let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+        let sr2 = s2s / r2;
+        let sr6 = sr2 * sr2 * sr2;
+        let sr12 = sr6 * sr6;
+
+        let eij = four_e_b * (sr12 - sr6);
+        es += eij;
+
+        let eh = half * eij;
+        ei += eh;
+        e[j] += eh.reduce_sum();
+
+        if let Some(w) = &mut w {
+            let h = half * twentyfour_e_b * sr6 / r2 * (two * sr6 - one);
+            for (k, &(a, b)) in VOIGT.iter().enumerate() {
+                let wk = h * d[a] * d[b];
+                wi[k] += wk;
+                w[j][k] += wk.reduce_sum();
+            }
+        }
+    }
+
+    for (e, ei) in e[N * i..N * (i + 1)].iter_mut().zip(ei.to_array()) {
+        *e += ei;
+    }
+
+    if let Some(w) = w {
+        for (k, wi) in wi.into_iter().enumerate() {
+            for (w, wi) in w[N * i..N * (i + 1)].iter_mut().zip(wi.to_array()) {
+                w[k] += wi;
+            }
+        }
+    }
+
+    es.reduce_sum()
+}
+
+fn pair<T: Float + AddAssign>(
+    s2: T,
+    e_b: T,
+    i: usize,
+    j: usize,
+    r: [&[T]; 3],
+    e: &mut [T],
+    w: Option<&mut [[T; 6]]>,
+) -> T {
+    let half = T::from(0.5).unwrap();
+
+    let d = [0, 1, 2].map(|q| r[q][j] - r[q][i]);
+    let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2];
+    let sr6 = (s2 / r2).powi(3);
+
+    let eij = T::from(4.0).unwrap() * e_b * (sr6 * sr6 - sr6);
+    e[i] += half * eij;
+    e[j] += half * eij;
+
+    if let Some(w) = w {
+        let h = half * T::from(24.0).unwrap() * e_b * sr6 / r2
+            * (T::from(2.0).unwrap() * sr6 - T::one());
+        for (k, &(a, b)) in VOIGT.iter().enumerate() {
+            let wk = h * d[a] * d[b];
+            w[i][k] += wk;
+            w[j][k] += wk;
+        }
+    }
+
+    eij
+}
+
+fn check_lengths<T>(
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    e: &[T],
+    w: &Option<&mut [[T; 6]]>,
+) {
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert_eq!(x.len(), e.len());
+    if let Some(w) = w {
+        assert_eq!(x.len(), w.len());
+    }
+}
+
+// Fills e with the energy of every atom and, if given, w with its virial
+// tensor. Returns the total energy.
+pub fn lennard_jones_per_atom<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    e: &mut [T],
+    mut w: Option<&mut [[T; 6]]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    check_lengths(x, y, z, e, &w);
+
+    e.fill(T::zero());
+    if let Some(w) = &mut w {
+        w.fill([T::zero(); 6]);
+    }
+
+    let s2 = sigma2(r_eq);
+    let r = [x, y, z];
+
+    let mut e_tot = T::zero();
+    for i in 0..x.len() / N {
+        e_tot += chunk_pairs::<N, _>(s2, e_b, i, r, e, w.as_deref_mut());
+    }
+
+    scalar_pairs::<N>(x.len(), |i, j| {
+        e_tot += pair(s2, e_b, i, j, r, e, w.as_deref_mut());
+    });
+
+    e_tot
+}
+
+pub struct PerAtomWorkspace<T> {
+    bufs: Vec<PerAtomBuf<T>>,
+}
+
+struct PerAtomBuf<T> {
+    e_tot: T,
+    e: Vec<T>,
+    w: Vec<[T; 6]>,
+}
+
+impl<T: Float> PerAtomWorkspace<T> {
+    pub fn new() -> Self {
+        Self { bufs: Vec::new() }
+    }
+
+    fn prepare(&mut self, threads: usize, n: usize, virial: bool) {
+        self.bufs.resize_with(threads, || PerAtomBuf {
+            e_tot: T::zero(),
+            e: Vec::new(),
+            w: Vec::new(),
+        });
+
+        for buf in &mut self.bufs {
+            buf.e_tot = T::zero();
+            buf.e.clear();
+            buf.e.resize(n, T::zero());
+            buf.w.clear();
+            if virial {
+                buf.w.resize(n, [T::zero(); 6]);
+            }
+        }
+    }
+}
+
+impl<T: Float> Default for PerAtomWorkspace<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Parallel over chunks, with every thread folding its share of them into its
+// own buffers, which are then reduced into e and w
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_per_atom_par<const N: usize, T>(
+    r_eq: T,
+    e_b: T,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    e: &mut [T],
+    mut w: Option<&mut [[T; 6]]>,
+    ws: &mut PerAtomWorkspace<T>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + Send + Sync,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    use rayon::prelude::*;
+
+    check_lengths(x, y, z, e, &w);
+
+    let virial = w.is_some();
+    ws.prepare(rayon::current_num_threads(), x.len(), virial);
+
+    let s2 = sigma2(r_eq);
+    let r = [x, y, z];
+
+    // Chunk i pairs with the N * i atoms before it, so the chunks are dealt
+    // out round-robin to keep the work per buffer even
+    let n_chunks = x.len() / N;
+    let n_bufs = ws.bufs.len();
+    ws.bufs.par_iter_mut().enumerate().for_each(|(t, buf)| {
+        let PerAtomBuf { e_tot, e, w } = buf;
+
+        for i in (t..n_chunks).step_by(n_bufs) {
+            let w = if virial { Some(&mut w[..]) } else { None };
+            *e_tot += chunk_pairs::<N, _>(s2, e_b, i, r, e, w);
+        }
+    });
+
+    e.fill(T::zero());
+    if let Some(w) = &mut w {
+        w.fill([T::zero(); 6]);
+    }
+
+    let mut e_tot = T::zero();
+    for buf in &ws.bufs {
+        e_tot += buf.e_tot;
+        for (e, &eb) in e.iter_mut().zip(&buf.e) {
+            *e += eb;
+        }
+        if let Some(w) = &mut w {
+            for (w, wb) in w.iter_mut().zip(&buf.w) {
+                for (w, &wb) in w.iter_mut().zip(wb) {
+                    *w += wb;
+                }
+            }
+        }
+    }
+
+    scalar_pairs::<N>(x.len(), |i, j| {
+        e_tot += pair(s2, e_b, i, j, r, e, w.as_deref_mut());
+    });
+
+    e_tot
+}
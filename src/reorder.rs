@@ -0,0 +1,158 @@
+use num_traits::Float;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Curve {
+    Morton,
+    Hilbert,
+}
+
+const BITS: u32 = 21;
+
+fn spread_bits(v: u64) -> u64 {
+    let mut v = v & 0x1f_ffff;
+    v = (v | v << 32) & 0x1f_0000_0000_ffff;
+    v = (v | v << 16) & 0x1f_0000_ff00_00ff;
+    v = (v | v << 8) & 0x100f_00f0_0f00_f00f;
+    v = (v | v << 4) & 0x10c3_0c30_c30c_30c3;
+    v = (v | v << 2) & 0x1249_2492_4924_9249;
+    v
+}
+
+pub fn morton_key(c: [u32; 3]) -> u64 {
+    spread_bits(c[0] as u64) << 2
+        | spread_bits(c[1] as u64) << 1
+        | spread_bits(c[2] as u64)
+}
+
+// Skilling's transform from axis coordinates to the transposed Hilbert index,
+// which is then interleaved like a Morton key
+pub fn hilbert_key(mut c: [u32; 3]) -> u64 {
+    let m = 1 << (BITS - 1);
+
+    let mut q = m;
+    while q > 1 {
+        let p = q - 1;
+        for i in 0..3 {
+            if c[i] & q != 0 {
+                c[0] ^= p;
+            } else {
+                let t = (c[0] ^ c[i]) & p;
+                c[0] ^= t;
+                c[i] ^= t;
+            }
+        }
+        q >>= 1;
+    }
+
+    for i in 1..3 {
+        c[i] ^= c[i - 1];
+    }
+
+    let mut t = 0;
+    let mut q = m;
+    while q > 1 {
+        if c[2] & q != 0 {
+            t ^= q - 1;
+        }
+        q >>= 1;
+    }
+
+    for x in &mut c {
+        *x ^= t;
+    }
+
+    morton_key(c)
+}
+
+fn grid_coord<T: Float>(r: T, l: T) -> u32 {
+    let max = (1u32 << BITS) - 1;
+    let s = (r / l - (r / l).floor()) * T::from(max).unwrap();
+    s.to_u32().unwrap_or(0).min(max)
+}
+
+pub fn curve_keys<T: Float>(
+    curve: Curve,
+    box_l: [T; 3],
+    x: &[T],
+    y: &[T],
+    z: &[T],
+) -> Vec<u64> {
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+
+    x.iter()
+        .zip(y)
+        .zip(z)
+        .map(|((&x, &y), &z)| {
+            let c = [
+                grid_coord(x, box_l[0]),
+                grid_coord(y, box_l[1]),
+                grid_coord(z, box_l[2]),
+            ];
+            match curve {
+                Curve::Morton => morton_key(c),
+                Curve::Hilbert => hilbert_key(c),
+            }
+        })
+        .collect()
+}
+
+// Applies a permutation so that v[i] becomes the old v[perm[i]]
+pub fn permute<U: Copy>(perm: &[usize], v: &mut [U]) {
+    assert_eq!(perm.len(), v.len());
+
+    let old = v.to_vec();
+    for (x, &p) in v.iter_mut().zip(perm) {
+        *x = old[p];
+    }
+}
+
+// Undoes permute, mapping per-particle results back to the original order
+pub fn unpermute<U: Copy>(perm: &[usize], v: &mut [U]) {
+    assert_eq!(perm.len(), v.len());
+
+    let old = v.to_vec();
+    for (x, &p) in old.iter().zip(perm) {
+        v[p] = *x;
+    }
+}
+
+// Sorts the particles along a space filling curve through the box. Returns
+// the permutation that was applied, so that velocities, species and anything
+// else stored per particle can be sorted with permute and results mapped back
+// with unpermute.
+pub fn reorder<T: Float>(
+    curve: Curve,
+    box_l: [T; 3],
+    x: &mut [T],
+    y: &mut [T],
+    z: &mut [T],
+) -> Vec<usize> {
+    let keys = curve_keys(curve, box_l, x, y, z);
+
+    let mut perm: Vec<_> = (0..keys.len()).collect();
+    perm.sort_unstable_by_key(|&i| keys[i]);
+
+    permute(&perm, x);
+    permute(&perm, y);
+    permute(&perm, z);
+
+    perm
+}
+
+pub fn reorder_particles<T: Float, S: Copy>(
+    curve: Curve,
+    box_l: [T; 3],
+    [x, y, z]: [&mut [T]; 3],
+    [vx, vy, vz]: [&mut [T]; 3],
+    species: &mut [S],
+) -> Vec<usize> {
+    let perm = reorder(curve, box_l, x, y, z);
+
+    permute(&perm, vx);
+    permute(&perm, vy);
+    permute(&perm, vz);
+    permute(&perm, species);
+
+    perm
+}
@@ -0,0 +1,515 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+use crate::{
+    cell_list::{wrap_positions, CellList},
+    ewald::{real_space_cells, Ewald},
+    fft::Fft3,
+};
+
+// Smooth particle-mesh Ewald (Essmann et al. 1995). The real space and self
+// terms are those of the plain Ewald sum, with the real space pairs found
+// from a cell list so that the cost is linear in the number of particles at a
+// fixed r_cut, and the structure factor of the
+// reciprocal sum is approximated by spreading the charges onto a grid with
+// cardinal B-splines of the given order,
+//
+//   Q(k) = sum_i q_i prod_a M_p(u_ia - k_a),    u_ia = K_a r_ia / L_a,
+//
+// so that E_rec = 1/2 sum_m G(m) |FFT(Q)(m)|^2 with the influence function
+//
+//   G(m) = 4 pi / V exp(-k^2 / (4 alpha^2)) / k^2 prod_a |b_a(m_a)|^2.
+//
+// Writing E_rec = 1/2 sum_k Q(k) (G * Q)(k), the gradient is found by
+// interpolating the convolution back to the particles with the B-spline
+// derivatives.
+
+// Values M_p(w + j) and derivatives for j = 0..p, which are the weights of the
+// grid points floor(u) - j of a particle at u with w = u - floor(u)
+fn bspline<T: Float>(w: T, m: &mut [T], dm: &mut [T]) {
+    let p = m.len();
+
+    m.fill(T::zero());
+    m[0] = w;
+    m[1] = T::one() - w;
+
+    for n in 3..=p {
+        // dM_n(x) / dx = M_{n-1}(x) - M_{n-1}(x - 1)
+        if n == p {
+            dm[0] = m[0];
+            for j in 1..p {
+                dm[j] = m[j] - m[j - 1];
+            }
+        }
+
+        let div = T::from(n - 1).unwrap();
+        for j in (0..n).rev() {
+            let x = w + T::from(j).unwrap();
+            let prev = if j > 0 { m[j - 1] } else { T::zero() };
+            m[j] = (x * m[j] + (T::from(n).unwrap() - x) * prev) / div;
+        }
+    }
+}
+
+// |b(m)|^2 = 1 / |sum_{k=0}^{p-2} M_p(k + 1) exp(2 pi i m k / K)|^2
+fn b_squared<T: Float>(order: usize, k: usize) -> Vec<T> {
+    let mut mp = vec![T::zero(); order];
+    let mut dm = vec![T::zero(); order];
+    bspline(T::zero(), &mut mp, &mut dm);
+
+    (0..k)
+        .map(|m| {
+            let mut re = T::zero();
+            let mut im = T::zero();
+            for j in 0..order - 1 {
+                let phi =
+                    T::from(2.0 * PI * (m * j) as f64 / k as f64).unwrap();
+                re = re + mp[j + 1] * phi.cos();
+                im = im + mp[j + 1] * phi.sin();
+            }
+            T::one() / (re * re + im * im)
+        })
+        .collect()
+}
+
+pub struct Spme<T> {
+    pub ewald: Ewald<T>,
+    pub order: usize,
+    fft: Fft3<T>,
+    influence: Vec<T>,
+}
+
+impl<T: Float + SimdElement> Spme<T> {
+    // Chooses alpha and k_cut as Ewald::new does, but r_cut may be up to the
+    // width of the box. Interpolating with B-splines of order p on a grid of
+    // spacing h gives a relative error of about 0.05 (alpha h)^p, so h is
+    // chosen to bring that down to the accuracy and the grid is rounded up to
+    // a power of two.
+    pub fn new(box_l: [T; 3], r_cut: T, accuracy: T, order: usize) -> Self {
+        let two = T::from(2.0).unwrap();
+        let s = (-accuracy.ln()).sqrt();
+        let alpha = s / r_cut;
+        let ewald = Ewald::with_params(box_l, alpha, r_cut, two * alpha * s);
+
+        let p = T::from(order).unwrap();
+        let h = (T::from(20.0).unwrap() * accuracy).powf(p.recip()) / alpha;
+        let grid = box_l.map(|l| {
+            let k = (l / h).ceil().to_usize().unwrap();
+            k.max(order).next_power_of_two()
+        });
+
+        Self::with_grid(ewald, order, grid)
+    }
+
+    // The order must be even, since the B-spline factors of odd orders
+    // vanish at the Nyquist frequency
+    pub fn with_grid(ewald: Ewald<T>, order: usize, grid: [usize; 3]) -> Self {
+        assert!(order >= 4 && order % 2 == 0, "order must be even and >= 4");
+        for k in grid {
+            assert!(k >= order, "the grid must be at least the order");
+        }
+
+        let fft = Fft3::new(grid);
+        let bsq = grid.map(|k| b_squared::<T>(order, k));
+
+        let tau = T::from(2.0 * PI).unwrap();
+        let pre = T::from(4.0 * PI).unwrap() / ewald.volume();
+        let four_alpha2 = T::from(4.0).unwrap() * ewald.alpha * ewald.alpha;
+
+        let [k0, k1, k2] = grid;
+        let mut influence = vec![T::zero(); fft.len()];
+        for m0 in 0..k0 {
+            for m1 in 0..k1 {
+                for m2 in 0..k2 {
+                    let m = [m0, m1, m2];
+                    let mut k2_sum = T::zero();
+                    for a in 0..3 {
+                        let s = if m[a] <= grid[a] / 2 {
+                            m[a] as f64
+                        } else {
+                            m[a] as f64 - grid[a] as f64
+                        };
+                        let k = tau * T::from(s).unwrap() / ewald.box_l[a];
+                        k2_sum = k2_sum + k * k;
+                    }
+                    if m == [0; 3] {
+                        continue;
+                    }
+
+                    influence[(m0 * k1 + m1) * k2 + m2] =
+                        pre * (-k2_sum / four_alpha2).exp() / k2_sum
+                            * bsq[0][m0]
+                            * bsq[1][m1]
+                            * bsq[2][m2];
+                }
+            }
+        }
+
+        Self {
+            ewald,
+            order,
+            fft,
+            influence,
+        }
+    }
+
+    pub fn grid(&self) -> [usize; 3] {
+        self.fft.dims()
+    }
+}
+
+// Grid points and B-spline weights of every particle along every axis
+struct Stencil<T> {
+    idx: [Vec<usize>; 3],
+    m: [Vec<T>; 3],
+    dm: [Vec<T>; 3],
+}
+
+impl<T: Float> Stencil<T> {
+    fn new(sp: &Spme<T>, r: [&[T]; 3], grid: [usize; 3]) -> Self {
+        let p = sp.order;
+        let n = r[0].len();
+
+        let mut idx = [(); 3].map(|_| vec![0; n * p]);
+        let mut m = [(); 3].map(|_| vec![T::zero(); n * p]);
+        let mut dm = [(); 3].map(|_| vec![T::zero(); n * p]);
+        for a in 0..3 {
+            let k = T::from(grid[a]).unwrap();
+            for i in 0..n {
+                let u = r[a][i] / sp.ewald.box_l[a] * k;
+                let u = u - (u / k).floor() * k;
+                let u0 = u.floor();
+                let k0 = u0.to_usize().unwrap() % grid[a];
+
+                for j in 0..p {
+                    idx[a][i * p + j] = (k0 + grid[a] * p - j) % grid[a];
+                }
+                bspline(
+                    u - u0,
+                    &mut m[a][i * p..(i + 1) * p],
+                    &mut dm[a][i * p..(i + 1) * p],
+                );
+            }
+        }
+
+        Self { idx, m, dm }
+    }
+}
+
+// Adds the reciprocal space gradient to g if given
+pub fn reciprocal<const N: usize, T>(
+    sp: &Spme<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>,
+{
+    for r in r {
+        assert_eq!(r.len(), q.len());
+    }
+
+    let p = sp.order;
+    let grid = sp.grid();
+    let [_, k1, k2] = grid;
+    let st = Stencil::new(sp, r, grid);
+
+    let mut charge = vec![T::zero(); sp.fft.len()];
+    for (i, &qi) in q.iter().enumerate() {
+        for j0 in i * p..(i + 1) * p {
+            let w0 = qi * st.m[0][j0];
+            for j1 in i * p..(i + 1) * p {
+                let w1 = w0 * st.m[1][j1];
+                let row = (st.idx[0][j0] * k1 + st.idx[1][j1]) * k2;
+                for j2 in i * p..(i + 1) * p {
+                    charge[row + st.idx[2][j2]] += w1 * st.m[2][j2];
+                }
+            }
+        }
+    }
+
+    let mut re = charge.clone();
+    let mut im = vec![T::zero(); re.len()];
+    sp.fft.forward::<N>(&mut re, &mut im);
+    for ((re, im), &gm) in re.iter_mut().zip(&mut im).zip(&sp.influence) {
+        *re = *re * gm;
+        *im = *im * gm;
+    }
+    sp.fft.inverse::<N>(&mut re, &mut im);
+    let conv = re;
+
+    let e = charge
+        .iter()
+        .zip(&conv)
+        .fold(T::zero(), |acc, (&q, &c)| acc + q * c)
+        * T::from(0.5).unwrap();
+
+    if let Some(g) = g {
+        let scale =
+            [0, 1, 2].map(|a| T::from(grid[a]).unwrap() / sp.ewald.box_l[a]);
+
+        for (i, &qi) in q.iter().enumerate() {
+            let mut gi = [T::zero(); 3];
+            for j0 in i * p..(i + 1) * p {
+                for j1 in i * p..(i + 1) * p {
+                    let row = (st.idx[0][j0] * k1 + st.idx[1][j1]) * k2;
+                    for j2 in i * p..(i + 1) * p {
+                        let c = conv[row + st.idx[2][j2]];
+                        let (m0, m1, m2) =
+                            (st.m[0][j0], st.m[1][j1], st.m[2][j2]);
+
+                        gi[0] += st.dm[0][j0] * m1 * m2 * c;
+                        gi[1] += m0 * st.dm[1][j1] * m2 * c;
+                        gi[2] += m0 * m1 * st.dm[2][j2] * c;
+                    }
+                }
+            }
+
+            for a in 0..3 {
+                g[a][i] += qi * scale[a] * gi[a];
+            }
+        }
+    }
+
+    e
+}
+
+// Total energy with the reciprocal sum from the mesh. The gradient is
+// overwritten if given.
+pub fn spme<const N: usize, T>(
+    sp: &Spme<T>,
+    q: &[T],
+    r: [&[T]; 3],
+    g: Option<[&mut [T]; 3]>,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let ew = &sp.ewald;
+
+    let mut rw = r.map(|r| r.to_vec());
+    let [x, y, z] = &mut rw;
+    wrap_positions(ew.box_l, x, y, z);
+    let mut cells = CellList::new(ew.box_l, ew.r_cut);
+    cells.build(x, y, z);
+    let rw = [&rw[0][..], &rw[1], &rw[2]];
+
+    match g {
+        Some(mut g) => {
+            for g in g.iter_mut() {
+                g.fill(T::zero());
+            }
+            let [gx, gy, gz] = g;
+            let e_real = real_space_cells::<N, _>(
+                ew,
+                q,
+                rw,
+                &cells,
+                Some([&mut *gx, &mut *gy, &mut *gz]),
+            );
+            e_real
+                + reciprocal::<N, _>(sp, q, r, Some([gx, gy, gz]))
+                + ew.self_energy(q)
+        }
+        None => {
+            real_space_cells::<N, _>(ew, q, rw, &cells, None)
+                + reciprocal::<N, _>(sp, q, r, None)
+                + ew.self_energy(q)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::{
+        dual::{self, Dual},
+        ewald,
+    };
+
+    // Rock salt of side n with random displacements
+    fn rock_salt(n: usize) -> (Vec<f64>, [Vec<f64>; 3]) {
+        let mut rng = ChaCha8Rng::seed_from_u64(3);
+        let mut q = Vec::new();
+        let mut r = [Vec::new(), Vec::new(), Vec::new()];
+        for i in 0..n * n * n {
+            let c = [i / (n * n), i / n % n, i % n];
+            let even = c.iter().sum::<usize>() % 2 == 0;
+            q.push(if even { 1.0 } else { -1.0 });
+            for (r, c) in r.iter_mut().zip(c) {
+                r.push(c as f64 + rng.gen_range(-0.2..0.2));
+            }
+        }
+        (q, r)
+    }
+
+    fn energy_grad(
+        f: impl Fn(Option<[&mut [f64]; 3]>) -> f64,
+        n: usize,
+    ) -> (f64, [Vec<f64>; 3]) {
+        let mut g = [(); 3].map(|_| vec![0.0; n]);
+        let [gx, gy, gz] = &mut g;
+        let e = f(Some([gx, gy, gz]));
+        (e, g)
+    }
+
+    // Compared against a converged plain Ewald sum, with r_cut both below
+    // and above half the box
+    #[test]
+    fn matches_ewald() {
+        let n = 6;
+        let (q, r) = rock_salt(n);
+        let r = [&r[0][..], &r[1], &r[2]];
+        let box_l = [n as f64; 3];
+
+        let ew = Ewald::new(box_l, 3.0, 1e-14);
+        let (e_ref, g_ref) =
+            energy_grad(|g| ewald::ewald::<4, _>(&ew, &q, r, g), q.len());
+        let g_rms = (g_ref.iter().flatten().map(|g| g * g).sum::<f64>()
+            / (3 * q.len()) as f64)
+            .sqrt();
+
+        for (r_cut, accuracy) in [(3.0, 1e-5), (3.0, 1e-8), (5.5, 1e-8)] {
+            let sp = Spme::new(box_l, r_cut, accuracy, 8);
+            let (e, g) = energy_grad(|g| spme::<4, _>(&sp, &q, r, g), q.len());
+
+            let e_err = ((e - e_ref) / e_ref).abs();
+            let g_err = g
+                .iter()
+                .flatten()
+                .zip(g_ref.iter().flatten())
+                .map(|(g, g_ref)| (g - g_ref).abs())
+                .fold(0.0, f64::max);
+            println!(
+                "r_cut {r_cut}, accuracy {accuracy:e}, grid {:?}: energy \
+                 error {e_err:e}, gradient error {:e}",
+                sp.grid(),
+                g_err / g_rms
+            );
+            assert!(e_err < 10.0 * accuracy);
+            assert!(g_err < 10.0 * accuracy * g_rms);
+
+            let e_only = spme::<4, _>(&sp, &q, r, None);
+            assert!(((e_only - e) / e).abs() < 1e-12);
+        }
+    }
+
+    // The reciprocal energy 1/2 sum_m G(m) |S(m)|^2 with the structure factor
+    // of the spread charges summed directly, one particle and axis at a time
+    fn reference_reciprocal(
+        sp: &Spme<f64>,
+        q: &[f64],
+        r: &[[Dual<f64>; 3]],
+    ) -> Dual<f64> {
+        let c = Dual::constant;
+        let p = sp.order;
+        let grid = sp.grid();
+
+        // exp(-2 pi i m k / K) summed over the stencil of every particle,
+        // as real and imaginary parts
+        type Factors = [Vec<(Dual<f64>, Dual<f64>)>; 3];
+        let factors: Vec<Factors> = r
+            .iter()
+            .map(|r| {
+                [0, 1, 2].map(|a| {
+                    let k = grid[a] as f64;
+                    let u = r[a] / c(sp.ewald.box_l[a]) * c(k);
+                    let u = u - (u / c(k)).floor() * c(k);
+                    let k0 = u.re.floor() as usize;
+
+                    let mut m = vec![c(0.0); p];
+                    let mut dm = vec![c(0.0); p];
+                    bspline(u - u.floor(), &mut m, &mut dm);
+
+                    (0..grid[a])
+                        .map(|mi| {
+                            let mut re = c(0.0);
+                            let mut im = c(0.0);
+                            for (j, m) in m.iter().enumerate() {
+                                let idx = (k0 + grid[a] * p - j) % grid[a];
+                                let phi = -2.0 * PI * (mi * idx) as f64 / k;
+                                re += *m * c(phi.cos());
+                                im += *m * c(phi.sin());
+                            }
+                            (re, im)
+                        })
+                        .collect()
+                })
+            })
+            .collect();
+
+        let [_, k1, k2] = grid;
+        let mut e = c(0.0);
+        for (mi, &g) in sp.influence.iter().enumerate() {
+            let m = [mi / (k1 * k2), mi / k2 % k1, mi % k2];
+            let mut s_re = c(0.0);
+            let mut s_im = c(0.0);
+            for (q, f) in q.iter().zip(&factors) {
+                let mut re = c(*q);
+                let mut im = c(0.0);
+                for a in 0..3 {
+                    let (fr, fi) = f[a][m[a]];
+                    (re, im) = (re * fr - im * fi, re * fi + im * fr);
+                }
+                s_re += re;
+                s_im += im;
+            }
+            e += c(0.5 * g) * (s_re * s_re + s_im * s_im);
+        }
+        e
+    }
+
+    // The mesh gradient is the exact derivative of the mesh energy, on a
+    // grid that differs between the axes
+    #[test]
+    fn dual_gradient() {
+        let n = 3;
+        let (q, r) = rock_salt(n);
+        let box_l = [n as f64; 3];
+        let ew = Ewald::new(box_l, 1.5, 1e-5);
+        let sp = Spme::with_grid(ew, 6, [16, 8, 16]);
+
+        let mut g = [(); 3].map(|_| vec![0.0; q.len()]);
+        let [gx, gy, gz] = &mut g;
+        let rs = [&r[0][..], &r[1], &r[2]];
+        reciprocal::<4, _>(&sp, &q, rs, Some([gx, gy, gz]));
+
+        let r_aos: Vec<_> =
+            (0..q.len()).map(|i| [r[0][i], r[1][i], r[2][i]]).collect();
+        let g_ref =
+            dual::gradient(&r_aos, |r| reference_reciprocal(&sp, &q, r));
+
+        let scale = g_ref.iter().flatten().fold(0.0f64, |m, g| m.max(g.abs()));
+        for (i, g_ref) in g_ref.iter().enumerate() {
+            for a in 0..3 {
+                let err = (g[a][i] - g_ref[a]).abs() / scale;
+                assert!(err < 1e-10, "ion {i}, error {err:e}");
+            }
+        }
+    }
+}
@@ -0,0 +1,350 @@
+use std::{
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{LaneCount, Simd, SimdElement, SimdFloat, SupportedLaneCount},
+};
+
+use num_traits::Float;
+
+use crate::lennard_jones_t::{scalar_pairs, sigma2};
+
+// Positions (or gradients) of particles in D dimensions, independent of the
+// memory layout. Kernels read single particles with get and N consecutive
+// particles into lanes with load, and accumulate into a gradient of the same
+// layout with add and add_simd.
+pub trait ParticleStorage<T: SimdElement, const D: usize> {
+    fn from_aos(r: &[[T; D]]) -> Self;
+
+    fn len(&self) -> usize;
+
+    fn get(&self, i: usize) -> [T; D];
+
+    fn add(&mut self, i: usize, v: [T; D]);
+
+    // Sets every particle to zero without reallocating
+    fn clear(&mut self);
+
+    fn load<const N: usize>(&self, i: usize) -> [Simd<T, N>; D]
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        let rl: [[T; D]; N] = std::array::from_fn(|l| self.get(i + l));
+        std::array::from_fn(|q| Simd::from_array(rl.map(|rl| rl[q])))
+    }
+
+    fn add_simd<const N: usize>(&mut self, i: usize, v: [Simd<T, N>; D])
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        let v = v.map(|v| v.to_array());
+        for l in 0..N {
+            self.add(i + l, v.map(|v| v[l]));
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn to_aos(&self) -> Vec<[T; D]> {
+        (0..self.len()).map(|i| self.get(i)).collect()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Aos<T, const D: usize>(pub Vec<[T; D]>);
+
+impl<T: Float + SimdElement + AddAssign, const D: usize> ParticleStorage<T, D>
+    for Aos<T, D>
+{
+    fn from_aos(r: &[[T; D]]) -> Self {
+        Self(r.to_vec())
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get(&self, i: usize) -> [T; D] {
+        self.0[i]
+    }
+
+    fn add(&mut self, i: usize, v: [T; D]) {
+        for (r, v) in self.0[i].iter_mut().zip(v) {
+            *r += v;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.0.fill([T::zero(); D]);
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Soa<T, const D: usize>(pub [Vec<T>; D]);
+
+impl<T: Float + SimdElement + AddAssign, const D: usize> ParticleStorage<T, D>
+    for Soa<T, D>
+{
+    fn from_aos(r: &[[T; D]]) -> Self {
+        Self(crate::lennard_jones_t::to_soa(r))
+    }
+
+    fn len(&self) -> usize {
+        self.0[0].len()
+    }
+
+    fn get(&self, i: usize) -> [T; D] {
+        std::array::from_fn(|q| self.0[q][i])
+    }
+
+    fn add(&mut self, i: usize, v: [T; D]) {
+        for (r, v) in self.0.iter_mut().zip(v) {
+            r[i] += v;
+        }
+    }
+
+    fn clear(&mut self) {
+        for r in &mut self.0 {
+            r.fill(T::zero());
+        }
+    }
+
+    fn load<const N: usize>(&self, i: usize) -> [Simd<T, N>; D]
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        std::array::from_fn(|q| Simd::from_slice(&self.0[q][i..]))
+    }
+
+    fn add_simd<const N: usize>(&mut self, i: usize, v: [Simd<T, N>; D])
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        for (r, v) in self.0.iter_mut().zip(v) {
+            for (r, v) in r[i..i + N].iter_mut().zip(v.to_array()) {
+                *r += v;
+            }
+        }
+    }
+}
+
+// Blocks of B particles stored as SoA within the block. The last block is
+// padded with zeros beyond len.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AoSoA<T, const D: usize, const B: usize> {
+    pub blocks: Vec<[[T; B]; D]>,
+    pub len: usize,
+}
+
+impl<T: Float + SimdElement + AddAssign, const D: usize, const B: usize>
+    ParticleStorage<T, D> for AoSoA<T, D, B>
+{
+    fn from_aos(r: &[[T; D]]) -> Self {
+        let mut blocks = vec![[[T::zero(); B]; D]; (r.len() + B - 1) / B];
+        for (i, ri) in r.iter().enumerate() {
+            for (b, &x) in blocks[i / B].iter_mut().zip(ri) {
+                b[i % B] = x;
+            }
+        }
+        Self {
+            blocks,
+            len: r.len(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn get(&self, i: usize) -> [T; D] {
+        let b = &self.blocks[i / B];
+        std::array::from_fn(|q| b[q][i % B])
+    }
+
+    fn add(&mut self, i: usize, v: [T; D]) {
+        for (b, v) in self.blocks[i / B].iter_mut().zip(v) {
+            b[i % B] += v;
+        }
+    }
+
+    fn clear(&mut self) {
+        self.blocks.fill([[T::zero(); B]; D]);
+    }
+
+    // Lanes that fit inside one block are loaded directly, which is always
+    // the case for aligned loads with N <= B
+    fn load<const N: usize>(&self, i: usize) -> [Simd<T, N>; D]
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        if i % B + N > B {
+            let rl: [[T; D]; N] = std::array::from_fn(|l| self.get(i + l));
+            return std::array::from_fn(|q| {
+                Simd::from_array(rl.map(|rl| rl[q]))
+            });
+        }
+
+        let b = &self.blocks[i / B];
+        std::array::from_fn(|q| Simd::from_slice(&b[q][i % B..]))
+    }
+
+    fn add_simd<const N: usize>(&mut self, i: usize, v: [Simd<T, N>; D])
+    where
+        LaneCount<N>: SupportedLaneCount,
+    {
+        let v = v.map(|v| v.to_array());
+        for l in 0..N {
+            for (b, v) in self.blocks[(i + l) / B].iter_mut().zip(&v) {
+                b[(i + l) % B] += v[l];
+            }
+        }
+    }
+}
+
+pub fn lennard_jones<const N: usize, const D: usize, T, P>(
+    r_eq: T,
+    e_b: T,
+    r: &P,
+) -> T
+where
+    T: Float + SimdElement + AddAssign,
+    P: ParticleStorage<T, D>,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let n = r.len();
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    let mut es = Simd::splat(T::zero());
+    for i in 0..n / N {
+        let ri = r.load::<N>(N * i);
+
+        for j in 0..N * i {
+            let rj = r.get(j);
+
+            let mut r2 = Simd::splat(T::zero());
+            for (rj, ri) in rj.into_iter().zip(ri) {
+                let d = Simd::splat(rj) - ri;
+                r2 += d * d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+        }
+    }
+
+    let mut e = es.reduce_sum();
+
+    scalar_pairs::<N>(n, |i, j| {
+        let (ri, rj) = (r.get(i), r.get(j));
+        let mut r2 = T::zero();
+        for (rj, ri) in rj.into_iter().zip(ri) {
+            let d = rj - ri;
+            r2 += d * d;
+        }
+        let sr6 = (s2 / r2).powi(3);
+
+        e += sr6 * sr6 - sr6;
+    });
+
+    e * T::from(4.0).unwrap() * e_b
+}
+
+pub fn lennard_jones_grad<const N: usize, const D: usize, T, P>(
+    r_eq: T,
+    e_b: T,
+    r: &P,
+    g: &mut P,
+) -> T
+where
+    T: Float + SimdElement + AddAssign + SubAssign,
+    P: ParticleStorage<T, D>,
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>,
+{
+    let n = r.len();
+    assert_eq!(g.len(), n);
+
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+    let e_b_s = Simd::splat(e_b);
+
+    let s2 = sigma2(r_eq);
+    let s2s = Simd::splat(s2);
+
+    g.clear();
+
+    let mut es = Simd::splat(T::zero());
+    for i in 0..n / N {
+        let ri = r.load::<N>(N * i);
+
+        let mut gi = [Simd::splat(T::zero()); D];
+
+        for j in 0..N * i {
+            let rj = r.get(j);
+
+            let mut d = [Simd::splat(T::zero()); D];
+            let mut r2 = Simd::splat(T::zero());
+            for ((d, rj), ri) in d.iter_mut().zip(rj).zip(ri) {
+                *d = Simd::splat(rj) - ri;
+                r2 += *d * *d;
+            }
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            let mut gj = [T::zero(); D];
+            for ((gj, gi), d) in gj.iter_mut().zip(&mut gi).zip(d) {
+                let gqs = gs * d;
+                *gi -= gqs;
+                *gj = gqs.reduce_sum();
+            }
+            g.add(j, gj);
+        }
+
+        g.add_simd(N * i, gi);
+    }
+
+    let mut e = es.reduce_sum();
+
+    scalar_pairs::<N>(n, |i, j| {
+        let (ri, rj) = (r.get(i), r.get(j));
+        let mut d = [T::zero(); D];
+        let mut r2 = T::zero();
+        for ((d, rj), ri) in d.iter_mut().zip(rj).zip(ri) {
+            *d = rj - ri;
+            r2 += *d * *d;
+        }
+        let sr6 = (s2 / r2).powi(3);
+
+        e += sr6 * sr6 - sr6;
+
+        let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
+        g.add(i, d.map(|d| -gs * d));
+        g.add(j, d.map(|d| gs * d));
+    });
+
+    e * T::from(4.0).unwrap() * e_b
+}
@@ -0,0 +1,88 @@
+use num_traits::Float;
+
+// Homogeneous tail corrections for Lennard-Jones truncated (and not shifted) at
+// r_cut, assuming g(r) = 1 beyond the cutoff. For an ordered pair of species a
+// and b with N_a and N_b particles in the volume V, with sigma = 2^(-1/6) r_eq,
+//
+//   E_ab = 8 pi / 3 N_a N_b / V e_b sigma^3 (1/3 (sigma/r_c)^9 - (sigma/r_c)^3)
+//   P_ab = 16 pi / 3 N_a N_b / V^2 e_b sigma^3 (2/3 (sigma/r_c)^9
+//          - (sigma/r_c)^3)
+//
+// and the corrections of a mixture are the sums over all ordered pairs.
+
+fn sigma3_and_ratios<T: Float>(r_eq: T, r_cut: T) -> (T, T, T) {
+    let sigma = T::from(2.0)
+        .unwrap()
+        .powf(-T::one() / T::from(6.0).unwrap())
+        * r_eq;
+    let sr3 = (sigma / r_cut).powi(3);
+    (sigma.powi(3), sr3, sr3 * sr3 * sr3)
+}
+
+pub fn tail_energy_pair<T: Float>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    n_a: usize,
+    n_b: usize,
+    volume: T,
+) -> T {
+    let (s3, sr3, sr9) = sigma3_and_ratios(r_eq, r_cut);
+    let pre = T::from(8.0 * std::f64::consts::PI / 3.0).unwrap();
+    let nn = T::from(n_a).unwrap() * T::from(n_b).unwrap();
+
+    pre * nn / volume * e_b * s3 * (sr9 / T::from(3.0).unwrap() - sr3)
+}
+
+pub fn tail_pressure_pair<T: Float>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    n_a: usize,
+    n_b: usize,
+    volume: T,
+) -> T {
+    let (s3, sr3, sr9) = sigma3_and_ratios(r_eq, r_cut);
+    let pre = T::from(16.0 * std::f64::consts::PI / 3.0).unwrap();
+    let nn = T::from(n_a).unwrap() * T::from(n_b).unwrap();
+
+    pre * nn / (volume * volume)
+        * e_b
+        * s3
+        * (T::from(2.0 / 3.0).unwrap() * sr9 - sr3)
+}
+
+pub fn species_counts(species: &[u8]) -> Vec<usize> {
+    let mut counts = Vec::new();
+    for &s in species {
+        let s = s as usize;
+        if s >= counts.len() {
+            counts.resize(s + 1, 0);
+        }
+        counts[s] += 1;
+    }
+    counts
+}
+
+// Energy and pressure corrections of a mixture, where params gives r_eq and
+// e_b for a pair of species
+pub fn tail_corrections<T: Float>(
+    params: impl Fn(usize, usize) -> (T, T),
+    r_cut: T,
+    species: &[u8],
+    volume: T,
+) -> (T, T) {
+    let counts = species_counts(species);
+
+    let mut e = T::zero();
+    let mut p = T::zero();
+    for (a, &n_a) in counts.iter().enumerate() {
+        for (b, &n_b) in counts.iter().enumerate() {
+            let (r_eq, e_b) = params(a, b);
+            e = e + tail_energy_pair(r_eq, e_b, r_cut, n_a, n_b, volume);
+            p = p + tail_pressure_pair(r_eq, e_b, r_cut, n_a, n_b, volume);
+        }
+    }
+
+    (e, p)
+}
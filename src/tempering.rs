@@ -0,0 +1,194 @@
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::{mc::Mc, md::Md};
+
+// Replica exchange over a ladder of temperatures. Every replica is advanced
+// independently on the rayon pool, after which neighbouring temperatures
+// k and k + 1 attempt to swap configurations, accepted with probability
+//
+//   min(1, exp((1 / T_k - 1 / T_{k+1}) (U_k - U_{k+1})))
+//
+// alternating between the even and the odd pairs. Swapping moves the
+// replicas between the slots and changes their temperatures, so replicas[k]
+// is always at temperatures[k].
+
+pub trait Replica: Send {
+    fn temperature(&self) -> f64;
+
+    fn set_temperature(&mut self, temperature: f64);
+
+    fn potential_energy(&self) -> f64;
+
+    // MD steps or MC sweeps
+    fn advance(&mut self, steps: usize);
+}
+
+impl Replica for Md {
+    fn temperature(&self) -> f64 {
+        self.thermostat_temperature()
+            .expect("MD replicas need a thermostat")
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        Md::set_temperature(self, temperature);
+    }
+
+    fn potential_energy(&self) -> f64 {
+        self.e_pot
+    }
+
+    fn advance(&mut self, steps: usize) {
+        self.run(steps);
+    }
+}
+
+impl Replica for Mc {
+    fn temperature(&self) -> f64 {
+        self.temperature
+    }
+
+    fn set_temperature(&mut self, temperature: f64) {
+        self.temperature = temperature;
+    }
+
+    fn potential_energy(&self) -> f64 {
+        self.e_pot
+    }
+
+    fn advance(&mut self, sweeps: usize) {
+        self.run(sweeps);
+    }
+}
+
+// K temperatures from t_min to t_max with a constant ratio, which gives
+// roughly even swap acceptance when the heat capacity is constant
+pub fn geometric_ladder(t_min: f64, t_max: f64, k: usize) -> Vec<f64> {
+    assert!(k >= 2);
+    let r = (t_max / t_min).powf(1.0 / (k - 1) as f64);
+    (0..k).map(|i| t_min * r.powi(i as i32)).collect()
+}
+
+pub struct ParallelTempering<R> {
+    pub replicas: Vec<R>,
+    pub temperatures: Vec<f64>,
+    // Index in the initial ordering of the replica at every temperature
+    pub ids: Vec<usize>,
+    // Steps or sweeps between exchange attempts
+    pub steps: usize,
+    pub rng: ChaCha8Rng,
+    pub exchanges: u64,
+    // Per neighbour pair (k, k + 1)
+    pub swap_attempts: Vec<u64>,
+    pub swap_accepted: Vec<u64>,
+    // Per temperature the number of samples and the sums of U and U^2
+    samples: Vec<u64>,
+    sum_u: Vec<f64>,
+    sum_u2: Vec<f64>,
+}
+
+impl<R: Replica> ParallelTempering<R> {
+    // The replicas must be ordered by increasing temperature
+    pub fn new(replicas: Vec<R>, steps: usize, seed: u64) -> Self {
+        let k = replicas.len();
+        let temperatures: Vec<_> =
+            replicas.iter().map(|r| r.temperature()).collect();
+        assert!(
+            temperatures.windows(2).all(|t| t[0] <= t[1]),
+            "replicas must be ordered by temperature"
+        );
+
+        Self {
+            replicas,
+            temperatures,
+            ids: (0..k).collect(),
+            steps,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            exchanges: 0,
+            swap_attempts: vec![0; k.saturating_sub(1)],
+            swap_accepted: vec![0; k.saturating_sub(1)],
+            samples: vec![0; k],
+            sum_u: vec![0.0; k],
+            sum_u2: vec![0.0; k],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.replicas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.replicas.is_empty()
+    }
+
+    fn attempt_swaps(&mut self) {
+        let parity = (self.exchanges % 2) as usize;
+
+        for k in (parity..self.len().saturating_sub(1)).step_by(2) {
+            let (t0, t1) = (self.temperatures[k], self.temperatures[k + 1]);
+            let u0 = self.replicas[k].potential_energy();
+            let u1 = self.replicas[k + 1].potential_energy();
+
+            let delta = (1.0 / t0 - 1.0 / t1) * (u0 - u1);
+
+            self.swap_attempts[k] += 1;
+            if delta >= 0.0 || self.rng.gen::<f64>() < delta.exp() {
+                self.swap_accepted[k] += 1;
+
+                self.replicas.swap(k, k + 1);
+                self.ids.swap(k, k + 1);
+                self.replicas[k].set_temperature(t0);
+                self.replicas[k + 1].set_temperature(t1);
+            }
+        }
+
+        self.exchanges += 1;
+    }
+
+    // Advances every replica, samples the energies and attempts swaps, the
+    // given number of times
+    pub fn run(&mut self, exchanges: usize) {
+        use rayon::prelude::*;
+
+        for _ in 0..exchanges {
+            let steps = self.steps;
+            self.replicas.par_iter_mut().for_each(|r| r.advance(steps));
+
+            for (k, r) in self.replicas.iter().enumerate() {
+                let u = r.potential_energy();
+                self.samples[k] += 1;
+                self.sum_u[k] += u;
+                self.sum_u2[k] += u * u;
+            }
+
+            self.attempt_swaps();
+        }
+    }
+
+    pub fn reset_observables(&mut self) {
+        self.samples.fill(0);
+        self.sum_u.fill(0.0);
+        self.sum_u2.fill(0.0);
+    }
+
+    // Fraction of accepted swaps of every neighbour pair
+    pub fn acceptance(&self) -> Vec<f64> {
+        self.swap_accepted
+            .iter()
+            .zip(&self.swap_attempts)
+            .map(|(&a, &n)| a as f64 / n.max(1) as f64)
+            .collect()
+    }
+
+    pub fn mean_energy(&self, k: usize) -> f64 {
+        self.sum_u[k] / self.samples[k] as f64
+    }
+
+    // Configurational heat capacity (<U^2> - <U>^2) / T^2
+    pub fn heat_capacity(&self, k: usize) -> f64 {
+        let n = self.samples[k] as f64;
+        let mean = self.sum_u[k] / n;
+        let var = self.sum_u2[k] / n - mean * mean;
+        var / self.temperatures[k].powi(2)
+    }
+}
@@ -0,0 +1,566 @@
+use std::{
+    iter::Sum,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    simd::{
+        LaneCount, Mask, Simd, SimdElement, SimdFloat, SimdPartialOrd,
+        StdFloat, SupportedLaneCount,
+    },
+};
+
+use num_traits::Float;
+
+// Upper triangular cell with lattice vectors a = (lx, 0, 0), b = (xy, ly, 0)
+// and c = (xz, yz, lz), stored as l = [lx, ly, lz] and tilt = [xy, xz, yz].
+// Any cell can be rotated into this form, see from_vectors. The minimum image
+// reduces one lattice vector at a time starting from c, which is exact as long
+// as the tilts are at most half the corresponding length and the cutoff is
+// below half the smallest width.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriclinicBox<T> {
+    pub l: [T; 3],
+    pub tilt: [T; 3],
+}
+
+fn dot<T: Float>(a: [T; 3], b: [T; 3]) -> T {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross<T: Float>(a: [T; 3], b: [T; 3]) -> [T; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+impl<T: Float> TriclinicBox<T> {
+    pub fn new(l: [T; 3], tilt: [T; 3]) -> Self {
+        Self { l, tilt }
+    }
+
+    pub fn orthogonal(l: [T; 3]) -> Self {
+        Self::new(l, [T::zero(); 3])
+    }
+
+    // The cell spanned by a, b and c rotated so that a is along x and b is in
+    // the xy plane. Coordinates given in the same frame as a, b and c have to
+    // be rotated the same way, or generated from the returned vectors.
+    pub fn from_vectors(a: [T; 3], b: [T; 3], c: [T; 3]) -> Self {
+        let lx = dot(a, a).sqrt();
+        let xy = dot(a, b) / lx;
+        let ly = (dot(b, b) - xy * xy).sqrt();
+        let xz = dot(a, c) / lx;
+        let yz = (dot(b, c) - xy * xz) / ly;
+        let lz = (dot(c, c) - xz * xz - yz * yz).sqrt();
+
+        Self::new([lx, ly, lz], [xy, xz, yz])
+    }
+
+    pub fn vectors(&self) -> [[T; 3]; 3] {
+        let zero = T::zero();
+        let [lx, ly, lz] = self.l;
+        let [xy, xz, yz] = self.tilt;
+
+        [[lx, zero, zero], [xy, ly, zero], [xz, yz, lz]]
+    }
+
+    pub fn volume(&self) -> T {
+        self.l[0] * self.l[1] * self.l[2]
+    }
+
+    // Distances between opposite faces
+    pub fn widths(&self) -> [T; 3] {
+        let [a, b, c] = self.vectors();
+        let v = self.volume();
+
+        [cross(b, c), cross(c, a), cross(a, b)].map(|n| v / dot(n, n).sqrt())
+    }
+
+    pub fn to_frac(&self, r: [T; 3]) -> [T; 3] {
+        let [lx, ly, lz] = self.l;
+        let [xy, xz, yz] = self.tilt;
+
+        let sz = r[2] / lz;
+        let sy = (r[1] - yz * sz) / ly;
+        let sx = (r[0] - xy * sy - xz * sz) / lx;
+
+        [sx, sy, sz]
+    }
+
+    pub fn to_cart(&self, s: [T; 3]) -> [T; 3] {
+        let [lx, ly, lz] = self.l;
+        let [xy, xz, yz] = self.tilt;
+
+        [
+            lx * s[0] + xy * s[1] + xz * s[2],
+            ly * s[1] + yz * s[2],
+            lz * s[2],
+        ]
+    }
+
+    pub fn min_image(&self, mut d: [T; 3]) -> [T; 3] {
+        let [lx, ly, lz] = self.l;
+        let [xy, xz, yz] = self.tilt;
+
+        let n = (d[2] / lz).round();
+        d[2] = d[2] - n * lz;
+        d[1] = d[1] - n * yz;
+        d[0] = d[0] - n * xz;
+
+        let n = (d[1] / ly).round();
+        d[1] = d[1] - n * ly;
+        d[0] = d[0] - n * xy;
+
+        d[0] = d[0] - (d[0] / lx).round() * lx;
+
+        d
+    }
+
+    // Wraps the positions into the cell, so that all fractional coordinates
+    // are in [0, 1)
+    pub fn wrap(&self, x: &mut [T], y: &mut [T], z: &mut [T]) {
+        for ((x, y), z) in x.iter_mut().zip(y.iter_mut()).zip(z.iter_mut()) {
+            let s = self.to_frac([*x, *y, *z]).map(|s| s - s.floor());
+            [*x, *y, *z] = self.to_cart(s);
+        }
+    }
+}
+
+impl<T: Float + SimdElement> TriclinicBox<T> {
+    #[inline(always)]
+    pub fn min_image_simd<const N: usize>(
+        &self,
+        mut d: [Simd<T, N>; 3],
+    ) -> [Simd<T, N>; 3]
+    where
+        LaneCount<N>: SupportedLaneCount,
+        Simd<T, N>: Sub<Output = Simd<T, N>>
+            + Mul<Output = Simd<T, N>>
+            + Div<Output = Simd<T, N>>
+            + StdFloat,
+    {
+        let [lx, ly, lz] = self.l.map(Simd::splat);
+        let [xy, xz, yz] = self.tilt.map(Simd::splat);
+
+        let n = (d[2] / lz).round();
+        d[2] -= n * lz;
+        d[1] -= n * yz;
+        d[0] -= n * xz;
+
+        let n = (d[1] / ly).round();
+        d[1] -= n * ly;
+        d[0] -= n * xy;
+
+        d[0] -= (d[0] / lx).round() * lx;
+
+        d
+    }
+}
+
+// Lattice points i a + j b + k c of an n^3 supercell of a cell with the given
+// basis in fractional coordinates
+pub fn setup_lattice<T: Float>(
+    n: usize,
+    cell: &TriclinicBox<T>,
+    basis: &[[T; 3]],
+) -> [Vec<T>; 3] {
+    let mut r = [Vec::new(), Vec::new(), Vec::new()];
+
+    for i in 0..n {
+        for j in 0..n {
+            for k in 0..n {
+                for b in basis {
+                    let s = [
+                        T::from(i).unwrap() + b[0],
+                        T::from(j).unwrap() + b[1],
+                        T::from(k).unwrap() + b[2],
+                    ];
+                    for (r, c) in r.iter_mut().zip(cell.to_cart(s)) {
+                        r.push(c);
+                    }
+                }
+            }
+        }
+    }
+
+    r
+}
+
+#[allow(clippy::too_many_arguments)]
+fn lennard_jones_triclinic_rest<T: Float + AddAssign + SubAssign>(
+    s2: T,
+    rc2: T,
+    e_b: T,
+    bx: &TriclinicBox<T>,
+    i: usize,
+    js: std::ops::Range<usize>,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    mut g: Option<(&mut [T], &mut [T], &mut [T])>,
+) -> T {
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let mut e = T::zero();
+    for j in js {
+        let [dx, dy, dz] =
+            bx.min_image([x[j] - x[i], y[j] - y[i], z[j] - z[i]]);
+
+        let r2 = dx * dx + dy * dy + dz * dz;
+        if r2 >= rc2 {
+            continue;
+        }
+
+        let sr2 = s2 / r2;
+        let sr6 = sr2 * sr2 * sr2;
+        let sr12 = sr6 * sr6;
+
+        e += sr12 - sr6;
+
+        if let Some((gx, gy, gz)) = &mut g {
+            let gs = -twentyfour * e_b * sr6 / r2 * (two * sr6 - one);
+
+            gx[i] -= gs * dx;
+            gy[i] -= gs * dy;
+            gz[i] -= gs * dz;
+
+            gx[j] += gs * dx;
+            gy[j] += gs * dy;
+            gz[j] += gs * dz;
+        }
+    }
+    e
+}
+
+// All pairs with the minimum image in a triclinic cell. The cutoff has to be
+// below half the smallest width of the cell.
+pub fn lennard_jones_triclinic<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    bx: &TriclinicBox<T>,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let four = T::from(4.0).unwrap();
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert!(bx.widths().iter().all(|&w| r_cut < w / two));
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let rc2 = r_cut * r_cut;
+    let rc2s = Simd::splat(rc2);
+    let inf = Simd::splat(T::infinity());
+
+    let mut e = T::zero();
+    let mut es = Simd::splat(T::zero());
+    for i in 0..x.len() {
+        let xi = Simd::splat(x[i]);
+        let yi = Simd::splat(y[i]);
+        let zi = Simd::splat(z[i]);
+
+        let (xcs, xr): (&[[_; N]], _) = x[..i].as_chunks();
+        let (ycs, _): (&[[_; N]], _) = y[..i].as_chunks();
+        let (zcs, _): (&[[_; N]], _) = z[..i].as_chunks();
+
+        for ((xc, yc), zc) in xcs.iter().zip(ycs).zip(zcs) {
+            let [dx, dy, dz] = bx.min_image_simd([
+                Simd::from(*xc) - xi,
+                Simd::from(*yc) - yi,
+                Simd::from(*zc) - zi,
+            ]);
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let r2 = r2.simd_lt(rc2s).select(r2, inf);
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+        }
+
+        e += lennard_jones_triclinic_rest(
+            s2,
+            rc2,
+            e_b,
+            bx,
+            i,
+            i - xr.len()..i,
+            x,
+            y,
+            z,
+            None,
+        );
+    }
+
+    e += es.reduce_sum();
+
+    e * four * e_b
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn lennard_jones_grad_triclinic<
+    const N: usize,
+    T: Float + SimdElement + Sum + AddAssign + SubAssign,
+>(
+    r_eq: T,
+    e_b: T,
+    r_cut: T,
+    bx: &TriclinicBox<T>,
+    x: &[T],
+    y: &[T],
+    z: &[T],
+    gx: &mut [T],
+    gy: &mut [T],
+    gz: &mut [T],
+) -> T
+where
+    LaneCount<N>: SupportedLaneCount,
+    Simd<T, N>: Add<Output = Simd<T, N>>
+        + Sub<Output = Simd<T, N>>
+        + Mul<Output = Simd<T, N>>
+        + Div<Output = Simd<T, N>>
+        + Neg<Output = Simd<T, N>>
+        + SimdFloat<Scalar = T>
+        + StdFloat
+        + SimdPartialOrd<Mask = Mask<<T as SimdElement>::Mask, N>>,
+{
+    let one = T::one();
+    let two = T::from(2.0).unwrap();
+    let three = T::from(3.0).unwrap();
+    let four = T::from(4.0).unwrap();
+    let twentyfour = T::from(24.0).unwrap();
+
+    let one_s = Simd::splat(one);
+    let two_s = Simd::splat(two);
+    let twentyfour_s = Simd::splat(twentyfour);
+
+    let e_b_s = Simd::splat(e_b);
+
+    assert_eq!(x.len(), y.len());
+    assert_eq!(x.len(), z.len());
+    assert!(bx.widths().iter().all(|&w| r_cut < w / two));
+
+    assert_eq!(x.len(), gx.len());
+    assert_eq!(x.len(), gy.len());
+    assert_eq!(x.len(), gz.len());
+
+    gx.fill(T::zero());
+    gy.fill(T::zero());
+    gz.fill(T::zero());
+
+    let s2 = two.powf(-one / three) * r_eq.powi(2);
+    let s2s = Simd::splat(s2);
+
+    let rc2 = r_cut * r_cut;
+    let rc2s = Simd::splat(rc2);
+    let inf = Simd::splat(T::infinity());
+
+    let mut e = T::zero();
+    let mut es = Simd::splat(T::zero());
+    for i in 0..x.len() {
+        let xi = Simd::splat(x[i]);
+        let yi = Simd::splat(y[i]);
+        let zi = Simd::splat(z[i]);
+
+        let mut gxi = Simd::splat(T::zero());
+        let mut gyi = Simd::splat(T::zero());
+        let mut gzi = Simd::splat(T::zero());
+
+        let (xcs, xr): (&[[_; N]], _) = x[..i].as_chunks();
+        let (ycs, _): (&[[_; N]], _) = y[..i].as_chunks();
+        let (zcs, _): (&[[_; N]], _) = z[..i].as_chunks();
+
+        let (gxcs, _): (&mut [[_; N]], _) = gx[..i].as_chunks_mut();
+        let (gycs, _): (&mut [[_; N]], _) = gy[..i].as_chunks_mut();
+        let (gzcs, _): (&mut [[_; N]], _) = gz[..i].as_chunks_mut();
+
+        for (((((xc, yc), zc), gxc), gyc), gzc) in xcs
+            .iter()
+            .zip(ycs)
+            .zip(zcs)
+            .zip(gxcs.iter_mut())
+            .zip(gycs.iter_mut())
+            .zip(gzcs.iter_mut())
+        {
+            let [dx, dy, dz] = bx.min_image_simd([
+                Simd::from(*xc) - xi,
+                Simd::from(*yc) - yi,
+                Simd::from(*zc) - zi,
+            ]);
+
+            let r2 = dx * dx + dy * dy + dz * dz;
+            let r2 = r2.simd_lt(rc2s).select(r2, inf);
+            let sr2 = s2s / r2;
+            let sr6 = sr2 * sr2 * sr2;
+            let sr12 = sr6 * sr6;
+
+            es += sr12 - sr6;
+
+            let gs = -twentyfour_s * e_b_s * sr6 / r2 * (two_s * sr6 - one_s);
+
+            let gxs = gs * dx;
+            let gys = gs * dy;
+            let gzs = gs * dz;
+
+            gxi -= gxs;
+            gyi -= gys;
+            gzi -= gzs;
+
+            *gxc = *(Simd::from(*gxc) + gxs).as_array();
+            *gyc = *(Simd::from(*gyc) + gys).as_array();
+            *gzc = *(Simd::from(*gzc) + gzs).as_array();
+        }
+
+        gx[i] += gxi.reduce_sum();
+        gy[i] += gyi.reduce_sum();
+        gz[i] += gzi.reduce_sum();
+
+        e += lennard_jones_triclinic_rest(
+            s2,
+            rc2,
+            e_b,
+            bx,
+            i,
+            i - xr.len()..i,
+            x,
+            y,
+            z,
+            Some((&mut *gx, &mut *gy, &mut *gz)),
+        );
+    }
+
+    e += es.reduce_sum();
+
+    e * four * e_b
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha8Rng;
+
+    use super::*;
+    use crate::cell_list::{
+        lennard_jones_cells, lennard_jones_grad_cells, CellList,
+    };
+
+    const A0: f64 = 1.5;
+    const R_CUT: f64 = 2.5;
+    const N: usize = 5;
+
+    // Energy per atom from all pairs and from the cell list
+    fn energies(bx: &TriclinicBox<f64>, r: &[Vec<f64>; 3]) -> [f64; 2] {
+        let [x, y, z] = r;
+        let mut cells = CellList::new_triclinic(bx, R_CUT);
+        cells.build(x, y, z);
+
+        let e = lennard_jones_triclinic::<8, _>(1.0, 1.0, R_CUT, bx, x, y, z);
+        let e_cells = lennard_jones_cells::<8, _>(1.0, 1.0, x, y, z, &cells);
+
+        [e, e_cells].map(|e| e / x.len() as f64)
+    }
+
+    fn fcc() -> [Vec<f64>; 3] {
+        let basis = [
+            [0.0, 0.0, 0.0],
+            [0.5, 0.5, 0.0],
+            [0.5, 0.0, 0.5],
+            [0.0, 0.5, 0.5],
+        ];
+        setup_lattice(N, &TriclinicBox::orthogonal([A0; 3]), &basis)
+    }
+
+    // The same crystal in a cell sheared by one lattice constant along every
+    // tilt, which is still a lattice vector of the crystal
+    fn sheared() -> (TriclinicBox<f64>, [Vec<f64>; 3]) {
+        let bx = TriclinicBox::new([A0 * N as f64; 3], [A0; 3]);
+        let mut r = fcc();
+        let [x, y, z] = &mut r;
+        bx.wrap(x, y, z);
+        (bx, r)
+    }
+
+    // The fcc crystal gives the same energy per atom in the orthogonal cell,
+    // the sheared cell and a supercell of the rhombohedral primitive cell
+    #[test]
+    fn equivalent_cells() {
+        let orth = TriclinicBox::orthogonal([A0 * N as f64; 3]);
+        let e_orth = energies(&orth, &fcc());
+
+        let (bx, r) = sheared();
+        let e_sheared = energies(&bx, &r);
+
+        let h = A0 / 2.0;
+        let prim =
+            TriclinicBox::from_vectors([0.0, h, h], [h, 0.0, h], [h, h, 0.0]);
+        let m = (3.0 * R_CUT / prim.widths()[0]).ceil() as usize;
+        let prim_super = TriclinicBox::new(
+            prim.l.map(|l| l * m as f64),
+            prim.tilt.map(|t| t * m as f64),
+        );
+        let e_prim =
+            energies(&prim_super, &setup_lattice(m, &prim, &[[0.0; 3]]));
+
+        let e_ref = e_orth[0];
+        for e in [e_orth, e_sheared, e_prim].into_iter().flatten() {
+            assert!((e - e_ref).abs() < 1e-12 * e_ref.abs(), "{e} vs {e_ref}");
+        }
+    }
+
+    // Gradients of a disordered configuration in the sheared cell
+    #[test]
+    fn gradient_matches_cells() {
+        let mut rng = ChaCha8Rng::seed_from_u64(4);
+        let (bx, [mut x, mut y, mut z]) = sheared();
+        for v in [&mut x, &mut y, &mut z] {
+            for r in v.iter_mut() {
+                *r += rng.gen_range(-0.1..0.1);
+            }
+        }
+        bx.wrap(&mut x, &mut y, &mut z);
+
+        let n_atoms = x.len();
+        let mut g = [(); 3].map(|_| vec![0.0; n_atoms]);
+        let mut g_cells = g.clone();
+
+        let [gx, gy, gz] = &mut g;
+        let e = lennard_jones_grad_triclinic::<8, _>(
+            1.0, 1.0, R_CUT, &bx, &x, &y, &z, gx, gy, gz,
+        );
+
+        let mut cells = CellList::new_triclinic(&bx, R_CUT);
+        cells.build(&x, &y, &z);
+        let [gx, gy, gz] = &mut g_cells;
+        let e_cells = lennard_jones_grad_cells::<8, _>(
+            1.0, 1.0, &x, &y, &z, gx, gy, gz, &cells,
+        );
+
+        assert!((e - e_cells).abs() < 1e-10 * e.abs());
+        for (g, g_cells) in g.iter().flatten().zip(g_cells.iter().flatten()) {
+            assert!((g - g_cells).abs() < 1e-10);
+        }
+    }
+}